@@ -0,0 +1,15 @@
+-- Personal access tokens for scripts and external agents.
+-- Only a SHA-256 hash of the token is stored, the token itself is shown once on creation.
+CREATE TABLE api_tokens (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,           -- JSON array of scope strings
+    expires_at TIMESTAMP,           -- NULL means the token never expires
+    last_used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX idx_api_tokens_user_id ON api_tokens (user_id);
@@ -0,0 +1,9 @@
+-- Kill switches that stop all agent activity.
+-- A row with the nil UUID (all zeroes) is the global switch, every other row belongs to a user.
+CREATE TABLE kill_switches (
+    user_id BLOB NOT NULL PRIMARY KEY,
+    engaged BOOLEAN NOT NULL DEFAULT 0,
+    updated_by BLOB,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (updated_by) REFERENCES users(id)
+);
@@ -0,0 +1,17 @@
+-- User defined rules that categorize incoming messages without asking the LLM.
+-- Rules are evaluated in ascending priority order and the first match wins.
+CREATE TABLE categorization_rules (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    name TEXT NOT NULL,
+    condition TEXT NOT NULL,        -- JSON encoded `RuleCondition`
+    category INTEGER NOT NULL,
+    reasoning TEXT NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX idx_categorization_rules_user_id ON categorization_rules (user_id, priority);
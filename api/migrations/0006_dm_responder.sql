@@ -0,0 +1,14 @@
+-- Messages written by a user's DM responder agent rather than the user themselves
+ALTER TABLE messages ADD COLUMN agent_authored BOOLEAN NOT NULL DEFAULT 0;
+
+-- What the DM responder does with messages of each category.
+-- Categories without a row are ignored.
+CREATE TABLE responder_policies (
+    user_id BLOB NOT NULL,
+    category INTEGER NOT NULL,
+    action TEXT NOT NULL,           -- 'autoReply', 'draft' or 'ignore'
+    instructions TEXT,              -- Extra guidance for the replies, e.g. tone or things to mention
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, category),
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
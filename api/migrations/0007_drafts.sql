@@ -0,0 +1,22 @@
+-- Messages and posts written by agents or delegates that wait for the owner's approval
+CREATE TABLE drafts (
+    id BLOB NOT NULL PRIMARY KEY,
+    owner_id BLOB NOT NULL,         -- The user the draft would be sent or posted as
+    created_by BLOB NOT NULL,       -- The delegate who wrote it, or the owner for agent drafts
+    kind TEXT NOT NULL,             -- 'message' or 'post'
+    source TEXT NOT NULL,           -- 'dmResponder', 'enhancer' or 'delegate'
+    conversation_id BLOB,           -- Only set for messages
+    in_reply_to BLOB,               -- The message a responder draft answers
+    content TEXT NOT NULL,          -- JSON encoded `UserContent`, like messages and posts
+    status TEXT NOT NULL DEFAULT 'pending', -- 'pending', 'approved' or 'rejected'
+    result_id BLOB,                 -- The message or post created when the draft was approved
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    resolved_at TIMESTAMP,
+    FOREIGN KEY (owner_id) REFERENCES users(id),
+    FOREIGN KEY (created_by) REFERENCES users(id),
+    FOREIGN KEY (conversation_id) REFERENCES conversations(id),
+    FOREIGN KEY (in_reply_to) REFERENCES messages(id)
+);
+
+CREATE INDEX idx_drafts_owner_id ON drafts (owner_id, status);
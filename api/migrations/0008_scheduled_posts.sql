@@ -0,0 +1,22 @@
+-- Posts that are published by the background scheduler once `scheduled_at` has passed
+CREATE TABLE scheduled_posts (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,          -- The user the post is published as
+    created_by BLOB NOT NULL,       -- The user or delegate who scheduled it
+    content TEXT NOT NULL,          -- JSON encoded `UserContent`, like posts
+    scheduled_at TIMESTAMP NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending', -- 'pending', 'published', 'failed' or 'cancelled'
+    post_id BLOB,                   -- The post that was published
+    error TEXT,                     -- Why publishing failed
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (created_by) REFERENCES users(id),
+    FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_scheduled_posts_due ON scheduled_posts (status, DATETIME(scheduled_at));
+CREATE INDEX idx_scheduled_posts_user_id ON scheduled_posts (user_id);
+
+-- Delegates can schedule posts too, the schedule is kept while the draft waits for approval
+ALTER TABLE drafts ADD COLUMN scheduled_at TIMESTAMP;
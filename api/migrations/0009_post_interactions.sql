@@ -0,0 +1,28 @@
+-- Reshares are posts that point at the post they share
+ALTER TABLE posts ADD COLUMN reshare_of BLOB REFERENCES posts(id) ON DELETE SET NULL;
+
+CREATE TABLE post_likes (
+    post_id BLOB NOT NULL,
+    user_id BLOB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (post_id, user_id),
+    FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE post_comments (
+    id BLOB NOT NULL PRIMARY KEY,
+    post_id BLOB NOT NULL,
+    user_id BLOB NOT NULL,
+    parent_id BLOB,                 -- The comment this one replies to, NULL for top level comments
+    content TEXT NOT NULL,          -- JSON encoded `UserContent`, like posts
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (parent_id) REFERENCES post_comments(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_posts_reshare_of ON posts (reshare_of);
+CREATE INDEX idx_post_likes_user_id ON post_likes (user_id);
+CREATE INDEX idx_post_comments_post_id ON post_comments (post_id, created_at);
@@ -0,0 +1,11 @@
+CREATE TABLE follows (
+    follower_id BLOB NOT NULL,
+    followee_id BLOB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (follower_id, followee_id),
+    FOREIGN KEY (follower_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (followee_id) REFERENCES users(id) ON DELETE CASCADE,
+    CHECK (follower_id != followee_id)
+);
+
+CREATE INDEX idx_follows_followee_id ON follows (followee_id);
@@ -0,0 +1,23 @@
+-- Uploaded files. The bytes live in the upload directory under their SHA-256,
+-- so identical uploads share a single file on disk.
+CREATE TABLE media (
+    id BLOB NOT NULL PRIMARY KEY,
+    owner_id BLOB NOT NULL,
+    sha256 TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    -- Set once the media is attached, these decide who else can see it
+    post_id BLOB,
+    conversation_id BLOB,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (post_id) REFERENCES posts(id) ON DELETE SET NULL,
+    FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_media_owner_id ON media (owner_id);
+CREATE INDEX idx_media_sha256 ON media (sha256);
+
+-- IDs of the attached media, as a JSON array in display order
+ALTER TABLE posts ADD COLUMN media TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE messages ADD COLUMN media TEXT NOT NULL DEFAULT '[]';
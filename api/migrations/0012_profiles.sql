@@ -0,0 +1,6 @@
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN bio TEXT;
+-- JSON array of `ProfileLink`
+ALTER TABLE users ADD COLUMN links TEXT NOT NULL DEFAULT '[]';
+-- When the avatar was last replaced, NULL if the user has none
+ALTER TABLE users ADD COLUMN avatar_updated_at TIMESTAMP;
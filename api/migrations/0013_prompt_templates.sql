@@ -0,0 +1,17 @@
+-- Versioned agent prompts that admins can edit without a rebuild.
+-- Agents use the highest version of each template, older versions are kept
+-- so every result can be traced back to the prompt that produced it.
+CREATE TABLE prompt_templates (
+    id BLOB NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,             -- 'enhancer', 'researcher' or 'categorizer'
+    version INTEGER NOT NULL,       -- Counts up from 1 for each name
+    body TEXT NOT NULL,             -- The prompt with `{{variable}}` placeholders
+    created_by BLOB,                -- The admin who saved it, NULL for the built-in default
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (name, version),
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- The template version that generated an agent draft or a categorization
+ALTER TABLE drafts ADD COLUMN prompt_template_id BLOB REFERENCES prompt_templates(id);
+ALTER TABLE user_message_metadata ADD COLUMN prompt_template_id BLOB REFERENCES prompt_templates(id);
@@ -0,0 +1,18 @@
+-- Where a message's category came from: 'agent', 'rule' or 'manual'
+ALTER TABLE user_message_metadata ADD COLUMN source TEXT NOT NULL DEFAULT 'agent';
+
+-- Every correction a user made to the category of a message.
+-- The latest ones are shown to the categorizer as examples of what the user wants.
+CREATE TABLE category_corrections (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    previous_category INTEGER,      -- NULL if the message wasn't categorized before
+    category INTEGER NOT NULL,
+    reasoning TEXT,                 -- The user's explanation, if they gave one
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_category_corrections_user_id ON category_corrections (user_id, message_id);
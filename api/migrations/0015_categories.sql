@@ -0,0 +1,127 @@
+-- Each user's own message categories, which the categorizer chooses from.
+-- Messages, rules, responder policies and corrections refer to a category by its key,
+-- so renaming a category keeps everything filed under it.
+CREATE TABLE categories (
+    user_id BLOB NOT NULL,
+    key TEXT NOT NULL,              -- e.g. 'important' or 'fanMail', never changes
+    name TEXT NOT NULL,             -- Shown to the user, e.g. 'Fan Mail'
+    description TEXT NOT NULL,      -- Tells the categorizer which messages belong in the category
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, key),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Every existing user starts with the built-in categories, new users get them on registration.
+-- Keep in sync with `DEFAULT_CATEGORIES`.
+CREATE TEMPORARY TABLE default_categories (
+    old_value INTEGER NOT NULL,     -- The `MessageCategory` discriminant rows used to store
+    key TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+
+INSERT INTO default_categories (old_value, key, name, description) VALUES
+    (0, 'important', 'Important', 'Messages that are personal and likely require a response. This includes messages from close contacts, direct questions, or follow-ups in an ongoing, meaningful conversation.'),
+    (1, 'sponsorship', 'Sponsorship', 'Direct inquiries related to paid partnerships, brand deals, affiliate marketing, or other advertising opportunities.'),
+    (2, 'networking', 'Networking', 'Messages from peers or new contacts aimed at building professional relationships, seeking advice, or collaboration (unpaid).'),
+    (3, 'generalInquiry', 'General Inquiry', 'Neutral questions or requests for information that are not time-sensitive.'),
+    (4, 'spam', 'Spam', 'Unsolicited, irrelevant, low-quality, or malicious messages. Often from unknown senders with no prior relationship.'),
+    (5, 'urgent', 'Urgent', 'Reserved for messages that are explicitly time-critical or indicate a potential emergency. Use this category sparingly.');
+
+INSERT INTO categories (user_id, key, name, description, position)
+SELECT users.id, d.key, d.name, d.description, d.old_value
+FROM users CROSS JOIN default_categories d;
+
+-- SQLite can't change the type of a column or add foreign keys to it,
+-- so every table that stores a category is rebuilt with the key instead of the discriminant.
+-- Deleting a category uncategorizes its messages and removes the rules and policies for it.
+
+CREATE TABLE user_message_metadata_new (
+    user_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    category TEXT NOT NULL,
+    reasoning TEXT,
+    source TEXT NOT NULL DEFAULT 'agent',
+    prompt_template_id BLOB REFERENCES prompt_templates(id),
+    PRIMARY KEY (user_id, message_id),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (message_id) REFERENCES messages(id),
+    FOREIGN KEY (user_id, category) REFERENCES categories(user_id, key) ON DELETE CASCADE
+);
+
+INSERT INTO user_message_metadata_new (user_id, message_id, category, reasoning, source, prompt_template_id)
+SELECT m.user_id, m.message_id, d.key, m.reasoning, m.source, m.prompt_template_id
+FROM user_message_metadata m JOIN default_categories d ON d.old_value = m.category;
+
+DROP TABLE user_message_metadata;
+ALTER TABLE user_message_metadata_new RENAME TO user_message_metadata;
+
+CREATE TABLE categorization_rules_new (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    name TEXT NOT NULL,
+    condition TEXT NOT NULL,        -- JSON encoded `RuleCondition`
+    category TEXT NOT NULL,
+    reasoning TEXT NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (user_id, category) REFERENCES categories(user_id, key) ON DELETE CASCADE
+);
+
+INSERT INTO categorization_rules_new (id, user_id, name, condition, category, reasoning, priority, enabled, created_at, updated_at)
+SELECT r.id, r.user_id, r.name, r.condition, d.key, r.reasoning, r.priority, r.enabled, r.created_at, r.updated_at
+FROM categorization_rules r JOIN default_categories d ON d.old_value = r.category;
+
+DROP TABLE categorization_rules;
+ALTER TABLE categorization_rules_new RENAME TO categorization_rules;
+CREATE INDEX idx_categorization_rules_user_id ON categorization_rules (user_id, priority);
+
+CREATE TABLE responder_policies_new (
+    user_id BLOB NOT NULL,
+    category TEXT NOT NULL,
+    action TEXT NOT NULL,           -- 'autoReply', 'draft' or 'ignore'
+    instructions TEXT,              -- Extra guidance for the replies, e.g. tone or things to mention
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, category),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (user_id, category) REFERENCES categories(user_id, key) ON DELETE CASCADE
+);
+
+INSERT INTO responder_policies_new (user_id, category, action, instructions, updated_at)
+SELECT p.user_id, d.key, p.action, p.instructions, p.updated_at
+FROM responder_policies p JOIN default_categories d ON d.old_value = p.category;
+
+DROP TABLE responder_policies;
+ALTER TABLE responder_policies_new RENAME TO responder_policies;
+
+-- The previous category is history, so it keeps its key even after the category is deleted
+CREATE TABLE category_corrections_new (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    previous_category TEXT,         -- NULL if the message wasn't categorized before
+    category TEXT NOT NULL,
+    reasoning TEXT,                 -- The user's explanation, if they gave one
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id, category) REFERENCES categories(user_id, key) ON DELETE CASCADE
+);
+
+INSERT INTO category_corrections_new (id, user_id, message_id, previous_category, category, reasoning, created_at)
+SELECT c.id, c.user_id, c.message_id, p.key, d.key, c.reasoning, c.created_at
+FROM category_corrections c
+JOIN default_categories d ON d.old_value = c.category
+LEFT JOIN default_categories p ON p.old_value = c.previous_category
+ORDER BY c.rowid;
+
+DROP TABLE category_corrections;
+ALTER TABLE category_corrections_new RENAME TO category_corrections;
+CREATE INDEX idx_category_corrections_user_id ON category_corrections (user_id, message_id);
+
+DROP TABLE default_categories;
@@ -0,0 +1,24 @@
+-- Messages a user pinned, or that were pinned for them because they were categorized as urgent
+CREATE TABLE pinned_messages (
+    user_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    reason TEXT NOT NULL,           -- 'urgent' or 'manual'
+    pinned_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    escalated_at TIMESTAMP,         -- When the user was notified again because the message stayed unread
+    PRIMARY KEY (user_id, message_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_pinned_messages_unescalated ON pinned_messages (reason, escalated_at);
+
+-- How a user wants to be reminded of urgent messages they haven't read.
+-- Users without a row get the defaults in `EscalationPolicy::default`.
+CREATE TABLE escalation_policies (
+    user_id BLOB NOT NULL PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    after_minutes INTEGER NOT NULL, -- How long an urgent message can stay unread
+    webhook_url TEXT,               -- Also POSTed every escalation, if set
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,18 @@
+-- Summaries of what happened in a user's inbox and what agents and delegates did for them.
+-- The scheduler writes one per user for every day and every week, users can ask for more on demand.
+CREATE TABLE digests (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    period TEXT NOT NULL,           -- 'daily' or 'weekly'
+    period_start TIMESTAMP NOT NULL,
+    period_end TIMESTAMP NOT NULL,
+    scheduled BOOLEAN NOT NULL,     -- Written by the scheduler rather than on demand
+    stats TEXT NOT NULL,            -- JSON encoded `DigestStats`
+    summary TEXT,                   -- Narrative written by the summarizer, NULL if it couldn't run
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- The scheduler only ever writes one digest per user and period
+CREATE UNIQUE INDEX idx_digests_scheduled ON digests (user_id, period, period_end) WHERE scheduled;
+CREATE INDEX idx_digests_user_id ON digests (user_id, created_at);
@@ -0,0 +1,28 @@
+-- Everything delegates and agents did on a user's behalf, and every change to who may act for them.
+-- Entries are never changed and only go away together with the account of the user they belong to.
+CREATE TABLE audit_log (
+    id BLOB NOT NULL PRIMARY KEY,
+    actor_id BLOB NOT NULL,         -- The delegate, or the user themselves for their own agents and delegations
+    agent TEXT,                     -- The agent that acted, NULL if a person did
+    principal_id BLOB NOT NULL,     -- The user it was done for
+    action TEXT NOT NULL,           -- `AuditAction`
+    target_type TEXT NOT NULL,      -- 'message', 'post', 'draft' or 'user'
+    target_id BLOB NOT NULL,
+    details TEXT,                   -- JSON with whatever else is worth knowing about the action
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (principal_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_audit_log_principal_id ON audit_log (principal_id, created_at);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
+
+-- Deleting the principal cascades here after their row is gone, anything else is refused
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+WHEN EXISTS (SELECT 1 FROM users WHERE id = OLD.principal_id)
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
@@ -0,0 +1,10 @@
+-- Finer grained delegations that can be limited in time and to some conversations
+ALTER TABLE delegations ADD COLUMN can_read_messages BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE delegations ADD COLUMN can_comment BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE delegations ADD COLUMN conversation_ids TEXT;  -- JSON array of the conversations reading and messaging are limited to, NULL for all
+ALTER TABLE delegations ADD COLUMN expires_at TIMESTAMP;   -- NULL if the delegation never expires
+ALTER TABLE delegations ADD COLUMN active_hours TEXT;      -- JSON encoded `ActiveHours`, NULL if the delegate may act any time
+ALTER TABLE delegations ADD COLUMN updated_at TIMESTAMP;   -- NULL until the delegation is changed
+
+-- Delegates that could send messages could always read them
+UPDATE delegations SET can_read_messages = can_message;
@@ -0,0 +1,6 @@
+-- The session ID is the secret in the `session` cookie, so sessions are listed and revoked
+-- by a separate ID that is safe to show
+ALTER TABLE sessions ADD COLUMN public_id BLOB;
+UPDATE sessions SET public_id = randomblob(16);
+
+CREATE UNIQUE INDEX idx_sessions_public_id ON sessions (public_id);
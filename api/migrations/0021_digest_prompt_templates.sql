@@ -0,0 +1,3 @@
+-- The summarizer prompt is an editable template too ('summarizer'),
+-- digests record the version their summary was written with
+ALTER TABLE digests ADD COLUMN prompt_template_id BLOB REFERENCES prompt_templates(id);
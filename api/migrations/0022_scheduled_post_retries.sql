@@ -0,0 +1,8 @@
+-- Publishing a scheduled post is retried with a growing delay before the post is marked failed,
+-- and scheduled posts can carry media that is attached once the post is published
+ALTER TABLE scheduled_posts ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;  -- Failed attempts to publish so far
+ALTER TABLE scheduled_posts ADD COLUMN retry_at TIMESTAMP;                   -- When to try again after a failed attempt
+ALTER TABLE scheduled_posts ADD COLUMN media TEXT NOT NULL DEFAULT '[]';     -- JSON array of the media IDs to attach
+
+DROP INDEX idx_scheduled_posts_due;
+CREATE INDEX idx_scheduled_posts_due ON scheduled_posts (status, DATETIME(COALESCE(retry_at, scheduled_at)));
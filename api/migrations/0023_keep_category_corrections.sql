@@ -0,0 +1,22 @@
+-- Corrections are history, so they outlive the category they moved a message to.
+-- The categorizer only gets shown the ones whose category still exists.
+CREATE TABLE category_corrections_new (
+    id BLOB NOT NULL PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    previous_category TEXT,         -- NULL if the message wasn't categorized before
+    category TEXT NOT NULL,         -- May refer to a deleted category
+    reasoning TEXT,                 -- The user's explanation, if they gave one
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+INSERT INTO category_corrections_new (id, user_id, message_id, previous_category, category, reasoning, created_at)
+SELECT id, user_id, message_id, previous_category, category, reasoning, created_at
+FROM category_corrections
+ORDER BY rowid;
+
+DROP TABLE category_corrections;
+ALTER TABLE category_corrections_new RENAME TO category_corrections;
+CREATE INDEX idx_category_corrections_user_id ON category_corrections (user_id, message_id);
@@ -0,0 +1,36 @@
+-- Audit entries outlive the account they belong to, so what was done on a user's behalf
+-- can still be traced after they deleted it. SQLite can't drop a foreign key,
+-- so the table is rebuilt without the one on `principal_id`.
+DROP TRIGGER audit_log_no_update;
+DROP TRIGGER audit_log_no_delete;
+
+CREATE TABLE audit_log_new (
+    id BLOB NOT NULL PRIMARY KEY,
+    actor_id BLOB NOT NULL,         -- The delegate, or the user themselves for their own agents and delegations
+    agent TEXT,                     -- The agent that acted, NULL if a person did
+    principal_id BLOB NOT NULL,     -- The user it was done for, who may have been deleted since
+    action TEXT NOT NULL,           -- `AuditAction`
+    target_type TEXT NOT NULL,      -- 'message', 'post', 'draft' or 'user'
+    target_id BLOB NOT NULL,
+    details TEXT,                   -- JSON with whatever else is worth knowing about the action
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO audit_log_new (id, actor_id, agent, principal_id, action, target_type, target_id, details, created_at)
+SELECT id, actor_id, agent, principal_id, action, target_type, target_id, details, created_at
+FROM audit_log
+ORDER BY rowid;
+
+DROP TABLE audit_log;
+ALTER TABLE audit_log_new RENAME TO audit_log;
+CREATE INDEX idx_audit_log_principal_id ON audit_log (principal_id, created_at);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'The audit log is append-only');
+END;
@@ -1,26 +1,45 @@
 use crate::{
-    entities::{ChatMessage, MessageCategory},
-    error::{ErrorResponse, Result},
+    UPLOAD_DIR,
+    auth::SessionAuth,
+    categories::describe_categories,
+    drafts::{NewDraft, submit_draft},
+    entities::{
+        ApiScope, ChatMessage, Draft, DraftKind, DraftSource, Media, MessageCategory,
+        PromptTemplateName, User, get_media, get_recent_corrected_messages, get_user_categories,
+        is_kill_switch_engaged, message_text,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    llm::{AgentRole, LlmRequest, TextStream},
+    media::{
+        StoredUpload, can_view_media, media_not_found, receive_file, store_upload, upload_path,
+    },
+    prompts::render_prompt,
+    rules::apply_categorization_rules,
+    state::{AgentTasks, AppState},
 };
 use axum::{
     Json,
+    extract::{Multipart, Query, State},
     http::StatusCode,
-    response::{IntoResponse, Response},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use futures_util::StreamExt as _;
 use rig::{
-    client::{CompletionClient, ProviderClient},
-    completion::Prompt,
-    providers::gemini::{
-        self,
-        completion::{
-            GEMINI_1_5_FLASH, GEMINI_2_0_FLASH,
-            gemini_api_types::{AdditionalParameters, GenerationConfig, ThinkingConfig},
-        },
-    },
+    OneOrMany,
+    message::{ImageMediaType, MimeType, UserContent},
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::{convert::Infallible, time::Duration};
+use tokio::{fs, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 #[derive(Deserialize, ToSchema)]
 pub struct InputPrompt {
@@ -28,235 +47,693 @@ pub struct InputPrompt {
 }
 
 #[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct PromptResponse {
     pub output: String,
+    /// The post draft holding the output, if one was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draft: Option<Draft>,
+    /// The prompt template version the output was generated with
+    pub prompt_template_id: Uuid,
 }
 
-pub fn generation_config() -> serde_json::Value {
-    let gen_cfg = GenerationConfig {
-        thinking_config: None,
-        ..Default::default()
-    };
-    let cfg = AdditionalParameters::default().with_config(gen_cfg);
-    serde_json::to_value(cfg).unwrap()
+#[derive(Deserialize, ToSchema)]
+pub struct EnhancePromptQuery {
+    /// Also queue the enhanced text as a post draft for approval
+    #[serde(default)]
+    pub draft: bool,
+}
+
+/// Refuse to run an agent for a user while their or the global kill switch is engaged.
+/// Every agent entry point must call this before doing any work.
+pub async fn ensure_agents_enabled(pool: &SqlitePool, user_id: Uuid) -> Result<()> {
+    if is_kill_switch_engaged(pool, user_id).await? {
+        return Err(AppError::KillSwitchEngaged(
+            "The agent kill switch is engaged".into(),
+        ));
+    }
+    Ok(())
 }
 
-pub const MODEL_NAME: &str = GEMINI_2_0_FLASH;
+async fn enhance_request(pool: &SqlitePool, prompt: &str) -> Result<(LlmRequest, Uuid)> {
+    let rendered = render_prompt(pool, PromptTemplateName::Enhancer, &[("text", prompt)]).await?;
+    let request =
+        LlmRequest::new("Impactful Text Enhancer Agent", rendered.text).with_input(prompt);
+    Ok((request, rendered.template_id))
+}
+
+async fn research_request(pool: &SqlitePool, prompt: &str) -> Result<(LlmRequest, Uuid)> {
+    let rendered =
+        render_prompt(pool, PromptTemplateName::Researcher, &[("query", prompt)]).await?;
+    let request = LlmRequest::new("Researcher Agent", rendered.text).with_input(prompt);
+    Ok((request, rendered.template_id))
+}
+
+/// Queue enhanced text as a post draft for the user to approve.
+async fn draft_enhanced_post(
+    state: &AppState,
+    user_id: Uuid,
+    output: &str,
+    prompt_template_id: Uuid,
+) -> Result<Draft> {
+    let draft = NewDraft {
+        owner_id: user_id,
+        created_by: user_id,
+        kind: DraftKind::Post,
+        source: DraftSource::Enhancer,
+        conversation_id: None,
+        in_reply_to: None,
+        content: OneOrMany::one(UserContent::text(output)),
+        scheduled_at: None,
+        prompt_template_id: Some(prompt_template_id),
+    };
+    submit_draft(state, draft).await
+}
 
 #[utoipa::path(
     post,
     path = "/api/agents/enhance_prompt",
     description = "Enhance a prompt",
     request_body(content = InputPrompt, description = "Prompt to enhance"),
+    params(
+        ("draft" = Option<bool>, Query, description = "Also queue the enhanced text as a post draft for approval")
+    ),
     responses(
         (status = OK, description = "Prompt response", body = PromptResponse),
-        (status = BAD_REQUEST, description = "Invalid prompt", body = ErrorResponse)
+        (status = BAD_REQUEST, description = "Invalid prompt", body = ErrorResponse),
+        (status = LOCKED, description = "The agent kill switch is engaged", body = ErrorResponse)
     )
 )]
-pub async fn enhance_prompt(Json(full_prompt): Json<InputPrompt>) -> Result<Response> {
-    let client = gemini::Client::from_env();
-    let agent = client
-        .agent(MODEL_NAME)
-        .name("Impactful Text Enhancer Agent")
-        .additional_params(generation_config())
-        .build();
-
-    let result = agent.prompt(format!(r#"### 
-    ### The Impactful Text Enhancer Prompt
-
-        You are an expert Copywriter and Digital Communication Strategist. Your job is to take a user's simple, direct, or rough piece of text and rewrite it to be more impactful, engaging, and nuanced.
-
-        **Your Enhancement Process:**
-
-        1.  **Identify the Core Intent**: Analyze the user's text to understand the core message and the underlying emotion or goal.
-        2.  **Consider Angles**: Mentally consider different possible angles or tones for the rewrite (e.g., professional, casual, enthusiastic, inquisitive) to determine which would be most effective for the likely context (social media, direct message, etc.).
-        3.  **Produce the Single Best Version**: Based on your analysis, produce a **single, polished, and impactful version** of the text. Your goal is to be decisive and provide the one result you determine to be the most effective.
-
-        **Crucial Output Rules:**
-
-        *   **Your response must contain *only* the final, enhanced text.** Note that emojis and hashtags are allowed, but must be used sparingly and only when they add value to the message.
-        *   Do NOT, under any circumstances, include conversational preambles like "Okay, I'm ready," "Here is the enhanced text," or any other explanatory sentences.
-        *   Do NOT provide a list of multiple options, titles, or variations.
-        *   The output should be only the raw, rewritten text, ready to be used directly.
-
-        **Example Transformation:**
-
-        *   **User's Raw Text:** `"I finished the report."`
-        *   **Your Required Output:**
-            ```
-            The report is complete and has been sent over for your review. I'm looking forward to hearing your feedback.
-            ```
+pub async fn enhance_prompt(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Query(query): Query<EnhancePromptQuery>,
+    Json(full_prompt): Json<InputPrompt>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    ensure_agents_enabled(&state.pool, session.0.id).await?;
+
+    let (request, prompt_template_id) = enhance_request(&state.pool, &full_prompt.prompt).await?;
+    let backend = state.llm.backend(AgentRole::Enhancer);
+    let result = backend.complete(request).await?;
+
+    let draft = if query.draft {
+        Some(draft_enhanced_post(&state, session.0.id, &result, prompt_template_id).await?)
+    } else {
+        None
+    };
+    Ok((
+        StatusCode::OK,
+        Json(PromptResponse {
+            output: result,
+            draft,
+            prompt_template_id,
+        }),
+    )
+        .into_response())
+}
 
-        ---
+#[utoipa::path(
+    post,
+    path = "/api/agents/research_prompt",
+    description = "Research a prompt",
+    request_body(content = InputPrompt, description = "Prompt to research"),
+    responses(
+        (status = OK, description = "Prompt response", body = PromptResponse),
+        (status = BAD_REQUEST, description = "Invalid prompt", body = ErrorResponse),
+        (status = LOCKED, description = "The agent kill switch is engaged", body = ErrorResponse)
+    )
+)]
+pub async fn research_prompt(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(full_prompt): Json<InputPrompt>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    ensure_agents_enabled(&state.pool, session.0.id).await?;
+
+    let (request, prompt_template_id) = research_request(&state.pool, &full_prompt.prompt).await?;
+    let backend = state.llm.backend(AgentRole::Researcher);
+    let result = backend.complete(request).await?;
+    Ok((
+        StatusCode::OK,
+        Json(PromptResponse {
+            output: result,
+            draft: None,
+            prompt_template_id,
+        }),
+    )
+        .into_response())
+}
 
-        **Your Task:**
+/// The events streamed by the `/stream` variants of the agent endpoints.
+///
+/// Like `/api/events`, every event is JSON with a `type` field and a `data` field.
+/// A stream always ends with either a `done` or an `error` event.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum AgentStreamEvent {
+    /// The next piece of the output
+    Chunk {
+        /// Text to append to the output so far
+        text: String,
+    },
+    /// The output is complete
+    Done(PromptResponse),
+    /// The agent failed, the output so far should be discarded
+    Error {
+        /// The same error a failed request responds with
+        #[schema(value_type = ErrorResponse)]
+        error: AppError,
+    },
+}
 
-        Analyze the user's text below. Following the process and the crucial output rules above, provide only the single, best-enhanced version of the text.
+/// Stream a model's reply to the client as [`AgentStreamEvent`]s.
+/// `finish` is called with the full output once the model is done to build the `done` event.
+///
+/// The response is returned right away so long replies aren't cut off by the request timeout.
+/// If the client disconnects, the model's stream is dropped, which cancels the request.
+/// Engaging a kill switch stops the stream too, before `finish` gets to run.
+fn stream_agent_reply<F, Fut>(
+    agent_tasks: &AgentTasks,
+    user_id: Uuid,
+    mut chunks: TextStream,
+    finish: F,
+) -> Response
+where
+    F: FnOnce(String) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<PromptResponse>> + Send,
+{
+    let (tx, rx) = mpsc::channel(32);
+    let cancelled_tx = tx.clone();
+    let on_cancel = async move {
+        let error = AppError::KillSwitchEngaged("The agent kill switch is engaged".into());
+        let _ = cancelled_tx.send(AgentStreamEvent::Error { error }).await;
+    };
+    let task = async move {
+        let forward = async {
+            let mut output = String::new();
+            while let Some(text) = chunks.next().await {
+                let text = text?;
+                output.push_str(&text);
+                // A failed send means the client is gone, which `closed` below picks up
+                let _ = tx.send(AgentStreamEvent::Chunk { text }).await;
+            }
+            finish(output).await
+        };
+        let event = tokio::select! {
+            _ = tx.closed() => return,
+            result = forward => match result {
+                Ok(response) => AgentStreamEvent::Done(response),
+                Err(error) => AgentStreamEvent::Error { error },
+            },
+        };
+        let _ = tx.send(event).await;
+    };
+    agent_tasks.spawn_or_cancel(user_id, task, on_cancel);
 
-        **User Text:** `{}`
+    let events = ReceiverStream::new(rx)
+        .map(|event| Ok::<_, Infallible>(Event::default().json_data(event).unwrap_or_default()));
+    Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+        .into_response()
+}
 
-        **Your Output:**
-        "#, full_prompt.prompt)).await?;
-    Ok((StatusCode::OK, Json(PromptResponse { output: result })).into_response())
+#[utoipa::path(
+    post,
+    path = "/api/agents/enhance_prompt/stream",
+    description = "Enhance a prompt, streaming the output as it's generated",
+    request_body(content = InputPrompt, description = "Prompt to enhance"),
+    params(
+        ("draft" = Option<bool>, Query, description = "Also queue the enhanced text as a post draft for approval once it's complete")
+    ),
+    responses(
+        (status = OK, description = "Stream of output chunks, ending with the full response or an error",
+         content_type = "text/event-stream", body = AgentStreamEvent),
+        (status = BAD_REQUEST, description = "Invalid prompt", body = ErrorResponse),
+        (status = LOCKED, description = "The agent kill switch is engaged", body = ErrorResponse)
+    )
+)]
+pub async fn enhance_prompt_stream(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Query(query): Query<EnhancePromptQuery>,
+    Json(full_prompt): Json<InputPrompt>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    ensure_agents_enabled(&state.pool, session.0.id).await?;
+
+    let (request, prompt_template_id) = enhance_request(&state.pool, &full_prompt.prompt).await?;
+    let chunks = state.llm.backend(AgentRole::Enhancer).stream(request);
+    let user_id = session.0.id;
+    let agent_tasks = state.agent_tasks.clone();
+    Ok(stream_agent_reply(
+        &agent_tasks,
+        user_id,
+        chunks,
+        move |output| async move {
+            let draft = if query.draft {
+                Some(draft_enhanced_post(&state, user_id, &output, prompt_template_id).await?)
+            } else {
+                None
+            };
+            Ok(PromptResponse {
+                output,
+                draft,
+                prompt_template_id,
+            })
+        },
+    ))
 }
 
 #[utoipa::path(
     post,
-    path = "/api/agents/research_prompt",
-    description = "Research a prompt",
+    path = "/api/agents/research_prompt/stream",
+    description = "Research a prompt, streaming the output as it's generated",
     request_body(content = InputPrompt, description = "Prompt to research"),
     responses(
-        (status = OK, description = "Prompt response", body = PromptResponse),
-        (status = BAD_REQUEST, description = "Invalid prompt", body = ErrorResponse)
+        (status = OK, description = "Stream of output chunks, ending with the full response or an error",
+         content_type = "text/event-stream", body = AgentStreamEvent),
+        (status = BAD_REQUEST, description = "Invalid prompt", body = ErrorResponse),
+        (status = LOCKED, description = "The agent kill switch is engaged", body = ErrorResponse)
     )
 )]
-pub async fn research_prompt(Json(full_prompt): Json<InputPrompt>) -> Result<Response> {
-    let client = gemini::Client::from_env();
-    let agent = client
-        .agent(MODEL_NAME)
-        .name("Researcher Agent")
-        .additional_params(generation_config())
-        .build();
+pub async fn research_prompt_stream(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(full_prompt): Json<InputPrompt>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    ensure_agents_enabled(&state.pool, session.0.id).await?;
+
+    let (request, prompt_template_id) = research_request(&state.pool, &full_prompt.prompt).await?;
+    let chunks = state.llm.backend(AgentRole::Researcher).stream(request);
+    Ok(stream_agent_reply(
+        &state.agent_tasks,
+        session.0.id,
+        chunks,
+        move |output| async move {
+            Ok(PromptResponse {
+                output,
+                draft: None,
+                prompt_template_id,
+            })
+        },
+    ))
+}
 
-    let result = agent.prompt(format!(r#"
-    ### The Social Media Content Researcher Prompt
-        You are a savvy Social Media Content Researcher. Your job is to take a topic from a user and find the most interesting, shareable, and accurate information about it. You will then package this research into a "Social Media Content Kit" that a content creator can easily use to write posts for platforms like Twitter, LinkedIn, or Instagram.
+/// At most this many images are sent to the captioner at once.
+const MAX_CAPTION_IMAGES: usize = 4;
+/// Images are sent to the model inline, which providers only allow for a few megabytes.
+const MAX_CAPTION_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+const MAX_CAPTION_DESCRIPTION_LEN: usize = 500;
+const CAPTION_CANDIDATES: usize = 3;
+
+#[derive(ToSchema)]
+#[schema(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct CaptionAssistForm {
+    /// A short description of the post, at most 500 characters
+    pub description: String,
+    /// IDs of already uploaded images to caption, repeat the field for more than one
+    pub media_ids: Vec<Uuid>,
+    /// Images to caption, repeat the field for more than one.
+    /// They are stored as media so they can be attached to the post afterwards.
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub files: Vec<Vec<u8>>,
+}
 
-        **Your Research Process:**
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+pub struct CaptionCandidate {
+    /// The caption text, without any hashtags.
+    pub caption: String,
+    /// A few relevant hashtags, each starting with `#`.
+    pub hashtags: Vec<String>,
+}
 
-        1.  **Identify Engaging Angles**: Analyze the user's topic to find interesting hooks, surprising facts, common misconceptions, or provocative questions that would grab attention on a social media feed.
-        2.  **Find Credible & Shareable Sources**: Perform web searches to find recent and reputable sources for these angles. Prioritize news articles, industry reports, university studies, and expert opinions that can be easily linked to and cited.
-        3.  **Extract "Nuggets"**: Your main goal is to extract distinct, bite-sized pieces of information. Pull out short statistics, impactful quotes, and key takeaways. Do not write long paragraphs.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CaptionSuggestions {
+    /// The caption candidates, each taking a different angle or tone.
+    pub captions: Vec<CaptionCandidate>,
+}
 
-        **Your Output Format: The Social Media Content Kit**
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptionAssistResponse {
+    pub captions: Vec<CaptionCandidate>,
+    /// The captioned images, including the ones uploaded with the request, ready to attach to a post
+    pub media_ids: Vec<Uuid>,
+    /// The prompt template version the captions were suggested with
+    pub prompt_template_id: Uuid,
+}
 
-        You must deliver your research in the following structure. Be concise and clear.
+fn unsupported_caption_image() -> AppError {
+    AppError::UserError((
+        LossyError(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        "Only PNG, JPEG, GIF and WebP images can be captioned".into(),
+    ))
+}
 
-        *   **1. Core Theme**: A single sentence that summarizes the main narrative or takeaway. This is the "big idea" for a potential post or thread.
-        *   **2. Tweetable Facts & Stats**: A bulleted list of 3-5 short, impactful facts or statistics. Each one should be easy to understand and ideally surprising. **You must include a source link for each fact.**
-            *   *Example: "Companies allowing remote work have 25% lower employee turnover than those that don't. (Source: [link])"*
-        *   **3. Compelling Quotes**: A bulleted list of 2-3 interesting quotes from experts or notable figures related to the topic. Include who said it. **You must include a source link for each quote.**
-            *   *Example: "'The future of work is not a place, it's a mindset.' - Stewart Butterfield, CEO of Slack (Source: [link])"*
-        *   **4. Engaging Questions**: A bulleted list of 2-3 open-ended questions you could ask an audience to spark conversation and engagement on the topic.
-            *   *Example: "What's the #1 thing your company could do to improve its remote work culture?"*
-        *   **5. Key Links & Sources**: A simple bulleted list of the top 3-4 articles, studies, or websites you found most useful during your research.
+/// An image to caption, either media the user can see or a file uploaded with the request.
+/// Uploads are only stored as media once the whole request checked out.
+enum CaptionImage {
+    Media(Media),
+    Upload(StoredUpload),
+}
 
-        ---
+/// Remove the files uploaded with a caption request that won't be stored.
+async fn discard_caption_uploads(images: impl IntoIterator<Item = CaptionImage>) {
+    for image in images {
+        if let CaptionImage::Upload(upload) = image {
+            let _ = fs::remove_file(&upload.path).await;
+        }
+    }
+}
+
+/// Read and check the caption form, collecting its images into `images` as they arrive.
+/// Returns the description.
+async fn read_caption_form(
+    state: &AppState,
+    session: &SessionAuth,
+    multipart: &mut Multipart,
+    images: &mut Vec<CaptionImage>,
+) -> Result<String> {
+    let bad_request =
+        |message: String| AppError::UserError((LossyError(StatusCode::BAD_REQUEST), message));
+    let too_many_images = || {
+        bad_request(format!(
+            "At most {MAX_CAPTION_IMAGES} images can be captioned at once"
+        ))
+    };
 
-        **Your Task:**
+    let mut description = None;
+    while let Some(mut field) = multipart.next_field().await? {
+        match field.name() {
+            Some("description") => {
+                description = Some(field.text().await?);
+            }
+            Some("mediaIds") => {
+                if images.len() == MAX_CAPTION_IMAGES {
+                    return Err(too_many_images());
+                }
+                let media_id = Uuid::try_parse(field.text().await?.trim())
+                    .map_err(|_| bad_request("Invalid media ID".into()))?;
+                let media = get_media(&state.pool, media_id)
+                    .await?
+                    .ok_or_else(media_not_found)?;
+                if !can_view_media(&state.pool, Some(session), &media).await? {
+                    return Err(media_not_found());
+                }
+                if media.size as u64 > MAX_CAPTION_IMAGE_BYTES {
+                    return Err(AppError::UserError((
+                        LossyError(StatusCode::PAYLOAD_TOO_LARGE),
+                        format!("Images can be at most {MAX_CAPTION_IMAGE_BYTES} bytes"),
+                    )));
+                }
+                if ImageMediaType::from_mime_type(&media.mime_type).is_none() {
+                    return Err(unsupported_caption_image());
+                }
+                images.push(CaptionImage::Media(media));
+            }
+            Some("files") => {
+                if images.len() == MAX_CAPTION_IMAGES {
+                    return Err(too_many_images());
+                }
+                let upload = receive_file(&mut field, &UPLOAD_DIR, MAX_CAPTION_IMAGE_BYTES).await?;
+                if ImageMediaType::from_mime_type(upload.mime_type).is_none() {
+                    fs::remove_file(&upload.path).await?;
+                    return Err(unsupported_caption_image());
+                }
+                images.push(CaptionImage::Upload(upload));
+            }
+            _ => {}
+        }
+    }
+
+    let description = description.unwrap_or_default().trim().to_string();
+    if description.is_empty() {
+        return Err(bad_request("The description can't be empty".into()));
+    }
+    if description.chars().count() > MAX_CAPTION_DESCRIPTION_LEN {
+        return Err(bad_request(format!(
+            "The description can be at most {MAX_CAPTION_DESCRIPTION_LEN} characters"
+        )));
+    }
+    if images.is_empty() {
+        return Err(bad_request("Add at least one image to caption".into()));
+    }
+    Ok(description)
+}
 
-        Apply this research process to the user's query below. Your final output should be only the "Social Media Content Kit" with all five sections filled out.
+#[utoipa::path(
+    post,
+    path = "/api/agents/caption_assist",
+    description = "Suggest captions with hashtags for a post from up to 4 images and a short description. \
+        Images can be uploaded with the request or referenced by the ID of media the user can see.",
+    request_body(content = CaptionAssistForm, content_type = "multipart/form-data"),
+    responses(
+        (status = OK, description = "Caption suggestions", body = CaptionAssistResponse),
+        (status = BAD_REQUEST, description = "Missing or invalid description or images", body = ErrorResponse),
+        (status = NOT_FOUND, description = "Referenced media not found", body = ErrorResponse),
+        (status = PAYLOAD_TOO_LARGE, description = "An image is too large", body = ErrorResponse),
+        (status = UNSUPPORTED_MEDIA_TYPE, description = "A file isn't a supported image", body = ErrorResponse),
+        (status = LOCKED, description = "The agent kill switch is engaged", body = ErrorResponse)
+    )
+)]
+pub async fn caption_assist(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    mut multipart: Multipart,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    ensure_agents_enabled(&state.pool, session.0.id).await?;
+
+    let mut images = Vec::new();
+    let description = match read_caption_form(&state, &session, &mut multipart, &mut images).await {
+        Ok(description) => description,
+        Err(e) => {
+            discard_caption_uploads(images).await;
+            return Err(e);
+        }
+    };
 
-        **User Query:** `{}`
-        "#, full_prompt.prompt)).await?;
-    Ok((StatusCode::OK, Json(PromptResponse { output: result })).into_response())
+    let mut pending = images.into_iter();
+    let mut images = Vec::new();
+    while let Some(image) = pending.next() {
+        let media = match image {
+            CaptionImage::Media(media) => media,
+            CaptionImage::Upload(upload) => {
+                match store_upload(&state.pool, session.0.id, upload).await {
+                    Ok(media) => media,
+                    Err(e) => {
+                        discard_caption_uploads(pending).await;
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        images.push(media);
+    }
+
+    let rendered = render_prompt(
+        &state.pool,
+        PromptTemplateName::Captioner,
+        &[
+            ("count", &CAPTION_CANDIDATES.to_string()),
+            ("description", &description),
+        ],
+    )
+    .await?;
+    let mut content = OneOrMany::one(UserContent::text(rendered.text));
+    for media in &images {
+        let bytes = fs::read(upload_path(&media.sha256)).await?;
+        content.push(UserContent::image_base64(
+            BASE64_STANDARD.encode(bytes),
+            ImageMediaType::from_mime_type(&media.mime_type),
+            None,
+        ));
+    }
+
+    let backend = state.llm.backend(AgentRole::Captioner);
+    let suggestions = backend
+        .extract::<CaptionSuggestions>(
+            LlmRequest::new("Caption Assist Agent", content).with_input(&description),
+        )
+        .await?;
+
+    // Models don't always stick to the hashtag format, so clean them up
+    let captions = suggestions
+        .captions
+        .into_iter()
+        .map(|candidate| CaptionCandidate {
+            caption: candidate.caption.trim().to_string(),
+            hashtags: candidate
+                .hashtags
+                .iter()
+                .map(|hashtag| hashtag.trim().trim_start_matches('#'))
+                .filter(|hashtag| !hashtag.is_empty() && !hashtag.contains(char::is_whitespace))
+                .map(|hashtag| format!("#{hashtag}"))
+                .collect(),
+        })
+        .filter(|candidate| !candidate.caption.is_empty())
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(CaptionAssistResponse {
+            captions,
+            media_ids: images.iter().map(|media| media.id).collect(),
+            prompt_template_id: rendered.template_id,
+        }),
+    )
+        .into_response())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MessageCategorization {
     /// A single sentence explaining *why* you chose a specific category, based on the provided context.
     pub reasoning: String,
-    /// The single category name you chose from the list above.
+    /// The key of the single category you chose from the list above.
     pub category: MessageCategory,
 }
 
+/// How many of the user's latest corrections the categorizer is shown as examples.
+const MAX_CORRECTION_EXAMPLES: i64 = 10;
+/// How many of a conversation's latest messages the categorizer sees as context.
+pub const MAX_CATEGORIZATION_HISTORY: i64 = 50;
+
+/// Categorize a message for `user_id`, one of its recipients.
+/// The user's categorization rules are tried first, the LLM only sees messages none of them match.
+/// Also returns the prompt template version the LLM was asked with, `None` when a rule matched.
+/// The user's recent corrections are sent along as examples, so the LLM learns what they want.
 pub async fn categorize_message(
+    state: &AppState,
+    user_id: Uuid,
     current_message: ChatMessage,
     history: &[ChatMessage],
-) -> Result<MessageCategorization> {
-    let client = gemini::Client::from_env();
-    let agent = client
-        .extractor::<MessageCategorization>(MODEL_NAME)
-        .additional_params(generation_config())
-        .build();
+) -> Result<(MessageCategorization, Option<Uuid>)> {
+    // The user's own rules take precedence and don't cost a model call
+    if let Some(categorization) =
+        apply_categorization_rules(&state.pool, user_id, &current_message).await?
+    {
+        return Ok((categorization, None));
+    }
+
+    ensure_agents_enabled(&state.pool, user_id).await?;
 
+    let backend = state.llm.backend(AgentRole::Categorizer);
+
+    let categories = get_user_categories(&state.pool, user_id).await?;
     let stringified_message = serde_json::to_string(&current_message)?;
     let stringified_message_history = serde_json::to_string(&history)?;
-    let result = agent.extract(format!(r#"
-    ### The Message Categorizer Agent Prompt
-
-        You are an AI-powered Message Triage Assistant. Your sole function is to analyze an incoming message and its conversational history, then classify it into one of the predefined categories below. Your classification must be accurate and based on a holistic understanding of the message content and the conversational context.
-
-        **Your Goal:** Help the user manage their inbox by correctly categorizing every incoming message based on rich, structured data.
-
-        **Step 1: Analyze the Provided Inputs**
-
-        You will receive two arguments: `currentMessage` and `messageHistory`. You must consider both in your analysis.
-
-        1.  **`currentMessage`**: A JSON object representing the new message to be categorized. It has the following structure:
-            ```json
-            {{
-              "id": "uuid",
-              "userId": "uuid",
-              "conversationId": "uuid",
-              "content": "The text content of the message.",
-              "createdAt": "timestamp"
-            }}
-            ```
-        2.  **`messageHistory`**: A JSON array of previous `ChatMessage` objects from the same conversation, ordered chronologically (oldest to newest). This array will be empty if the `currentMessage` is the first in the conversation.
-
-        **Your Analysis Process:**
-        *   First, examine the `content` of the `currentMessage` for keywords, intent, and sentiment.
-        *   Next, review the `messageHistory`. Is this a new conversation, or a reply to a previous message? The history provides the essential context for the `currentMessage`.
-        *   Pay attention to the flow of `userId`s between the `messageHistory` and the `currentMessage` to understand who said what.
-
-        **Step 2: Choose ONE Category**
-
-        You must choose exactly one of the following categories. Read their descriptions carefully.
-
-        *   **`Important`**: Messages that are personal and likely require a response. This includes messages from close contacts, direct questions, or follow-ups in an ongoing, meaningful conversation.
-        *   **`Sponsorship`**: Direct inquiries related to paid partnerships, brand deals, affiliate marketing, or other advertising opportunities.
-        *   **`Networking`**: Messages from peers or new contacts aimed at building professional relationships, seeking advice, or collaboration (unpaid).
-        *   **`General Inquiry`**: Neutral questions or requests for information that are not time-sensitive.
-        *   **`Spam`**: Unsolicited, irrelevant, low-quality, or malicious messages. Often from unknown senders with no prior relationship.
-        *   **`Urgent`**: Reserved for messages that are explicitly time-critical or indicate a potential emergency. Use this category sparingly.
-
-        **Step 3: Provide Your Output in JSON Format**
-
-        Your final output must be a single, valid JSON object. It must contain two keys:
-
-        1.  **`reasoning`**: A single sentence explaining *why* you chose a specific category, based on the provided message and its history.
-        2.  **`category`**: The single category name you chose from the list above.
-
-        **Example:**
-
-        *   **Input:**
-            *   `currentMessage`:
-                ```json
-                {{
-                  "id": "abc-123",
-                  "userId": "uuid-user",
-                  "conversationId": "uuid-conversation",
-                  "content": "Hi! We're from BrandCorp and we'd love to discuss a paid promotional campaign with you for our new product.",
-                  "createdAt": "..."
-                }}
-                ```
-            *   `messageHistory`: `[]`
-        *   **Your Required Output:**
-            ```json
-            {{
-              "reasoning": "The message is the first in the conversation and explicitly mentions a 'paid promotional campaign', which directly maps to the Sponsorship category.",
-              "category": "Sponsorship"
-            }}
-            ```
-
-        ---
-
-        **Your Task:**
-
-        Analyze the following `currentMessage` and `messageHistory`, then provide your classification in the required JSON format.
-
-        **`currentMessage`:**
-        ```json
-        {}
-        ```
-
-        **`messageHistory`:**
-        ```json
-        {}
-        ```
-
-        **Your Output:**
-        "#, stringified_message, stringified_message_history)).await?;
-    Ok(result)
+    let rendered = render_prompt(
+        &state.pool,
+        PromptTemplateName::Categorizer,
+        &[
+            ("current_message", &stringified_message),
+            ("message_history", &stringified_message_history),
+            ("categories", &describe_categories(&categories)),
+        ],
+    )
+    .await?;
+    let mut request = LlmRequest::new("Message Categorizer Agent", rendered.text)
+        .with_input(current_message.text());
+    request.preamble = correction_examples(&state.pool, user_id).await?;
+    let result: MessageCategorization = backend.extract(request).await?;
+    if !categories
+        .iter()
+        .any(|category| category.key == result.category)
+    {
+        return Err(AppError::LlmBackendError(format!(
+            "The categorizer chose `{}`, which isn't one of the user's categories",
+            result.category
+        )));
+    }
+    Ok((result, Some(rendered.template_id)))
+}
+
+/// The messages `user_id` recently corrected the category of, as few-shot examples for the categorizer.
+/// `None` if they never corrected one.
+async fn correction_examples(pool: &SqlitePool, user_id: Uuid) -> Result<Option<String>> {
+    let corrections = get_recent_corrected_messages(pool, user_id, MAX_CORRECTION_EXAMPLES).await?;
+    if corrections.is_empty() {
+        return Ok(None);
+    }
+    let examples = corrections
+        .into_iter()
+        .map(|correction| {
+            serde_json::json!({
+                "content": message_text(&correction.content),
+                "category": correction.category,
+                "reasoning": correction.reasoning,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(Some(format!(
+        r#"The user you categorize messages for corrected the categories of the messages below, newest first.
+They show what the user wants, so categorize similar messages the same way.
+`reasoning` is the user's own explanation and may be missing.
+
+```json
+{}
+```"#,
+        serde_json::to_string_pretty(&examples)?
+    )))
+}
+
+/// How many of a conversation's latest messages the DM responder sees when writing a reply.
+pub const MAX_REPLY_HISTORY: i64 = 20;
+
+/// Write a reply to the latest message in a conversation on behalf of `owner_id`.
+/// `history` is the conversation's latest messages in chronological order.
+/// Also returns the prompt template version the reply was written with.
+pub async fn generate_reply(
+    state: &AppState,
+    owner_id: Uuid,
+    participants: &[User],
+    history: &[ChatMessage],
+    instructions: Option<&str>,
+) -> Result<(String, Uuid)> {
+    ensure_agents_enabled(&state.pool, owner_id).await?;
+
+    let username = |user_id: Uuid| {
+        participants
+            .iter()
+            .find(|user| user.id == user_id)
+            .map(|user| user.username.as_str())
+            .unwrap_or("Unknown user")
+    };
+    let transcript = history
+        .iter()
+        .map(|message| {
+            let author = if message.sender_id == owner_id {
+                "You".to_string()
+            } else {
+                username(message.sender_id).to_string()
+            };
+            format!("{author}: {}", message.text())
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let last_message = history
+        .last()
+        .map(|message| message.text())
+        .unwrap_or_default();
+    let instructions = instructions.unwrap_or("None");
+
+    let rendered = render_prompt(
+        &state.pool,
+        PromptTemplateName::Responder,
+        &[
+            ("instructions", instructions),
+            ("conversation", &transcript),
+        ],
+    )
+    .await?;
+
+    let backend = state.llm.backend(AgentRole::Responder);
+    let result = backend
+        .complete(LlmRequest::new("DM Responder Agent", rendered.text).with_input(last_message))
+        .await?;
+    Ok((result.trim().to_string(), rendered.template_id))
 }
@@ -0,0 +1,278 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::{TryStreamExt as _, stream};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+use tracing::warn;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::{
+    auth::SessionAuth,
+    entities::{
+        ApiScope, AuditAction, AuditEntry, AuditTarget, create_audit_entry, get_audit_entries,
+    },
+    error::{AppError, Result},
+    llm::AgentRole,
+    pagination::{Page, PageQuery},
+    state::AppState,
+};
+
+/// Entries fetched at a time while exporting
+const EXPORT_BATCH_SIZE: i64 = 100;
+
+// ====== Request/Response Structs ======
+
+/// Narrows down the audit log, every filter that is set has to match.
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+pub struct AuditFilter {
+    /// Only what this delegate did, or the current user's own agents and delegation changes
+    pub actor_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    /// Only what this agent did
+    pub agent: Option<AgentRole>,
+    /// Only what agents did if `true`, only what people did if `false`
+    pub by_agent: Option<bool>,
+    /// Only entries about this message, post, draft or user
+    pub target_id: Option<Uuid>,
+    /// Only entries from this time on
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries from before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// An entry to append to the audit log.
+pub struct NewAuditEntry {
+    pub actor_id: Uuid,
+    pub agent: Option<AgentRole>,
+    pub principal_id: Uuid,
+    pub action: AuditAction,
+    pub target_type: AuditTarget,
+    pub target_id: Uuid,
+    pub details: Option<serde_json::Value>,
+}
+
+impl NewAuditEntry {
+    /// Something `actor_id` did for `principal_id`, or `principal_id` did to their own delegations.
+    pub fn new(
+        actor_id: Uuid,
+        principal_id: Uuid,
+        action: AuditAction,
+        target_type: AuditTarget,
+        target_id: Uuid,
+    ) -> Self {
+        Self {
+            actor_id,
+            agent: None,
+            principal_id,
+            action,
+            target_type,
+            target_id,
+            details: None,
+        }
+    }
+
+    /// Something one of `principal_id`'s agents did for them.
+    pub fn agent(
+        agent: AgentRole,
+        principal_id: Uuid,
+        action: AuditAction,
+        target_type: AuditTarget,
+        target_id: Uuid,
+    ) -> Self {
+        Self {
+            agent: Some(agent),
+            ..Self::new(principal_id, principal_id, action, target_type, target_id)
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+// ====== Recording ======
+
+/// Append an entry to the audit log.
+/// Has to be called in the same transaction as the action it records, so that the action
+/// is rolled back if its entry can't be written and never happens without being audited.
+pub async fn record_audit_entry(conn: &mut SqliteConnection, entry: NewAuditEntry) -> Result<()> {
+    create_audit_entry(conn, &entry).await?;
+    Ok(())
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    description = "List what delegates and agents did on the current user's behalf, \
+        and every change to their delegations, newest first",
+    params(
+        AuditFilter,
+        PageQuery
+    ),
+    responses(
+        (status = OK, description = "Audit log entries", body = Page<AuditEntry>),
+    )
+)]
+pub async fn get_audit_log_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Query(filter): Query<AuditFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AuditRead)?;
+    let entries = get_audit_entries(&state.pool, session.0.id, &filter, &page).await?;
+    Ok((StatusCode::OK, Json(entries)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/audit/export",
+    description = "Download the current user's audit log as JSON Lines, one entry per line, newest first. \
+        Takes the same filters as `/api/audit`. The log is streamed, so it can be of any length.",
+    params(
+        AuditFilter
+    ),
+    responses(
+        (status = OK, description = "Audit log entries", content_type = "application/x-ndjson", body = AuditEntry),
+    )
+)]
+pub async fn export_audit_log_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AuditRead)?;
+    let principal_id = session.0.id;
+    let first_page = PageQuery {
+        limit: Some(EXPORT_BATCH_SIZE),
+        ..Default::default()
+    };
+    // One batch is loaded at a time, the next one only once the client took the previous one
+    let lines = stream::try_unfold(Some(first_page), move |page| {
+        let pool = state.pool.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(page) = page else {
+                return Ok(None);
+            };
+            let entries = get_audit_entries(&pool, principal_id, &filter, &page).await?;
+            let mut lines = String::new();
+            for entry in &entries.items {
+                lines.push_str(&serde_json::to_string(entry)?);
+                lines.push('\n');
+            }
+            let next_page = entries.next_cursor.map(|cursor| PageQuery {
+                before: Some(cursor),
+                ..page
+            });
+            Ok::<_, AppError>(Some((lines, next_page)))
+        }
+    })
+    .map_err(move |e| {
+        warn!("Failed to export the audit log of {principal_id}: {e}");
+        std::io::Error::other(e.to_string())
+    });
+
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, "application/x-ndjson"),
+            (
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"audit-log.jsonl\"",
+            ),
+        ],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::delete_user,
+        testing::{sign_up, test_pool},
+    };
+
+    /// A user with a single audit entry, and that entry's ID.
+    async fn audited_user(pool: &sqlx::SqlitePool) -> (Uuid, Uuid) {
+        let user = sign_up(pool, "audited").await.0;
+        let mut conn = pool.acquire().await.unwrap();
+        record_audit_entry(
+            &mut conn,
+            NewAuditEntry::new(
+                user.id,
+                user.id,
+                AuditAction::DelegationGranted,
+                AuditTarget::User,
+                Uuid::new_v4(),
+            ),
+        )
+        .await
+        .unwrap();
+        // The test pool only has the one connection
+        drop(conn);
+        let entries = get_audit_entries(
+            pool,
+            user.id,
+            &AuditFilter::default(),
+            &PageQuery::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries.items.len(), 1);
+        (user.id, entries.items[0].id)
+    }
+
+    #[tokio::test]
+    async fn entries_cant_be_changed() {
+        let pool = test_pool().await;
+        let (_, entry_id) = audited_user(&pool).await;
+        let result = sqlx::query!(
+            "UPDATE audit_log SET action = 'delegationRevoked' WHERE id = ?",
+            entry_id
+        )
+        .execute(&pool)
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn entries_cant_be_deleted() {
+        let pool = test_pool().await;
+        let (_, entry_id) = audited_user(&pool).await;
+        let result = sqlx::query!("DELETE FROM audit_log WHERE id = ?", entry_id)
+            .execute(&pool)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn entries_outlive_their_user() {
+        let pool = test_pool().await;
+        let (user_id, entry_id) = audited_user(&pool).await;
+        delete_user(&pool, user_id).await.unwrap();
+        let remaining =
+            sqlx::query_scalar!("SELECT COUNT(*) FROM audit_log WHERE id = ?", entry_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(remaining, 1);
+    }
+}
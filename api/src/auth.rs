@@ -1,19 +1,170 @@
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
 use axum::{
-    extract::{FromRequestParts, OptionalFromRequestParts, State},
-    http::{header::COOKIE, request::Parts},
+    extract::{FromRequestParts, OptionalFromRequestParts, Request, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        request::Parts,
+    },
+    middleware::Next,
+    response::Response,
 };
+use chrono::Utc;
 use color_eyre::eyre::eyre;
-use tracing::{Level, instrument};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use tracing::{Level, debug, instrument, warn};
 use uuid::Uuid;
 
 use crate::{
-    entities::{User, get_session, get_user_by_id},
-    error::{AppError, LossyError},
+    ADMIN_USERNAMES, SESSION_RENEW_THRESHOLD, SESSION_TTL,
+    entities::{
+        ApiScope, ApiToken, Session, User, delete_expired_sessions, get_api_token_by_hash,
+        get_session, get_user_by_id, renew_session, touch_api_token, touch_session,
+    },
+    error::{self, AppError, LossyError},
     state::AppState,
 };
 
+/// The outcome of checking a password against what is stored in `users.password`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    /// The password does not match.
+    Invalid,
+    /// The password matches an Argon2 hash.
+    Valid,
+    /// The password matches a legacy plaintext row, which should be rehashed.
+    ValidNeedsRehash,
+}
+
+/// Hash a password using Argon2id with a random salt.
+///
+/// Hashing is deliberately slow, so it is run on the blocking thread pool
+/// to avoid stalling the async runtime.
+pub async fn hash_password(password: String) -> error::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string())
+    })
+    .await
+    .map_err(|e| AppError::Generic(LossyError(eyre!(e))))?
+}
+
+/// Verify a password against the value stored in the database.
+///
+/// Rows created before passwords were hashed still hold the plaintext password,
+/// so anything that doesn't parse as a PHC formatted Argon2 hash is compared in constant time
+/// and reported as needing a rehash.
+pub async fn verify_password(password: String, stored: String) -> error::Result<PasswordCheck> {
+    tokio::task::spawn_blocking(move || {
+        let hash = match PasswordHash::new(&stored) {
+            Ok(hash) if hash.algorithm.as_str().starts_with("argon2") => hash,
+            // A plaintext password can look like anything, including a malformed hash
+            _ => {
+                let matches: bool = password.as_bytes().ct_eq(stored.as_bytes()).into();
+                return Ok(if matches {
+                    PasswordCheck::ValidNeedsRehash
+                } else {
+                    PasswordCheck::Invalid
+                });
+            }
+        };
+
+        match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(PasswordCheck::Valid),
+            Err(argon2::password_hash::Error::Password) => Ok(PasswordCheck::Invalid),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await
+    .map_err(|e| AppError::Generic(LossyError(eyre!(e))))?
+}
+
+/// How often expired sessions are swept from the database.
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Prefix given to every API token so they are easy to recognize (and to scan for if leaked).
+const API_TOKEN_PREFIX: &str = "cloneops_";
+
+/// How the user behind a request authenticated.
 #[derive(Debug)]
-pub struct SessionAuth(pub User);
+pub enum Credential {
+    /// A browser session from the `session` cookie. Has full access to the account.
+    Session(Session),
+    /// An API token from the `Authorization: Bearer` header. Limited to its scopes.
+    Token(ApiToken),
+}
+
+/// The authenticated user making a request, extracted from either
+/// the `session` cookie or an `Authorization: Bearer` API token.
+#[derive(Debug)]
+pub struct SessionAuth(pub User, pub Credential);
+
+impl SessionAuth {
+    /// Fail unless the request is allowed to act with `scope`.
+    /// Sessions can do anything, API tokens need the scope to have been granted.
+    pub fn require_scope(&self, scope: ApiScope) -> error::Result<()> {
+        match &self.1 {
+            Credential::Session(_) => Ok(()),
+            Credential::Token(token) if token.scopes.contains(&scope) => Ok(()),
+            Credential::Token(_) => Err(AppError::UserError((
+                LossyError(StatusCode::FORBIDDEN),
+                format!("This API token is missing the `{}` scope", scope.as_str()),
+            ))),
+        }
+    }
+
+    /// Fail unless the user is listed in `CLONEOPS_ADMINS`.
+    pub fn require_admin(&self) -> error::Result<()> {
+        if ADMIN_USERNAMES.contains(&self.0.username.to_lowercase()) {
+            Ok(())
+        } else {
+            Err(AppError::UserError((
+                LossyError(StatusCode::FORBIDDEN),
+                "Only admins can do this".into(),
+            )))
+        }
+    }
+
+    /// Fail unless the request was made with a session cookie.
+    /// Used for account management that API tokens should never be able to do.
+    pub fn require_session(&self) -> error::Result<&Session> {
+        match &self.1 {
+            Credential::Session(session) => Ok(session),
+            Credential::Token(_) => Err(AppError::UserError((
+                LossyError(StatusCode::FORBIDDEN),
+                "This action can't be performed with an API token".into(),
+            ))),
+        }
+    }
+}
+
+/// Generate a new random API token, returning the token and the hash to store.
+pub fn generate_api_token() -> (String, String) {
+    let token = format!(
+        "{API_TOKEN_PREFIX}{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let hash = hash_api_token(&token);
+    (token, hash)
+}
+
+/// Tokens are long and random, so a fast hash is enough to keep them
+/// safe at rest while still allowing lookups by hash.
+fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
 
 /// Attempt to extract the user from the request's session cookie.
 impl<S> FromRequestParts<S> for SessionAuth
@@ -27,7 +178,11 @@ where
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         <Self as axum::extract::OptionalFromRequestParts<S>>::from_request_parts(parts, state)
             .await
-            .and_then(|res| res.ok_or(AppError::AuthError("No session cookie provided".into())))
+            .and_then(|res| {
+                res.ok_or(AppError::AuthError(
+                    "No session cookie or API token provided".into(),
+                ))
+            })
     }
 }
 
@@ -49,27 +204,254 @@ where
             .await
             .map_err(|_| AppError::Generic(LossyError(eyre!("Database error"))))?;
 
-        let cookies = match parts.headers.get(COOKIE) {
-            Some(k) => k,
-            None => return Ok(None),
-        };
+        // API tokens take precedence over cookies since scripts don't usually send cookies
+        if let Some(token) = bearer_token_from_headers(&parts.headers) {
+            let Some(token) = get_api_token_by_hash(&state.pool, &hash_api_token(token)).await?
+            else {
+                return Err(AppError::AuthError("Invalid API token".into()));
+            };
 
-        let session_id: Uuid = match cookies
-            .to_str()
-            .ok()
-            .and_then(|s| s.split(';').find_map(|s| s.trim().strip_prefix("session=")))
-            .and_then(|s| Uuid::try_parse(s).ok())
-        {
-            Some(id) => id,
-            None => return Ok(None),
+            touch_api_token(&state.pool, token.id).await?;
+            let user = get_user_by_id(&state.pool, token.user_id).await?;
+
+            return Ok(Some(SessionAuth(user, Credential::Token(token))));
+        }
+
+        let Some(session_id) = session_id_from_headers(&parts.headers) else {
+            return Ok(None);
         };
 
         let Some(session) = get_session(&state.pool, session_id).await? else {
             return Err(AppError::AuthError("Invalid session cookie".into()));
         };
 
+        touch_session(&state.pool, session.id).await?;
         let user = get_user_by_id(&state.pool, session.user_id).await?;
+        if let Some(renewal) = parts.extensions.get::<SessionRenewal>() {
+            *renewal.0.lock().unwrap() = Some(session.clone());
+        }
+
+        Ok(Some(SessionAuth(user, Credential::Session(session))))
+    }
+}
+
+/// Read the session ID out of the `session` cookie, if there is one.
+fn session_id_from_headers(headers: &HeaderMap) -> Option<Uuid> {
+    headers
+        .get(COOKIE)?
+        .to_str()
+        .ok()
+        .and_then(|s| s.split(';').find_map(|s| s.trim().strip_prefix("session=")))
+        .and_then(|s| Uuid::try_parse(s).ok())
+}
+
+/// Read an API token out of the `Authorization: Bearer` header, if there is one.
+fn bearer_token_from_headers(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| token.starts_with(API_TOKEN_PREFIX))
+}
+
+/// Build the `Set-Cookie` values for a session so that the cookies
+/// expire at the same time as the session does on the server.
+pub fn session_cookies(session: &Session) -> [String; 2] {
+    let max_age = (session.expires - Utc::now()).num_seconds().max(0);
+    // For development with cross-origin requests (localhost:3000 -> localhost:6969)
+    // In production, you'd want Secure=true and proper domain settings
+    [
+        format!(
+            "session={}; HttpOnly; Max-Age={max_age}; Path=/; SameSite=Lax",
+            session.id
+        ),
+        format!("authenticated=true; Max-Age={max_age}; Path=/; SameSite=Lax"),
+    ]
+}
+
+/// Handed from [`session_renewal`] to the [`SessionAuth`] extractor,
+/// which leaves the session it authenticated with in it.
+#[derive(Clone, Default)]
+struct SessionRenewal(Arc<Mutex<Option<Session>>>);
+
+/// Middleware that extends a session once it has used up `SESSION_RENEW_THRESHOLD`
+/// of its lifetime, so that active users aren't logged out mid-use.
+/// The session is the one `SessionAuth` already loaded, and it's only renewed after the handler ran,
+/// so a session the handler logged out or revoked doesn't come back in the refreshed cookies.
+pub async fn session_renewal(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let renewal = SessionRenewal::default();
+    request.extensions_mut().insert(renewal.clone());
+
+    let mut response = next.run(request).await;
+    let Some(session) = renewal.0.lock().unwrap().take() else {
+        return response;
+    };
+    // The handler already set the session cookie itself, e.g. to clear it on logout
+    let sets_session_cookie = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|cookie| cookie.as_bytes().starts_with(b"session="));
+    if sets_session_cookie {
+        return response;
+    }
+
+    match renew_session_if_due(&state.pool, &session).await {
+        Ok(Some(session)) => {
+            for cookie in session_cookies(&session) {
+                if let Ok(value) = HeaderValue::from_str(&cookie) {
+                    response.headers_mut().append(SET_COOKIE, value);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to renew session: {e}"),
+    }
+    response
+}
+
+async fn renew_session_if_due(
+    pool: &SqlitePool,
+    session: &Session,
+) -> error::Result<Option<Session>> {
+    let ttl = SESSION_TTL.num_seconds() as f64;
+    let remaining = (session.expires - Utc::now()).num_seconds() as f64;
+    if remaining > ttl * (1.0 - *SESSION_RENEW_THRESHOLD) {
+        return Ok(None);
+    }
+
+    renew_session(pool, session.id, Utc::now() + *SESSION_TTL).await
+}
+
+/// Periodically delete expired sessions so the table doesn't grow forever.
+pub async fn sweep_expired_sessions(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match delete_expired_sessions(&pool).await {
+            Ok(0) => {}
+            Ok(removed) => debug!("Removed {removed} expired sessions"),
+            Err(e) => warn!("Failed to remove expired sessions: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{
+        entities::create_api_token,
+        llm::MockScript,
+        testing::{authenticate_token, sign_in_with_token, sign_up, test_state},
+    };
+
+    async fn check(password: &str, stored: &str) -> PasswordCheck {
+        verify_password(password.into(), stored.into())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hashed_passwords() {
+        let hash = hash_password("hunter2".into()).await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(check("hunter2", &hash).await, PasswordCheck::Valid);
+        assert_eq!(check("hunter3", &hash).await, PasswordCheck::Invalid);
+    }
+
+    #[tokio::test]
+    async fn legacy_plaintext_passwords() {
+        assert_eq!(
+            check("hunter2", "hunter2").await,
+            PasswordCheck::ValidNeedsRehash
+        );
+        assert_eq!(check("hunter3", "hunter2").await, PasswordCheck::Invalid);
+    }
+
+    #[tokio::test]
+    async fn plaintext_that_looks_like_a_hash() {
+        for stored in [
+            "$argon2",
+            "$argon2id$v=19$",
+            "$argon2id$not$a$hash",
+            "$scrypt$ln=1$abc",
+        ] {
+            assert_eq!(
+                check(stored, stored).await,
+                PasswordCheck::ValidNeedsRehash,
+                "{stored}"
+            );
+            assert_eq!(
+                check("hunter2", stored).await,
+                PasswordCheck::Invalid,
+                "{stored}"
+            );
+        }
+    }
+
+    fn forbidden<T>(result: error::Result<T>) -> bool {
+        matches!(
+            result,
+            Err(AppError::UserError((LossyError(StatusCode::FORBIDDEN), _)))
+        )
+    }
+
+    #[tokio::test]
+    async fn tokens_are_limited_to_their_scopes() {
+        let state = test_state(MockScript::default()).await;
+        let session = sign_up(&state.pool, "owner").await;
+        assert!(session.require_scope(ApiScope::AuditRead).is_ok());
+        assert!(session.require_session().is_ok());
+
+        let token = sign_in_with_token(
+            &state,
+            session.0.id,
+            &[ApiScope::PostsRead, ApiScope::MessagesRead],
+        )
+        .await;
+        assert_eq!(token.0.id, session.0.id);
+        assert!(token.require_scope(ApiScope::PostsRead).is_ok());
+        assert!(token.require_scope(ApiScope::MessagesRead).is_ok());
+        assert!(forbidden(token.require_scope(ApiScope::PostsWrite)));
+        assert!(forbidden(token.require_scope(ApiScope::AuditRead)));
+        // Account management needs a session whatever the scopes
+        assert!(forbidden(token.require_session()));
+    }
+
+    #[tokio::test]
+    async fn unknown_and_expired_tokens_are_refused() {
+        let state = test_state(MockScript::default()).await;
+        let session = sign_up(&state.pool, "owner").await;
+
+        let (unknown, _) = generate_api_token();
+        assert!(matches!(
+            authenticate_token(&state, &unknown).await,
+            Err(AppError::AuthError(_))
+        ));
 
-        Ok(Some(SessionAuth(user)))
+        let (expired, hash) = generate_api_token();
+        let expires_at = Utc::now() - TimeDelta::minutes(1);
+        create_api_token(
+            &state.pool,
+            session.0.id,
+            "expired",
+            &hash,
+            &[ApiScope::PostsRead],
+            Some(expires_at),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            authenticate_token(&state, &expired).await,
+            Err(AppError::AuthError(_))
+        ));
     }
 }
@@ -0,0 +1,360 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::SessionAuth,
+    entities::{
+        ApiScope, Category, MessageCategory, create_category, delete_category, get_user_categories,
+        update_category,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    state::AppState,
+};
+
+const MAX_NAME_LEN: usize = 50;
+const MAX_DESCRIPTION_LEN: usize = 500;
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryRequest {
+    pub name: String,
+    /// Tells the categorizer which messages belong in the category
+    pub description: String,
+    /// Categories are listed from the lowest to the highest position
+    #[serde(default)]
+    pub position: i64,
+}
+
+impl CategoryRequest {
+    fn validate(&mut self) -> Result<()> {
+        let invalid = |message: String| {
+            Err(AppError::UserError((
+                LossyError(StatusCode::BAD_REQUEST),
+                message,
+            )))
+        };
+
+        self.name = self.name.trim().to_string();
+        self.description = self.description.trim().to_string();
+        if self.name.is_empty() {
+            return invalid("Category name can't be empty".into());
+        }
+        if self.name.chars().count() > MAX_NAME_LEN {
+            return invalid(format!(
+                "Category name can be at most {MAX_NAME_LEN} characters"
+            ));
+        }
+        if self.description.is_empty() {
+            return invalid("The categorizer needs a description of the category".into());
+        }
+        if self.description.chars().count() > MAX_DESCRIPTION_LEN {
+            return invalid(format!(
+                "Category description can be at most {MAX_DESCRIPTION_LEN} characters"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The key of a new category, its name in camel case, e.g. `Fan Mail` becomes `fanMail`.
+fn category_key(name: &str) -> Result<MessageCategory> {
+    let key = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .enumerate()
+        .map(|(i, word)| {
+            let word = word.to_lowercase();
+            if i == 0 {
+                return word;
+            }
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    if key.is_empty() {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Category name needs at least one letter or digit".into(),
+        )));
+    }
+    Ok(MessageCategory::new(key))
+}
+
+// ====== Prompt ======
+
+/// The user's categories as the list the categorizer chooses from.
+pub fn describe_categories(categories: &[Category]) -> String {
+    categories
+        .iter()
+        .map(|category| {
+            format!(
+                "*   **`{}`** ({}): {}",
+                category.key, category.name, category.description
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/categories",
+    description = "List the categories the current user's messages are sorted into",
+    responses(
+        (status = OK, description = "Categories", body = Vec<Category>),
+    )
+)]
+pub async fn list_categories_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let categories = get_user_categories(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(categories)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/categories",
+    description = "Add a category for the categorizer to sort the current user's messages into. \
+        Its key is derived from the name, e.g. `Fan Mail` becomes `fanMail`.",
+    request_body = CategoryRequest,
+    responses(
+        (status = CREATED, description = "Category created", body = Category),
+        (status = BAD_REQUEST, description = "Invalid category", body = ErrorResponse),
+        (status = CONFLICT, description = "A category with the same key already exists", body = ErrorResponse),
+    )
+)]
+pub async fn create_category_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(mut payload): Json<CategoryRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    payload.validate()?;
+    let key = category_key(&payload.name)?;
+    let category = create_category(&state.pool, session.0.id, &key, &payload).await?;
+    Ok((StatusCode::CREATED, Json(category)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/categories/{key}",
+    description = "Rename, redescribe or move one of the current user's categories. \
+        The key stays the same, so messages, rules and responder policies keep referring to it.",
+    params(
+        ("key" = MessageCategory, Path, description = "Key of the category to update")
+    ),
+    request_body = CategoryRequest,
+    responses(
+        (status = OK, description = "Category updated", body = Category),
+        (status = BAD_REQUEST, description = "Invalid category", body = ErrorResponse),
+        (status = NOT_FOUND, description = "Category not found", body = ErrorResponse),
+    )
+)]
+pub async fn update_category_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(key): Path<MessageCategory>,
+    Json(mut payload): Json<CategoryRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    payload.validate()?;
+    let category = update_category(&state.pool, session.0.id, &key, &payload).await?;
+    Ok((StatusCode::OK, Json(category)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/categories/{key}",
+    description = "Delete one of the current user's categories. Its messages become uncategorized \
+        and the rules and responder policy for it are deleted too. Corrections to it are kept, \
+        but no longer shown to the categorizer. The last category can't be deleted.",
+    params(
+        ("key" = MessageCategory, Path, description = "Key of the category to delete")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Category deleted"),
+        (status = NOT_FOUND, description = "Category not found", body = ErrorResponse),
+        (status = CONFLICT, description = "It's the user's last category", body = ErrorResponse),
+    )
+)]
+pub async fn delete_category_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(key): Path<MessageCategory>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    delete_category(&state.pool, session.0.id, &key).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{
+            DEFAULT_CATEGORIES, correct_message_category, create_chat_message, create_conversation,
+            get_category_corrections, get_recent_corrected_messages,
+        },
+        testing::{sign_up, test_pool},
+    };
+    use rig::{OneOrMany, message::UserContent};
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+
+    fn request(name: &str) -> CategoryRequest {
+        CategoryRequest {
+            name: name.into(),
+            description: format!("Messages about {name}"),
+            position: 10,
+        }
+    }
+
+    fn status<T>(result: Result<T>) -> StatusCode {
+        match result {
+            Ok(_) => StatusCode::OK,
+            Err(e) => e.into_response().status(),
+        }
+    }
+
+    async fn keys(pool: &SqlitePool, user_id: Uuid) -> Vec<String> {
+        get_user_categories(pool, user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|category| category.key.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn keys_are_camel_cased_names() {
+        assert_eq!(category_key("Fan Mail").unwrap().to_string(), "fanMail");
+        assert_eq!(
+            category_key("  brand-DEALS 2025 ").unwrap().to_string(),
+            "brandDeals2025"
+        );
+        assert_eq!(status(category_key("!!")), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn categories_can_be_created_updated_and_deleted() {
+        let pool = test_pool().await;
+        let user_id = sign_up(&pool, "owner").await.0.id;
+        assert_eq!(keys(&pool, user_id).await.len(), DEFAULT_CATEGORIES.len());
+
+        let key = category_key("Fan Mail").unwrap();
+        create_category(&pool, user_id, &key, &request("Fan Mail"))
+            .await
+            .unwrap();
+        assert_eq!(
+            status(create_category(&pool, user_id, &key, &request("Fan Mail")).await),
+            StatusCode::CONFLICT
+        );
+
+        let renamed = update_category(&pool, user_id, &key, &request("Fans"))
+            .await
+            .unwrap();
+        assert_eq!(renamed.key, key);
+        assert_eq!(renamed.name, "Fans");
+        assert_eq!(
+            keys(&pool, user_id).await.last(),
+            Some(&"fanMail".to_string())
+        );
+
+        delete_category(&pool, user_id, &key).await.unwrap();
+        assert!(!keys(&pool, user_id).await.contains(&"fanMail".to_string()));
+        assert_eq!(
+            status(delete_category(&pool, user_id, &key).await),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn the_last_category_cant_be_deleted() {
+        let pool = test_pool().await;
+        let user_id = sign_up(&pool, "owner").await.0.id;
+        let mut keys = keys(&pool, user_id).await;
+        let last = keys.pop().unwrap();
+        for key in keys {
+            delete_category(&pool, user_id, &MessageCategory::new(key))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            status(delete_category(&pool, user_id, &MessageCategory::new(last)).await),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(get_user_categories(&pool, user_id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_category_keeps_its_corrections() {
+        let pool = test_pool().await;
+        let user_id = sign_up(&pool, "owner").await.0.id;
+        let fan_id = sign_up(&pool, "fan").await.0.id;
+        let conversation = create_conversation(&pool, &[user_id, fan_id])
+            .await
+            .unwrap();
+        let message = |text: &'static str| {
+            create_chat_message(
+                &pool,
+                conversation.id,
+                fan_id,
+                OneOrMany::one(UserContent::text(text)),
+                false,
+                &[],
+            )
+        };
+        let fan_mail = message("I love your videos!").await.unwrap();
+        let offer = message("Free followers, click here").await.unwrap();
+
+        let key = category_key("Fan Mail").unwrap();
+        create_category(&pool, user_id, &key, &request("Fan Mail"))
+            .await
+            .unwrap();
+        correct_message_category(&pool, user_id, fan_mail.id, key.clone(), Some("A fan"))
+            .await
+            .unwrap();
+        correct_message_category(&pool, user_id, offer.id, MessageCategory::new("spam"), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_recent_corrected_messages(&pool, user_id, 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        delete_category(&pool, user_id, &key).await.unwrap();
+
+        let corrections = get_category_corrections(&pool, user_id, fan_mail.id)
+            .await
+            .unwrap();
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].category, key);
+        assert_eq!(corrections[0].reasoning.as_deref(), Some("A fan"));
+
+        // Only the correction to a category that still exists is an example
+        let examples = get_recent_corrected_messages(&pool, user_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].category, MessageCategory::new("spam"));
+    }
+}
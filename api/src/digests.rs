@@ -0,0 +1,374 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, Days, NaiveTime, Utc};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    agents::ensure_agents_enabled,
+    auth::SessionAuth,
+    entities::{
+        ApiScope, Digest, DigestPeriod, DigestStats, PromptTemplateName, create_digest, get_digest,
+        get_digest_stats, get_user_digests, get_users_missing_digest,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    events::{SseEvent, broadcast_event},
+    llm::{AgentRole, LlmRequest},
+    pagination::{Page, PageQuery},
+    prompts::render_prompt,
+    state::AppState,
+};
+
+const DIGEST_SCHEDULER_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const DIGEST_SCHEDULER_BATCH_SIZE: i64 = 20;
+/// Delegate posts quoted to the summarizer, the rest are only counted
+const MAX_SUMMARIZED_POSTS: usize = 10;
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateDigestRequest {
+    /// Covers the last 24 hours for `daily` and the last 7 days for `weekly`
+    pub period: DigestPeriod,
+}
+
+pub struct NewDigest {
+    pub user_id: Uuid,
+    pub period: DigestPeriod,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub scheduled: bool,
+    pub stats: DigestStats,
+    pub summary: Option<String>,
+    pub prompt_template_id: Option<Uuid>,
+}
+
+// ====== Periods ======
+
+impl DigestPeriod {
+    fn length(self) -> Days {
+        match self {
+            DigestPeriod::Daily => Days::new(1),
+            DigestPeriod::Weekly => Days::new(7),
+        }
+    }
+
+    /// The most recent period that is over, in UTC.
+    /// Days end at midnight and weeks end at midnight on Monday.
+    fn last_completed(self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let today = now.date_naive();
+        let end = match self {
+            DigestPeriod::Daily => today,
+            DigestPeriod::Weekly => {
+                today - Days::new(today.weekday().num_days_from_monday().into())
+            }
+        };
+        let end = end.and_time(NaiveTime::MIN).and_utc();
+        (end - self.length(), end)
+    }
+}
+
+// ====== Generation ======
+
+/// The stats as plain text for the summarizer.
+fn describe_stats(stats: &DigestStats) -> String {
+    let title = |title: &Option<String>| title.clone().unwrap_or_else(|| "Untitled".into());
+    let mut lines = vec![format!("Messages received: {}", stats.messages_received)];
+
+    lines.push("Messages per category:".into());
+    lines.extend(
+        stats
+            .categories
+            .iter()
+            .map(|category| format!("- {}: {}", category.name, category.count)),
+    );
+
+    lines.push(format!(
+        "Conversations with unread urgent messages: {}",
+        stats.unread_urgent_threads.len()
+    ));
+    lines.extend(stats.unread_urgent_threads.iter().map(|thread| {
+        format!(
+            "- {}: {} unread, latest at {}",
+            title(&thread.conversation_title),
+            thread.unread_urgent,
+            thread.latest_at.format("%Y-%m-%d %H:%M UTC")
+        )
+    }));
+
+    lines.push(format!(
+        "Posts published by delegates: {}",
+        stats.delegate_posts.len()
+    ));
+    lines.extend(
+        stats
+            .delegate_posts
+            .iter()
+            .take(MAX_SUMMARIZED_POSTS)
+            .map(|post| format!("- By {}: {}", post.created_by_username, post.text)),
+    );
+
+    lines.push(format!(
+        "Automatic replies sent by the DM responder: {}",
+        stats
+            .agent_replies
+            .iter()
+            .map(|replies| replies.replies)
+            .sum::<i64>()
+    ));
+    lines.extend(stats.agent_replies.iter().map(|replies| {
+        format!(
+            "- {}: {}",
+            title(&replies.conversation_title),
+            replies.replies
+        )
+    }));
+
+    lines.join("\n")
+}
+
+/// Ask the summarizer for a short narrative of the stats,
+/// along with the prompt template version it was written with.
+/// Returns `None` when there's nothing to summarize.
+async fn summarize(
+    state: &AppState,
+    user_id: Uuid,
+    period: DigestPeriod,
+    stats: &DigestStats,
+) -> Result<Option<(String, Uuid)>> {
+    let quiet = stats.messages_received == 0
+        && stats.unread_urgent_threads.is_empty()
+        && stats.delegate_posts.is_empty()
+        && stats.agent_replies.is_empty();
+    if quiet {
+        return Ok(None);
+    }
+    ensure_agents_enabled(&state.pool, user_id).await?;
+
+    let period = match period {
+        DigestPeriod::Daily => "day",
+        DigestPeriod::Weekly => "week",
+    };
+    let activity = describe_stats(stats);
+    let rendered = render_prompt(
+        &state.pool,
+        PromptTemplateName::Summarizer,
+        &[("period", period), ("activity", &activity)],
+    )
+    .await?;
+    let backend = state.llm.backend(AgentRole::Summarizer);
+    let result = backend
+        .complete(LlmRequest::new("Digest Summarizer Agent", rendered.text).with_input(activity))
+        .await?;
+    Ok(Some((result.trim().to_string(), rendered.template_id)))
+}
+
+/// Gather the stats of `user_id` between `period_start` and `period_end`, summarize them and save the digest.
+/// The digest is saved without a summary if the summarizer can't run.
+/// Returns `None` if the scheduler already wrote a digest for the same period.
+pub async fn generate_digest(
+    state: &AppState,
+    user_id: Uuid,
+    period: DigestPeriod,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+    scheduled: bool,
+) -> Result<Option<Digest>> {
+    let stats = get_digest_stats(&state.pool, user_id, period_start, period_end).await?;
+    let (summary, prompt_template_id) = match summarize(state, user_id, period, &stats).await {
+        Ok(Some((summary, prompt_template_id))) => (Some(summary), Some(prompt_template_id)),
+        Ok(None) => (None, None),
+        Err(e) => {
+            warn!("Failed to summarize digest for {user_id}: {e}");
+            (None, None)
+        }
+    };
+
+    let digest = NewDigest {
+        user_id,
+        period,
+        period_start,
+        period_end,
+        scheduled,
+        stats,
+        summary,
+        prompt_template_id,
+    };
+    let digest = create_digest(&state.pool, &digest).await?;
+    if let Some(digest) = &digest {
+        broadcast_event(
+            &state.clients,
+            &[user_id],
+            &SseEvent::DigestCreated(digest.clone()),
+        )
+        .await;
+    }
+    Ok(digest)
+}
+
+/// Periodically write every user's daily and weekly digests once the day or week is over.
+/// Only the most recent period is written, periods missed while the server was down are skipped.
+pub async fn run_digest_scheduler(state: AppState) {
+    let mut interval = tokio::time::interval(DIGEST_SCHEDULER_INTERVAL);
+    loop {
+        interval.tick().await;
+        for period in [DigestPeriod::Daily, DigestPeriod::Weekly] {
+            let (period_start, period_end) = period.last_completed(Utc::now());
+            let users = match get_users_missing_digest(
+                &state.pool,
+                period,
+                period_end,
+                DIGEST_SCHEDULER_BATCH_SIZE,
+            )
+            .await
+            {
+                Ok(users) => users,
+                Err(e) => {
+                    warn!("Failed to load users missing a {period:?} digest: {e}");
+                    continue;
+                }
+            };
+
+            for user_id in users {
+                match generate_digest(&state, user_id, period, period_start, period_end, true).await
+                {
+                    Ok(Some(digest)) => {
+                        debug!("Wrote {period:?} digest {} for {user_id}", digest.id)
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to write {period:?} digest for {user_id}: {e}"),
+                }
+            }
+        }
+    }
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/digests",
+    description = "List the current user's digests, newest first",
+    params(
+        PageQuery
+    ),
+    responses(
+        (status = OK, description = "Digests", body = Page<Digest>),
+    )
+)]
+pub async fn list_digests_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Query(page): Query<PageQuery>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let digests = get_user_digests(&state.pool, session.0.id, &page).await?;
+    Ok((StatusCode::OK, Json(digests)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/digests",
+    description = "Write a digest of the current user's activity up to now. \
+        Daily and weekly digests are also written automatically once a day or week (in UTC) is over.",
+    request_body = CreateDigestRequest,
+    responses(
+        (status = CREATED, description = "Digest written", body = Digest),
+    )
+)]
+pub async fn create_digest_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<CreateDigestRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    let period_end = Utc::now();
+    let period_start = period_end - payload.period.length();
+    let Some(digest) = generate_digest(
+        &state,
+        session.0.id,
+        payload.period,
+        period_start,
+        period_end,
+        false,
+    )
+    .await?
+    else {
+        // Only scheduled digests can clash with an existing one
+        return Err(AppError::UserError((
+            LossyError(StatusCode::CONFLICT),
+            "Digest already exists!".into(),
+        )));
+    };
+    Ok((StatusCode::CREATED, Json(digest)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/digests/{id}",
+    params(
+        ("id" = Uuid, Path, description = "ID of the digest")
+    ),
+    responses(
+        (status = OK, description = "Digest", body = Digest),
+        (status = NOT_FOUND, description = "Digest not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_digest_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let digest = get_digest(&state.pool, session.0.id, id).await?;
+    Ok((StatusCode::OK, Json(digest)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn daily_period_is_yesterday() {
+        assert_eq!(
+            DigestPeriod::Daily.last_completed(at(2024, 5, 15, 13)),
+            (at(2024, 5, 14, 0), at(2024, 5, 15, 0))
+        );
+        // Right at midnight the day that just ended is the one that's over
+        assert_eq!(
+            DigestPeriod::Daily.last_completed(at(2024, 5, 15, 0)),
+            (at(2024, 5, 14, 0), at(2024, 5, 15, 0))
+        );
+    }
+
+    #[test]
+    fn weekly_period_ends_on_monday() {
+        // 2024-05-15 is a Wednesday
+        assert_eq!(
+            DigestPeriod::Weekly.last_completed(at(2024, 5, 15, 13)),
+            (at(2024, 5, 6, 0), at(2024, 5, 13, 0))
+        );
+        assert_eq!(
+            DigestPeriod::Weekly.last_completed(at(2024, 5, 13, 0)),
+            (at(2024, 5, 6, 0), at(2024, 5, 13, 0))
+        );
+        assert_eq!(
+            DigestPeriod::Weekly.last_completed(at(2024, 5, 12, 23)),
+            (at(2024, 4, 29, 0), at(2024, 5, 6, 0))
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use rig::{OneOrMany, message::UserContent};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    audit::{NewAuditEntry, record_audit_entry},
+    auth::SessionAuth,
+    entities::{
+        ApiScope, AuditAction, AuditTarget, Draft, DraftKind, DraftSource, DraftStatus,
+        create_chat_message, create_draft, create_post, create_scheduled_post, get_user_drafts,
+        is_user_in_conversation, reopen_draft, resolve_draft, set_draft_result,
+        update_draft_content,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    events::{SseEvent, broadcast_event},
+    llm::AgentRole,
+    messaging::deliver_message,
+    pagination::{Page, PageQuery},
+    posts::broadcast_new_post,
+    state::AppState,
+    utoipa_compat,
+};
+
+// ====== Request/Response Structs ======
+
+/// A message or post to hold for the owner's approval.
+pub struct NewDraft {
+    pub owner_id: Uuid,
+    pub created_by: Uuid,
+    pub kind: DraftKind,
+    pub source: DraftSource,
+    pub conversation_id: Option<Uuid>,
+    pub in_reply_to: Option<Uuid>,
+    pub content: OneOrMany<UserContent>,
+    /// When to publish a post draft once it's approved
+    pub scheduled_at: Option<DateTime<Utc>>,
+    /// The prompt template version an agent wrote the draft with
+    pub prompt_template_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftsQuery {
+    /// Only return drafts with this status, all drafts are returned if omitted
+    pub status: Option<DraftStatus>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EditDraftRequest {
+    #[schema(value_type = Vec<utoipa_compat::UserContent>)]
+    pub content: OneOrMany<UserContent>,
+}
+
+// ====== Draft Queue ======
+
+/// Queue a draft and let its owner, and the delegate who wrote it, know about it.
+pub async fn submit_draft(state: &AppState, draft: NewDraft) -> Result<Draft> {
+    let mut tx = state.pool.begin().await?;
+    let draft = create_draft(&mut *tx, &draft).await?;
+    let agent = match draft.source {
+        DraftSource::DmResponder => Some(AgentRole::Responder),
+        DraftSource::Enhancer => Some(AgentRole::Enhancer),
+        DraftSource::Delegate => None,
+    };
+    let entry = match agent {
+        Some(agent) => NewAuditEntry::agent(
+            agent,
+            draft.owner_id,
+            AuditAction::AgentDraftCreated,
+            AuditTarget::Draft,
+            draft.id,
+        ),
+        None => NewAuditEntry::new(
+            draft.created_by,
+            draft.owner_id,
+            AuditAction::DelegatedPostDrafted,
+            AuditTarget::Draft,
+            draft.id,
+        ),
+    };
+    record_audit_entry(&mut tx, entry.with_details(json!({ "kind": draft.kind }))).await?;
+    tx.commit().await?;
+    broadcast_event(
+        &state.clients,
+        &draft_recipients(&draft),
+        &SseEvent::DraftCreated(draft.clone()),
+    )
+    .await;
+    Ok(draft)
+}
+
+fn draft_recipients(draft: &Draft) -> Vec<Uuid> {
+    if draft.created_by == draft.owner_id {
+        vec![draft.owner_id]
+    } else {
+        vec![draft.owner_id, draft.created_by]
+    }
+}
+
+/// Send, post or schedule an approved draft as its owner,
+/// returning the ID of the new message, post or scheduled post.
+async fn publish_draft(state: &AppState, draft: &Draft) -> Result<Uuid> {
+    let content = draft.content.0.clone();
+    match draft.kind {
+        DraftKind::Message => {
+            let Some(conversation_id) = draft.conversation_id else {
+                return Err(AppError::Generic(LossyError(eyre!(
+                    "Message draft {} has no conversation",
+                    draft.id
+                ))));
+            };
+            if !is_user_in_conversation(&state.pool, draft.owner_id, conversation_id).await? {
+                return Err(AppError::AuthError(
+                    "You are no longer a member of this conversation.".into(),
+                ));
+            }
+            let agent_authored = draft.source == DraftSource::DmResponder;
+            let message = create_chat_message(
+                &state.pool,
+                conversation_id,
+                draft.owner_id,
+                content,
+                agent_authored,
+                &[],
+            )
+            .await?;
+            deliver_message(state, &message).await?;
+            Ok(message.id)
+        }
+        DraftKind::Post => {
+            // Drafts approved after their scheduled time are published right away
+            if let Some(scheduled_at) = draft.scheduled_at.filter(|at| *at > Utc::now()) {
+                let scheduled_post = create_scheduled_post(
+                    &state.pool,
+                    draft.owner_id,
+                    draft.created_by,
+                    content,
+                    &[],
+                    scheduled_at,
+                )
+                .await?;
+                return Ok(scheduled_post.id);
+            }
+            let mut tx = state.pool.begin().await?;
+            let post =
+                create_post(&mut *tx, draft.owner_id, draft.created_by, content, &[]).await?;
+            if post.created_by != post.user_id {
+                record_audit_entry(
+                    &mut tx,
+                    NewAuditEntry::new(
+                        post.created_by,
+                        post.user_id,
+                        AuditAction::DelegatedPostPublished,
+                        AuditTarget::Post,
+                        post.id,
+                    )
+                    .with_details(json!({ "draftId": draft.id })),
+                )
+                .await?;
+            }
+            tx.commit().await?;
+            broadcast_new_post(state, &post).await?;
+            Ok(post.id)
+        }
+    }
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/drafts",
+    description = "List the messages and posts waiting for the current user's approval",
+    params(
+        ("status" = Option<DraftStatus>, Query, description = "Only return drafts with this status"),
+        PageQuery
+    ),
+    responses(
+        (status = OK, description = "Drafts", body = Page<Draft>),
+    )
+)]
+pub async fn list_drafts_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Query(query): Query<DraftsQuery>,
+    Query(page): Query<PageQuery>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    session.require_scope(ApiScope::PostsRead)?;
+    let drafts = get_user_drafts(&state.pool, session.0.id, query.status, &page).await?;
+    Ok((StatusCode::OK, Json(drafts)).into_response())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/drafts/{id}",
+    description = "Change the content of a pending draft before approving it",
+    params(
+        ("id" = Uuid, Path, description = "ID of the draft to edit")
+    ),
+    request_body = EditDraftRequest,
+    responses(
+        (status = OK, description = "Draft updated", body = Draft),
+        (status = NOT_FOUND, description = "Pending draft not found", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Drafts can't be changed with an API token", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn edit_draft_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(draft_id): Path<Uuid>,
+    Json(payload): Json<EditDraftRequest>,
+) -> Result<Response> {
+    // Drafts exist so a person signs off on what agents write, so tokens can't touch them
+    session.require_session()?;
+    let draft = update_draft_content(&state.pool, session.0.id, draft_id, payload.content).await?;
+    Ok((StatusCode::OK, Json(draft)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/drafts/{id}/approve",
+    description = "Send or post a pending draft as the current user",
+    params(
+        ("id" = Uuid, Path, description = "ID of the draft to approve")
+    ),
+    responses(
+        (status = OK, description = "Draft approved", body = Draft),
+        (status = NOT_FOUND, description = "Pending draft not found", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Drafts can't be approved with an API token", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn approve_draft_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(draft_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_session()?;
+    let draft = resolve_draft(&state.pool, session.0.id, draft_id, DraftStatus::Approved).await?;
+
+    let result_id = match publish_draft(&state, &draft).await {
+        Ok(result_id) => result_id,
+        Err(e) => {
+            reopen_draft(&state.pool, draft.id).await?;
+            return Err(e);
+        }
+    };
+    let draft = set_draft_result(&state.pool, draft.id, result_id).await?;
+
+    broadcast_event(
+        &state.clients,
+        &draft_recipients(&draft),
+        &SseEvent::DraftResolved(draft.clone()),
+    )
+    .await;
+    Ok((StatusCode::OK, Json(draft)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/drafts/{id}/reject",
+    description = "Discard a pending draft",
+    params(
+        ("id" = Uuid, Path, description = "ID of the draft to reject")
+    ),
+    responses(
+        (status = OK, description = "Draft rejected", body = Draft),
+        (status = NOT_FOUND, description = "Pending draft not found", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Drafts can't be rejected with an API token", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn reject_draft_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(draft_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_session()?;
+    let draft = resolve_draft(&state.pool, session.0.id, draft_id, DraftStatus::Rejected).await?;
+    broadcast_event(
+        &state.clients,
+        &draft_recipients(&draft),
+        &SseEvent::DraftResolved(draft.clone()),
+    )
+    .await;
+    Ok((StatusCode::OK, Json(draft)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        entities::{create_conversation, get_chat_message},
+        llm::MockScript,
+        testing::{sign_up, subscribe, test_state},
+    };
+
+    #[tokio::test]
+    async fn approved_message_drafts_are_delivered() {
+        let state = test_state(MockScript::default()).await;
+        let owner = sign_up(&state.pool, "owner").await;
+        let fan_id = sign_up(&state.pool, "fan").await.0.id;
+        let conversation = create_conversation(&state.pool, &[owner.0.id, fan_id])
+            .await
+            .unwrap();
+        let draft = submit_draft(
+            &state,
+            NewDraft {
+                owner_id: owner.0.id,
+                created_by: owner.0.id,
+                kind: DraftKind::Message,
+                source: DraftSource::DmResponder,
+                conversation_id: Some(conversation.id),
+                in_reply_to: None,
+                content: OneOrMany::one(UserContent::text("Happy to collaborate!")),
+                scheduled_at: None,
+                prompt_template_id: None,
+            },
+        )
+        .await
+        .unwrap();
+        let mut fan_events = subscribe(&state, fan_id);
+
+        let response = approve_draft_handler(State(state.clone()), owner, Path(draft.id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let draft: Draft = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        let message_id = draft.result_id.expect("the sent message");
+        let message = get_chat_message(&state.pool, message_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.sender_id, draft.owner_id);
+        assert_eq!(message.conversation_id, conversation.id);
+        assert!(message.agent_authored);
+
+        // The fan is sent the message and gets it categorized, like one the owner wrote themselves
+        let delivered = timeout(Duration::from_secs(5), async {
+            let (mut sent, mut categorized) = (false, false);
+            while !(sent && categorized) {
+                match fan_events.recv().await.unwrap() {
+                    SseEvent::NewMessage(message) => sent |= message.id == message_id,
+                    SseEvent::MessageCategorized { message_id: id, .. } => {
+                        categorized |= id == message_id
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+        assert!(delivered.is_ok(), "the message wasn't delivered to the fan");
+    }
+}
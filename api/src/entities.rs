@@ -1,14 +1,26 @@
 use axum::http::StatusCode;
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Utc, Weekday};
+use color_eyre::eyre::eyre;
 use rig::{OneOrMany, message::UserContent};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::{SqlitePool, prelude::FromRow, types::Json};
+use sqlx::{
+    Acquire, Sqlite, SqliteConnection, SqliteExecutor, SqlitePool, prelude::FromRow, types::Json,
+};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
+    SESSION_TTL,
+    audit::{AuditFilter, NewAuditEntry},
+    categories::CategoryRequest,
+    digests::NewDigest,
+    drafts::NewDraft,
     error::{AppError, LossyError, Result},
+    llm::AgentRole,
+    pagination::{Cursor, Page, PageQuery},
+    posts::DelegationSettings,
+    rules::CategorizationRuleRequest,
     users::CreateUser,
 };
 
@@ -17,21 +29,117 @@ use crate::{
 pub struct User {
     pub id: Uuid,
     pub username: String,
+    /// Argon2 PHC string. Never sent to clients.
+    #[serde(skip_serializing)]
     pub password: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    #[schema(value_type = Vec<ProfileLink>)]
+    pub links: Json<Vec<ProfileLink>>,
+    /// When the avatar was last replaced, unset if the user has no avatar
+    pub avatar_updated_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A user in a followers or following list.
+#[derive(Clone, Debug, FromRow)]
+pub struct FollowedUser {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub links: Json<Vec<ProfileLink>>,
+    pub avatar_updated_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    /// When the follow started, which the lists are sorted by
+    pub followed_at: DateTime<Utc>,
+}
+
+/// A link shown on a user's profile.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileLink {
+    pub label: String,
+    pub url: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Session {
+    /// The secret in the `session` cookie. Never sent to clients.
+    #[serde(skip_serializing)]
     pub id: Uuid,
+    /// Identifies the session when listing and revoking sessions
+    pub public_id: Uuid,
     pub user_id: Uuid,
     pub expires: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A permission that can be granted to an API token.
+/// Session cookies implicitly carry every scope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum ApiScope {
+    #[serde(rename = "posts:read")]
+    PostsRead,
+    #[serde(rename = "posts:write")]
+    PostsWrite,
+    #[serde(rename = "messages:read")]
+    MessagesRead,
+    #[serde(rename = "messages:write")]
+    MessagesWrite,
+    #[serde(rename = "delegations:manage")]
+    DelegationsManage,
+    #[serde(rename = "agents:run")]
+    AgentsRun,
+    #[serde(rename = "audit:read")]
+    AuditRead,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PostsRead => "posts:read",
+            ApiScope::PostsWrite => "posts:write",
+            ApiScope::MessagesRead => "messages:read",
+            ApiScope::MessagesWrite => "messages:write",
+            ApiScope::DelegationsManage => "delegations:manage",
+            ApiScope::AgentsRun => "agents:run",
+            ApiScope::AuditRead => "audit:read",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    #[schema(value_type = Vec<ApiScope>)]
+    pub scopes: Json<Vec<ApiScope>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The `kill_switches.user_id` of the switch that stops agents for every user.
+pub const GLOBAL_KILL_SWITCH: Uuid = Uuid::nil();
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KillSwitch {
+    pub engaged: bool,
+    /// Who last flipped the switch, if it has ever been flipped
+    pub updated_by: Option<Uuid>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Conversation {
@@ -57,20 +165,112 @@ pub struct ChatMessage {
     pub conversation_id: Uuid,
     pub sender_id: Uuid,
     pub content: String,
+    /// Written by the sender's DM responder agent rather than the sender themselves
+    pub agent_authored: bool,
+    /// IDs of the attached media, served from `/api/media/{id}`
+    #[schema(value_type = Vec<Uuid>)]
+    pub media: Json<Vec<Uuid>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, sqlx::Type, ToSchema, JsonSchema)]
-#[repr(u8)]
+impl ChatMessage {
+    /// The text parts of the message joined by newlines, without any images.
+    /// Falls back to the raw stored content if it isn't valid `UserContent`.
+    pub fn text(&self) -> String {
+        message_text(&self.content)
+    }
+}
+
+/// The text parts of stored message content, see [`ChatMessage::text`].
+pub fn message_text(content: &str) -> String {
+    match serde_json::from_str::<OneOrMany<UserContent>>(content) {
+        Ok(content) => content
+            .iter()
+            .filter_map(|part| match part {
+                UserContent::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(_) => content.to_string(),
+    }
+}
+
+/// The key of one of a user's categories, e.g. `important` or `fanMail`.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema, JsonSchema,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct MessageCategory(pub String);
+
+impl MessageCategory {
+    pub const URGENT: &str = "urgent";
+
+    pub fn new(key: impl Into<String>) -> Self {
+        Self(key.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for MessageCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The categories every user starts with as `(key, name, description)`.
+/// Keep in sync with the `0015_categories` migration, which gave them to existing users.
+pub const DEFAULT_CATEGORIES: [(&str, &str, &str); 6] = [
+    (
+        "important",
+        "Important",
+        "Messages that are personal and likely require a response. This includes messages from close contacts, direct questions, or follow-ups in an ongoing, meaningful conversation.",
+    ),
+    (
+        "sponsorship",
+        "Sponsorship",
+        "Direct inquiries related to paid partnerships, brand deals, affiliate marketing, or other advertising opportunities.",
+    ),
+    (
+        "networking",
+        "Networking",
+        "Messages from peers or new contacts aimed at building professional relationships, seeking advice, or collaboration (unpaid).",
+    ),
+    (
+        "generalInquiry",
+        "General Inquiry",
+        "Neutral questions or requests for information that are not time-sensitive.",
+    ),
+    (
+        "spam",
+        "Spam",
+        "Unsolicited, irrelevant, low-quality, or malicious messages. Often from unknown senders with no prior relationship.",
+    ),
+    (
+        MessageCategory::URGENT,
+        "Urgent",
+        "Reserved for messages that are explicitly time-critical or indicate a potential emergency. Use this category sparingly.",
+    ),
+];
+
+/// One of the categories a user's messages are sorted into.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub enum MessageCategory {
-    Important,
-    Sponsorship,
-    Networking,
-    GeneralInquiry,
-    Spam,
-    Urgent,
+pub struct Category {
+    /// Derived from the name when the category is created and never changes
+    pub key: MessageCategory,
+    pub name: String,
+    /// Tells the categorizer which messages belong in the category
+    pub description: String,
+    /// Categories are listed from the lowest to the highest position
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 // This special struct will be returned by our get_chat_messages function
@@ -82,11 +282,204 @@ pub struct ChatMessageWithMetadata {
     pub conversation_id: Uuid,
     pub sender_id: Uuid,
     pub content: String,
+    pub agent_authored: bool,
+    #[schema(value_type = Vec<Uuid>)]
+    pub media: Json<Vec<Uuid>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     // Plus the user-specific metadata, which can be null
     pub category: Option<MessageCategory>,
     pub reasoning: Option<String>,
+    /// Where the category came from
+    pub category_source: Option<CategorizationSource>,
+    /// The prompt template version the message was categorized with, unset if the agent didn't categorize it
+    pub prompt_template_id: Option<Uuid>,
+}
+
+/// Where the category of a message came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum CategorizationSource {
+    /// The categorizer agent
+    Agent,
+    /// One of the user's categorization rules
+    Rule,
+    /// The user set it themselves
+    Manual,
+}
+
+/// A user's correction of the category of a message.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryCorrection {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub message_id: Uuid,
+    /// The category before the correction, unset if the message wasn't categorized before
+    pub previous_category: Option<MessageCategory>,
+    pub category: MessageCategory,
+    /// The user's explanation, if they gave one
+    pub reasoning: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The content of a message a user corrected the category of, for the categorizer to learn from.
+#[derive(Clone, Debug, FromRow)]
+pub struct CorrectedMessage {
+    /// The stored message content, see [`message_text`]
+    pub content: String,
+    pub category: MessageCategory,
+    pub reasoning: Option<String>,
+}
+
+/// When a categorization rule applies to an incoming message.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum RuleCondition {
+    /// The sender is one of these users.
+    /// Pair it with `important` for an allow list of known contacts, or `spam` for a block list.
+    Sender { user_ids: Vec<Uuid> },
+    /// The message contains any of these keywords, ignoring case
+    Keywords { keywords: Vec<String> },
+    /// The message matches this regular expression
+    Regex { pattern: String },
+    /// The sender has never written to the user before, in any conversation,
+    /// and the user has never written to them
+    FirstMessageFromUnknownSender,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategorizationRule {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    #[schema(value_type = RuleCondition)]
+    pub condition: Json<RuleCondition>,
+    pub category: MessageCategory,
+    /// Stored as the reasoning of every message the rule categorizes
+    pub reasoning: String,
+    /// Rules are evaluated from the lowest to the highest priority
+    pub priority: i64,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What the DM responder does with a categorized message.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum ResponderAction {
+    /// Send a generated reply as the user straight away
+    AutoReply,
+    /// Generate a reply but let the user decide whether to send it
+    Draft,
+    /// Leave the message alone
+    #[default]
+    Ignore,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponderPolicy {
+    pub category: MessageCategory,
+    pub action: ResponderAction,
+    /// Extra guidance for generated replies, e.g. tone or things to mention
+    pub instructions: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum DraftKind {
+    Message,
+    Post,
+}
+
+/// Who wrote a draft.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum DraftSource {
+    /// A reply from the owner's DM responder
+    DmResponder,
+    /// A caption from the prompt enhancer
+    Enhancer,
+    /// A post written by one of the owner's delegates
+    Delegate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum DraftStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    pub id: Uuid,
+    /// The user the draft is sent or posted as once approved
+    pub owner_id: Uuid,
+    pub created_by: Uuid,
+    pub kind: DraftKind,
+    pub source: DraftSource,
+    /// The conversation a message draft is sent to
+    pub conversation_id: Option<Uuid>,
+    /// The message a DM responder draft answers
+    pub in_reply_to: Option<Uuid>,
+    #[schema(value_type = Vec<crate::utoipa_compat::UserContent>)]
+    pub content: Json<OneOrMany<UserContent>>,
+    pub status: DraftStatus,
+    /// When a post draft should be published, it's published right away on approval if unset
+    pub scheduled_at: Option<DateTime<Utc>>,
+    /// The message, post or scheduled post that was created when the draft was approved
+    pub result_id: Option<Uuid>,
+    /// The prompt template version an agent wrote the draft with
+    pub prompt_template_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// The agent prompts that admins can edit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum PromptTemplateName {
+    Enhancer,
+    Researcher,
+    Categorizer,
+    Summarizer,
+    Responder,
+    Captioner,
+}
+
+/// One version of an agent prompt.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptTemplate {
+    pub id: Uuid,
+    pub name: PromptTemplateName,
+    /// Counts up from 1, agents use the highest version
+    pub version: i64,
+    /// The prompt, with `{{variable}}` placeholders
+    pub body: String,
+    /// The admin who saved this version, unset for the built-in default
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
@@ -97,6 +490,101 @@ pub struct Post {
     pub created_by: Uuid,
     #[schema(value_type = Vec<crate::utoipa_compat::UserContent>)]
     pub content: Json<OneOrMany<UserContent>>,
+    /// The post this one reshares
+    pub reshare_of: Option<Uuid>,
+    /// IDs of the attached media, served from `/api/media/{id}`
+    #[schema(value_type = Vec<Uuid>)]
+    pub media: Json<Vec<Uuid>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A post along with how people interacted with it, as seen by the viewer.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedPost {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_by: Uuid,
+    #[schema(value_type = Vec<crate::utoipa_compat::UserContent>)]
+    pub content: Json<OneOrMany<UserContent>>,
+    pub reshare_of: Option<Uuid>,
+    #[schema(value_type = Vec<Uuid>)]
+    pub media: Json<Vec<Uuid>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub like_count: i64,
+    pub comment_count: i64,
+    pub reshare_count: i64,
+    /// Whether the viewer liked the post
+    pub liked: bool,
+}
+
+/// An uploaded file, served from `/api/media/{id}`.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Media {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    /// Hex encoded SHA-256 of the file
+    pub sha256: String,
+    pub mime_type: String,
+    /// Size in bytes
+    pub size: i64,
+    /// The post the media is attached to
+    pub post_id: Option<Uuid>,
+    /// The conversation of the message the media is attached to
+    pub conversation_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Comment {
+    pub id: Uuid,
+    pub post_id: Uuid,
+    pub user_id: Uuid,
+    /// The comment this one replies to, unset for top level comments
+    pub parent_id: Option<Uuid>,
+    #[schema(value_type = Vec<crate::utoipa_compat::UserContent>)]
+    pub content: Json<OneOrMany<UserContent>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum ScheduledPostStatus {
+    Pending,
+    Published,
+    /// The post couldn't be published, e.g. because the delegation was revoked
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledPost {
+    pub id: Uuid,
+    /// The user the post is published as
+    pub user_id: Uuid,
+    pub created_by: Uuid,
+    #[schema(value_type = Vec<crate::utoipa_compat::UserContent>)]
+    pub content: Json<OneOrMany<UserContent>>,
+    pub scheduled_at: DateTime<Utc>,
+    pub status: ScheduledPostStatus,
+    /// The post that was published
+    pub post_id: Option<Uuid>,
+    /// Why publishing failed, or why the last attempt did while it's retried
+    pub error: Option<String>,
+    /// IDs of the media attached to the post once it's published
+    #[schema(value_type = Vec<Uuid>)]
+    pub media: Json<Vec<Uuid>>,
+    /// How many attempts to publish the post failed
+    pub attempts: i64,
+    /// When publishing is tried again after a failed attempt
+    pub retry_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -108,20 +596,86 @@ pub struct Delegation {
     pub delegate_id: Uuid,
     pub can_post: bool,
     pub can_message: bool,
+    /// Read the owner's conversations, implied by `canMessage`
+    pub can_read_messages: bool,
     pub can_delete_posts: bool,
+    pub can_comment: bool,
+    /// The conversations reading and messaging are limited to, every conversation if unset
+    #[schema(value_type = Option<Vec<Uuid>>)]
+    pub conversation_ids: Option<Json<Vec<Uuid>>>,
+    /// The delegation stops working at this time, it never does if unset
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the delegate may act, any time if unset
+    #[schema(value_type = Option<ActiveHours>)]
+    pub active_hours: Option<Json<ActiveHours>>,
     pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Delegation {
+    /// Whether the delegate may act for the owner at `at`.
+    pub fn is_in_effect(&self, at: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| at < expires_at)
+            && self
+                .active_hours
+                .as_ref()
+                .is_none_or(|active_hours| active_hours.contains(at))
+    }
+
+    /// Whether the delegation reaches the conversation, regardless of what it allows there.
+    pub fn covers_conversation(&self, conversation_id: Uuid) -> bool {
+        self.conversation_ids
+            .as_ref()
+            .is_none_or(|conversation_ids| conversation_ids.contains(&conversation_id))
+    }
+}
+
+/// A weekly window a delegate may act in, e.g. business hours.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveHours {
+    /// Days the window opens on, e.g. `["Mon", "Tue", "Wed", "Thu", "Fri"]`
+    #[schema(value_type = Vec<String>)]
+    pub days: Vec<Weekday>,
+    /// Local time the window opens, e.g. `09:00:00`
+    #[schema(value_type = String)]
+    pub start: NaiveTime,
+    /// Local time the window closes, windows that close before they open run past midnight
+    #[schema(value_type = String)]
+    pub end: NaiveTime,
+    /// Offset of the local time from UTC in minutes, e.g. `-300` for New York in winter
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+impl ActiveHours {
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let Some(offset) = FixedOffset::east_opt(self.utc_offset_minutes * 60) else {
+            return false;
+        };
+        let local = at.with_timezone(&offset);
+        let (day, time) = (local.weekday(), local.time());
+        if self.start <= self.end {
+            self.days.contains(&day) && self.start <= time && time < self.end
+        } else {
+            // The part after midnight belongs to the day the window opened on
+            (self.days.contains(&day) && self.start <= time)
+                || (self.days.contains(&day.pred()) && time < self.end)
+        }
+    }
 }
 
 pub async fn create_user(pool: &SqlitePool, user: &CreateUser) -> Result<User> {
     let user_id = Uuid::new_v4();
+    let mut tx = pool.begin().await?;
     let Some(user) = sqlx::query_as!(
         User,
-        r#"INSERT INTO users (id, username, password) VALUES (?, ?, ?) RETURNING id AS "id: _", username, password, created_at AS "created_at: _", updated_at AS "updated_at: _""#,
+        r#"INSERT INTO users (id, username, password) VALUES (?, ?, ?) RETURNING id AS "id: _", username, password, display_name, bio, links AS "links: Json<Vec<ProfileLink>>", avatar_updated_at AS "avatar_updated_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _""#,
         user_id,
         user.username,
         user.password
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut *tx)
     .await?
     else {
         return Err(AppError::UserError((
@@ -129,13 +683,15 @@ pub async fn create_user(pool: &SqlitePool, user: &CreateUser) -> Result<User> {
             "Username already in use!".into(),
         )));
     };
+    create_default_categories(&mut tx, user.id).await?;
+    tx.commit().await?;
     Ok(user)
 }
 
 pub async fn get_user(pool: &SqlitePool, username: String) -> Result<Option<User>> {
     Ok(sqlx::query_as!(
         User,
-        r#"SELECT id AS "id: _", username, password, created_at AS "created_at: _", updated_at AS "updated_at: _" FROM users WHERE username = ?"#,
+        r#"SELECT id AS "id: _", username, password, display_name, bio, links AS "links: Json<Vec<ProfileLink>>", avatar_updated_at AS "avatar_updated_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _" FROM users WHERE username = ?"#,
         username
     )
     .fetch_optional(pool)
@@ -145,7 +701,7 @@ pub async fn get_user(pool: &SqlitePool, username: String) -> Result<Option<User
 pub async fn get_user_by_id(pool: &SqlitePool, id: Uuid) -> Result<User> {
     let Some(user) = sqlx::query_as!(
         User,
-        r#"SELECT id AS "id: _", username, password, created_at AS "created_at: _", updated_at AS "updated_at: _" FROM users WHERE id = ?"#,
+        r#"SELECT id AS "id: _", username, password, display_name, bio, links AS "links: Json<Vec<ProfileLink>>", avatar_updated_at AS "avatar_updated_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _" FROM users WHERE id = ?"#,
         id
     )
     .fetch_optional(pool)
@@ -159,6 +715,65 @@ pub async fn get_user_by_id(pool: &SqlitePool, id: Uuid) -> Result<User> {
     Ok(user)
 }
 
+/// Replace the editable profile fields of a user.
+pub async fn update_user_profile(
+    pool: &SqlitePool,
+    id: Uuid,
+    username: &str,
+    display_name: Option<&str>,
+    bio: Option<&str>,
+    links: &[ProfileLink],
+) -> Result<User> {
+    let links = Json(links);
+    let result = sqlx::query_as!(
+        User,
+        r#"
+        UPDATE users
+        SET username = ?, display_name = ?, bio = ?, links = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE id = ?
+        RETURNING id AS "id: _", username, password, display_name, bio, links AS "links: Json<Vec<ProfileLink>>", avatar_updated_at AS "avatar_updated_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        "#,
+        username,
+        display_name,
+        bio,
+        links,
+        id
+    )
+    .fetch_one(pool)
+    .await;
+
+    match result {
+        Ok(user) => Ok(user),
+        // Usernames are unique regardless of case
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(AppError::UserError((
+            LossyError(StatusCode::CONFLICT),
+            "Username already in use!".into(),
+        ))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn set_user_avatar_updated(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET avatar_updated_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn update_user_password(pool: &SqlitePool, id: Uuid, password: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET password = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        password,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 pub async fn delete_user(pool: &SqlitePool, id: Uuid) -> Result<()> {
     let result = sqlx::query!("DELETE FROM users WHERE id = ?", id)
         .execute(pool)
@@ -174,18 +789,28 @@ pub async fn delete_user(pool: &SqlitePool, id: Uuid) -> Result<()> {
     Ok(())
 }
 
-pub async fn create_session(pool: &SqlitePool, user_id: Uuid) -> Result<Session> {
-    let expires = Utc::now() + Duration::days(7);
+pub async fn create_session(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    user_agent: Option<&str>,
+    ip_address: Option<String>,
+) -> Result<Session> {
+    let expires = Utc::now() + *SESSION_TTL;
     let session_id = Uuid::new_v4();
+    let public_id = Uuid::new_v4();
     let session = sqlx::query_as!(
         Session,
         r#"
-        INSERT INTO sessions (id, user_id, expires) VALUES (?, ?, ?)
-        RETURNING id AS "id: _", user_id AS "user_id: _", expires AS "expires: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        INSERT INTO sessions (id, public_id, user_id, expires, last_seen_at, user_agent, ip_address)
+        VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP, ?, ?)
+        RETURNING id AS "id: _", public_id AS "public_id!: _", user_id AS "user_id: _", expires AS "expires: _", last_seen_at AS "last_seen_at: _", user_agent, ip_address, created_at AS "created_at: _", updated_at AS "updated_at: _"
         "#,
         session_id,
+        public_id,
         user_id,
-        expires
+        expires,
+        user_agent,
+        ip_address
     )
     .fetch_one(pool)
     .await?;
@@ -196,7 +821,7 @@ pub async fn get_session(pool: &SqlitePool, id: Uuid) -> Result<Option<Session>>
     Ok(sqlx::query_as!(
         Session,
         r#"
-        SELECT id AS "id: _", user_id AS "user_id: _", expires AS "expires: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        SELECT id AS "id: _", public_id AS "public_id!: _", user_id AS "user_id: _", expires AS "expires: _", last_seen_at AS "last_seen_at: _", user_agent, ip_address, created_at AS "created_at: _", updated_at AS "updated_at: _"
         FROM sessions 
         WHERE id = ? AND DATETIME(expires) > CURRENT_TIMESTAMP
         "#,
@@ -206,91 +831,297 @@ pub async fn get_session(pool: &SqlitePool, id: Uuid) -> Result<Option<Session>>
     .await?)
 }
 
-pub async fn delete_session(pool: &SqlitePool, id: Uuid) -> Result<()> {
-    sqlx::query!("DELETE FROM sessions WHERE id = ?", id)
-        .execute(pool)
-        .await?;
-    Ok(())
-}
-
-pub async fn create_conversation(pool: &SqlitePool, user_ids: &[Uuid]) -> Result<Conversation> {
-    let mut tx = pool.begin().await?;
-    let conv_id = Uuid::new_v4();
-    let conv = sqlx::query_as!(
-        Conversation,
-        r#"INSERT INTO conversations (id) VALUES (?) RETURNING id AS "id: _", title, last_message_id AS "last_message_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _""#,
-        conv_id
-    )
-    .fetch_one(&mut *tx)
-    .await?;
-
-    for user_id in user_ids {
-        sqlx::query!(
-            "INSERT INTO conversation_participants (conversation_id, user_id) VALUES (?, ?)",
-            conv.id,
-            user_id
-        )
-        .execute(&mut *tx)
-        .await?;
-    }
-
-    tx.commit().await?;
-    Ok(conv)
-}
-
-pub async fn get_user_conversations(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<Conversation>> {
-    let convos = sqlx::query_as!(
-        Conversation,
+pub async fn get_user_sessions(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<Session>> {
+    Ok(sqlx::query_as!(
+        Session,
         r#"
-        SELECT c.id AS "id: _", c.title, c.last_message_id AS "last_message_id: _", c.created_at AS "created_at: _", c.updated_at AS "updated_at: _"
-        FROM conversations c
-        JOIN conversation_participants cp ON c.id = cp.conversation_id
-        WHERE cp.user_id = ?
-        ORDER BY c.updated_at DESC
+        SELECT id AS "id: _", public_id AS "public_id!: _", user_id AS "user_id: _", expires AS "expires: _", last_seen_at AS "last_seen_at: _", user_agent, ip_address, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM sessions
+        WHERE user_id = ? AND DATETIME(expires) > CURRENT_TIMESTAMP
+        ORDER BY DATETIME(COALESCE(last_seen_at, created_at)) DESC
         "#,
         user_id
     )
     .fetch_all(pool)
-    .await?;
-    Ok(convos)
+    .await?)
 }
 
-pub async fn get_conversation_participants(
+/// Push a session's expiry out to `expires`.
+/// Returns `None` if the session no longer exists, e.g. because it was just logged out.
+pub async fn renew_session(
     pool: &SqlitePool,
-    conversation_id: Uuid,
-) -> Result<Vec<User>> {
-    let users = sqlx::query_as!(
-        User,
+    id: Uuid,
+    expires: DateTime<Utc>,
+) -> Result<Option<Session>> {
+    let session = sqlx::query_as!(
+        Session,
         r#"
-        SELECT u.id AS "id: _", u.username, u.password, u.created_at AS "created_at: _", u.updated_at AS "updated_at: _"
-        FROM users u
-        JOIN conversation_participants cp ON u.id = cp.user_id
-        WHERE cp.conversation_id = ?
+        UPDATE sessions SET expires = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE id = ?
+        RETURNING id AS "id: _", public_id AS "public_id!: _", user_id AS "user_id: _", expires AS "expires: _", last_seen_at AS "last_seen_at: _", user_agent, ip_address, created_at AS "created_at: _", updated_at AS "updated_at: _"
         "#,
-        conversation_id
+        expires,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(session)
+}
+
+/// Record that a session was used. Only writes once a minute per session
+/// so that busy clients don't turn every request into a write.
+pub async fn touch_session(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE sessions SET last_seen_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND (last_seen_at IS NULL OR DATETIME(last_seen_at) < DATETIME('now', '-1 minute'))
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_session(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    sqlx::query!("DELETE FROM sessions WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Delete one of a user's sessions by its public ID, failing if it doesn't belong to them.
+pub async fn delete_user_session(pool: &SqlitePool, user_id: Uuid, public_id: Uuid) -> Result<()> {
+    let result = sqlx::query!(
+        "DELETE FROM sessions WHERE public_id = ? AND user_id = ?",
+        public_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Session not found!".into(),
+        )));
+    }
+    Ok(())
+}
+
+/// Remove every session that has expired, returning how many were removed.
+pub async fn delete_expired_sessions(pool: &SqlitePool) -> Result<u64> {
+    let result = sqlx::query!("DELETE FROM sessions WHERE DATETIME(expires) <= CURRENT_TIMESTAMP")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ====== API Token Functions ======
+
+pub async fn create_api_token(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    name: &str,
+    token_hash: &str,
+    scopes: &[ApiScope],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<ApiToken> {
+    let token_id = Uuid::new_v4();
+    let scopes = Json(scopes);
+    let token = sqlx::query_as!(
+        ApiToken,
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, expires_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", user_id AS "user_id: _", name, scopes AS "scopes: Json<Vec<ApiScope>>", expires_at AS "expires_at: _", last_used_at AS "last_used_at: _", created_at AS "created_at: _"
+        "#,
+        token_id,
+        user_id,
+        name,
+        token_hash,
+        scopes,
+        expires_at
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(token)
+}
+
+/// Look up an unexpired token by the hash of its secret.
+pub async fn get_api_token_by_hash(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<ApiToken>> {
+    Ok(sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT id AS "id: _", user_id AS "user_id: _", name, scopes AS "scopes: Json<Vec<ApiScope>>", expires_at AS "expires_at: _", last_used_at AS "last_used_at: _", created_at AS "created_at: _"
+        FROM api_tokens
+        WHERE token_hash = ? AND (expires_at IS NULL OR DATETIME(expires_at) > CURRENT_TIMESTAMP)
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn get_user_api_tokens(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<ApiToken>> {
+    Ok(sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT id AS "id: _", user_id AS "user_id: _", name, scopes AS "scopes: Json<Vec<ApiScope>>", expires_at AS "expires_at: _", last_used_at AS "last_used_at: _", created_at AS "created_at: _"
+        FROM api_tokens
+        WHERE user_id = ?
+        ORDER BY DATETIME(created_at) DESC
+        "#,
+        user_id
     )
     .fetch_all(pool)
+    .await?)
+}
+
+/// Record that a token was used. Like `touch_session`, this only writes once a minute.
+pub async fn touch_api_token(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE api_tokens SET last_used_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND (last_used_at IS NULL OR DATETIME(last_used_at) < DATETIME('now', '-1 minute'))
+        "#,
+        id
+    )
+    .execute(pool)
     .await?;
-    Ok(users)
+    Ok(())
+}
+
+pub async fn delete_api_token(pool: &SqlitePool, user_id: Uuid, id: Uuid) -> Result<()> {
+    let result = sqlx::query!(
+        "DELETE FROM api_tokens WHERE id = ? AND user_id = ?",
+        id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "API token not found!".into(),
+        )));
+    }
+    Ok(())
+}
+
+pub async fn create_conversation(pool: &SqlitePool, user_ids: &[Uuid]) -> Result<Conversation> {
+    let mut tx = pool.begin().await?;
+    let conv_id = Uuid::new_v4();
+    let conv = sqlx::query_as!(
+        Conversation,
+        r#"INSERT INTO conversations (id) VALUES (?) RETURNING id AS "id: _", title, last_message_id AS "last_message_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _""#,
+        conv_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    for user_id in user_ids {
+        sqlx::query!(
+            "INSERT INTO conversation_participants (conversation_id, user_id) VALUES (?, ?)",
+            conv.id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(conv)
+}
+
+/// The conversations of `user_id`, limited to `only` if it's set.
+pub async fn get_user_conversations(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    only: Option<&[Uuid]>,
+    page: &PageQuery,
+) -> Result<Page<Conversation>> {
+    let bounds = page.bounds();
+    let only = only.map(Json);
+    let convos = sqlx::query_as!(
+        Conversation,
+        r#"
+        SELECT c.id AS "id: _", c.title, c.last_message_id AS "last_message_id: _", c.created_at AS "created_at: _", c.updated_at AS "updated_at: _"
+        FROM conversations c
+        JOIN conversation_participants cp ON c.id = cp.conversation_id
+        WHERE cp.user_id = ?1
+        AND (?2 IS NULL OR (DATETIME(c.updated_at), c.id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(c.updated_at), c.id) > (DATETIME(?4), ?5))
+        -- `only` is a JSON array of hyphenated UUIDs while IDs are stored as blobs
+        AND (?8 IS NULL OR LOWER(HEX(c.id)) IN (SELECT REPLACE(LOWER(value), '-', '') FROM json_each(?8)))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(c.updated_at) END ASC,
+            CASE WHEN ?6 THEN c.id END ASC,
+            DATETIME(c.updated_at) DESC,
+            c.id DESC
+        LIMIT ?7
+        "#,
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch,
+        only
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::new(convos, page))
 }
 
-pub async fn create_chat_message(
+pub async fn get_conversation_participants(
     pool: &SqlitePool,
     conversation_id: Uuid,
+) -> Result<Vec<User>> {
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT u.id AS "id: _", u.username, u.password, u.display_name, u.bio, u.links AS "links: Json<Vec<ProfileLink>>", u.avatar_updated_at AS "avatar_updated_at: _", u.created_at AS "created_at: _", u.updated_at AS "updated_at: _"
+        FROM users u
+        JOIN conversation_participants cp ON u.id = cp.user_id
+        WHERE cp.conversation_id = ?
+        "#,
+        conversation_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(users)
+}
+
+/// Send a message as `sender_id`, attaching `media` the sender uploaded.
+pub async fn create_chat_message<'c>(
+    conn: impl Acquire<'c, Database = Sqlite>,
+    conversation_id: Uuid,
     sender_id: Uuid,
     content: OneOrMany<UserContent>,
+    agent_authored: bool,
+    media: &[Uuid],
 ) -> Result<ChatMessage> {
-    let mut tx = pool.begin().await?;
+    let mut tx = conn.begin().await?;
+    attach_media(&mut tx, sender_id, media, None, Some(conversation_id)).await?;
     let msg_id = Uuid::new_v4();
     let msg_content = Json(content);
+    let msg_media = Json(media);
     let msg = sqlx::query_as!(
         ChatMessage,
-        r#"INSERT INTO messages (id, conversation_id, sender_id, content) VALUES (?, ?, ?, ?) 
-        RETURNING id AS "id: _", conversation_id AS "conversation_id: _", sender_id AS "sender_id: _", content, created_at AS "created_at: _", updated_at AS "updated_at: _""#,
+        r#"INSERT INTO messages (id, conversation_id, sender_id, content, agent_authored, media) VALUES (?, ?, ?, ?, ?, ?) 
+        RETURNING id AS "id: _", conversation_id AS "conversation_id: _", sender_id AS "sender_id: _", content, agent_authored, media AS "media: Json<Vec<Uuid>>", created_at AS "created_at: _", updated_at AS "updated_at: _""#,
         msg_id,
         conversation_id,
         sender_id,
-        msg_content
+        msg_content,
+        agent_authored,
+        msg_media
     )
     .fetch_one(&mut *tx)
     .await?;
@@ -311,7 +1142,9 @@ pub async fn get_chat_messages(
     pool: &SqlitePool,
     conversation_id: Uuid,
     user_id: Uuid,
-) -> Result<Vec<ChatMessageWithMetadata>> {
+    page: &PageQuery,
+) -> Result<Page<ChatMessageWithMetadata>> {
+    let bounds = page.bounds();
     let messages = sqlx::query_as!(
         ChatMessageWithMetadata,
         r#"
@@ -320,27 +1153,46 @@ pub async fn get_chat_messages(
             m.conversation_id AS "conversation_id: _", 
             m.sender_id AS "sender_id: _", 
             m.content, 
+            m.agent_authored,
+            m.media AS "media: Json<Vec<Uuid>>",
             m.created_at AS "created_at: _", 
             m.updated_at AS "updated_at: _",
             meta.category AS "category: _",
-            meta.reasoning AS "reasoning: _"
+            meta.reasoning AS "reasoning: _",
+            meta.source AS "category_source: _",
+            meta.prompt_template_id AS "prompt_template_id: _"
         FROM messages m
-        LEFT JOIN user_message_metadata meta ON m.id = meta.message_id AND meta.user_id = ?
-        WHERE m.conversation_id = ?
-        ORDER BY DATETIME(m.created_at) DESC
+        LEFT JOIN user_message_metadata meta ON m.id = meta.message_id AND meta.user_id = ?1
+        WHERE m.conversation_id = ?2
+        AND (?3 IS NULL OR (DATETIME(m.created_at), m.id) < (DATETIME(?3), ?4))
+        AND (?5 IS NULL OR (DATETIME(m.created_at), m.id) > (DATETIME(?5), ?6))
+        ORDER BY
+            CASE WHEN ?7 THEN DATETIME(m.created_at) END ASC,
+            CASE WHEN ?7 THEN m.id END ASC,
+            DATETIME(m.created_at) DESC,
+            m.id DESC
+        LIMIT ?8
         "#,
         user_id,
-        conversation_id
+        conversation_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
     )
     .fetch_all(pool)
     .await?;
-    Ok(messages)
+    Ok(Page::new(messages, page))
 }
 
 pub async fn get_conversation_messages(
     pool: &SqlitePool,
     conversation_id: Uuid,
-) -> Result<Vec<ChatMessage>> {
+    page: &PageQuery,
+) -> Result<Page<ChatMessage>> {
+    let bounds = page.bounds();
     let messages = sqlx::query_as!(
         ChatMessage,
         r#"
@@ -349,67 +1201,223 @@ pub async fn get_conversation_messages(
             conversation_id AS "conversation_id: _", 
             sender_id AS "sender_id: _", 
             content, 
+            agent_authored,
+            media AS "media: Json<Vec<Uuid>>",
             created_at AS "created_at: _", 
             updated_at AS "updated_at: _"
         FROM messages
-        WHERE conversation_id = ?
-        ORDER BY DATETIME(created_at) DESC
+        WHERE conversation_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN id END ASC,
+            DATETIME(created_at) DESC,
+            id DESC
+        LIMIT ?7
         "#,
-        conversation_id
+        conversation_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
     )
     .fetch_all(pool)
     .await?;
-    Ok(messages)
+    Ok(Page::new(messages, page))
 }
 
-pub async fn get_conversation_messages_chronological(
+/// The latest `limit` messages of a conversation, oldest first.
+pub async fn get_latest_conversation_messages(
     pool: &SqlitePool,
     conversation_id: Uuid,
+    limit: i64,
 ) -> Result<Vec<ChatMessage>> {
-    let messages = sqlx::query_as!(
+    let mut messages = sqlx::query_as!(
         ChatMessage,
         r#"
-        SELECT 
-            id AS "id: _", 
-            conversation_id AS "conversation_id: _", 
-            sender_id AS "sender_id: _", 
-            content, 
-            created_at AS "created_at: _", 
+        SELECT
+            id AS "id: _",
+            conversation_id AS "conversation_id: _",
+            sender_id AS "sender_id: _",
+            content,
+            agent_authored,
+            media AS "media: Json<Vec<Uuid>>",
+            created_at AS "created_at: _",
             updated_at AS "updated_at: _"
         FROM messages
         WHERE conversation_id = ?
-        ORDER BY DATETIME(created_at) ASC
+        ORDER BY DATETIME(created_at) DESC, id DESC
+        LIMIT ?
         "#,
-        conversation_id
+        conversation_id,
+        limit
     )
     .fetch_all(pool)
     .await?;
+    messages.reverse();
     Ok(messages)
 }
 
+/// Store the category the categorizer or a rule picked for a message.
+/// A category the user set themselves always wins, even over a categorizer that was
+/// still running when they set it. Returns `false` if the message kept the user's category.
 pub async fn categorize_message(
-    pool: &SqlitePool,
+    executor: impl SqliteExecutor<'_>,
     user_id: Uuid,
     message_id: Uuid,
     category: MessageCategory,
     reasoning: String,
-) -> Result<()> {
+    source: CategorizationSource,
+    prompt_template_id: Option<Uuid>,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO user_message_metadata (user_id, message_id, category, reasoning, source, prompt_template_id)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT(user_id, message_id) DO UPDATE SET
+            category = excluded.category,
+            reasoning = excluded.reasoning,
+            source = excluded.source,
+            prompt_template_id = excluded.prompt_template_id
+        WHERE user_message_metadata.source != 'manual';
+        "#,
+        user_id,
+        message_id,
+        category,
+        reasoning,
+        source,
+        prompt_template_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The reasoning shown for a category the user set without explaining why.
+pub const MANUAL_CATEGORY_REASONING: &str = "You set this category yourself.";
+
+/// Override the category of a message for `user_id` and keep the correction in their history.
+pub async fn correct_message_category(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+    category: MessageCategory,
+    reasoning: Option<&str>,
+) -> Result<CategoryCorrection> {
+    let mut tx = pool.begin().await?;
+    let previous_category = sqlx::query_scalar!(
+        r#"SELECT category AS "category: MessageCategory" FROM user_message_metadata WHERE user_id = ? AND message_id = ?"#,
+        user_id,
+        message_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let shown_reasoning = reasoning.unwrap_or(MANUAL_CATEGORY_REASONING);
     sqlx::query!(
         r#"
-        INSERT INTO user_message_metadata (user_id, message_id, category, reasoning)
-        VALUES (?, ?, ?, ?)
+        INSERT INTO user_message_metadata (user_id, message_id, category, reasoning, source, prompt_template_id)
+        VALUES (?, ?, ?, ?, 'manual', NULL)
         ON CONFLICT(user_id, message_id) DO UPDATE SET
             category = excluded.category,
-            reasoning = excluded.reasoning;
+            reasoning = excluded.reasoning,
+            source = excluded.source,
+            prompt_template_id = excluded.prompt_template_id;
+        "#,
+        user_id,
+        message_id,
+        category,
+        shown_reasoning
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let id = Uuid::new_v4();
+    let correction = sqlx::query_as!(
+        CategoryCorrection,
+        r#"
+        INSERT INTO category_corrections (id, user_id, message_id, previous_category, category, reasoning)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", user_id AS "user_id: _", message_id AS "message_id: _", previous_category AS "previous_category: _", category AS "category: _", reasoning, created_at AS "created_at: _"
         "#,
+        id,
         user_id,
         message_id,
+        previous_category,
         category,
         reasoning
     )
-    .execute(pool)
+    .fetch_one(&mut *tx)
     .await?;
-    Ok(())
+    tx.commit().await?;
+    Ok(correction)
+}
+
+/// A user's corrections of the category of a message, newest first.
+pub async fn get_category_corrections(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+) -> Result<Vec<CategoryCorrection>> {
+    Ok(sqlx::query_as!(
+        CategoryCorrection,
+        r#"
+        SELECT id AS "id: _", user_id AS "user_id: _", message_id AS "message_id: _", previous_category AS "previous_category: _", category AS "category: _", reasoning, created_at AS "created_at: _"
+        FROM category_corrections
+        WHERE user_id = ? AND message_id = ?
+        ORDER BY rowid DESC
+        "#,
+        user_id,
+        message_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The messages a user most recently corrected the category of, newest first.
+/// Only the latest correction of each message counts, and only if its category still exists.
+pub async fn get_recent_corrected_messages(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    limit: i64,
+) -> Result<Vec<CorrectedMessage>> {
+    Ok(sqlx::query_as!(
+        CorrectedMessage,
+        r#"
+        SELECT m.content, c.category AS "category: MessageCategory", c.reasoning
+        FROM category_corrections c
+        JOIN messages m ON m.id = c.message_id
+        JOIN categories cat ON cat.user_id = c.user_id AND cat.key = c.category
+        WHERE c.user_id = ?1
+        AND NOT EXISTS (
+            SELECT 1 FROM category_corrections newer
+            WHERE newer.user_id = c.user_id AND newer.message_id = c.message_id AND newer.rowid > c.rowid
+        )
+        ORDER BY c.rowid DESC
+        LIMIT ?2
+        "#,
+        user_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn get_chat_message(pool: &SqlitePool, id: Uuid) -> Result<Option<ChatMessage>> {
+    Ok(sqlx::query_as!(
+        ChatMessage,
+        r#"
+        SELECT id AS "id: _", conversation_id AS "conversation_id: _", sender_id AS "sender_id: _", content, agent_authored, media AS "media: Json<Vec<Uuid>>", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM messages
+        WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
 }
 
 pub async fn is_user_in_conversation(
@@ -498,38 +1506,53 @@ pub async fn add_users_to_conversation(
 
 // ====== Posts Functions ======
 
-pub async fn create_post(
-    pool: &SqlitePool,
+/// Create a post, attaching `media` uploaded by `created_by`.
+/// Takes a pool or a connection, so the post can be created as part of a larger transaction.
+pub async fn create_post<'c>(
+    conn: impl Acquire<'c, Database = Sqlite>,
     user_id: Uuid,
     created_by: Uuid,
     content: OneOrMany<UserContent>,
+    media: &[Uuid],
 ) -> Result<Post> {
+    let mut tx = conn.begin().await?;
     let post_id = Uuid::new_v4();
     let content_json = Json(content);
+    let media_json = Json(media);
     let post = sqlx::query_as!(
         Post,
         r#"
-        INSERT INTO posts (id, user_id, created_by, content)
-        VALUES (?, ?, ?, ?)
+        INSERT INTO posts (id, user_id, created_by, content, media)
+        VALUES (?, ?, ?, ?, ?)
         RETURNING 
             id AS "id: _",
             user_id AS "user_id: _",
             created_by AS "created_by: _",
             content AS "content: Json<OneOrMany<UserContent>>",
+            reshare_of AS "reshare_of: _",
+            media AS "media: Json<Vec<Uuid>>",
             created_at AS "created_at: _",
             updated_at AS "updated_at: _"
         "#,
         post_id,
         user_id,
         created_by,
-        content_json
+        content_json,
+        media_json
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
+    attach_media(&mut tx, created_by, media, Some(post.id), None).await?;
+    tx.commit().await?;
     Ok(post)
 }
 
-pub async fn get_user_posts(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<Post>> {
+pub async fn get_user_posts(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<Post>> {
+    let bounds = page.bounds();
     let posts = sqlx::query_as!(
         Post,
         r#"
@@ -538,310 +1561,2540 @@ pub async fn get_user_posts(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<Post
             user_id AS "user_id: _",
             created_by AS "created_by: _",
             content AS "content: Json<OneOrMany<UserContent>>",
+            reshare_of AS "reshare_of: _",
+            media AS "media: Json<Vec<Uuid>>",
             created_at AS "created_at: _",
             updated_at AS "updated_at: _"
         FROM posts
-        WHERE user_id = ?
-        ORDER BY DATETIME(created_at) DESC
+        WHERE user_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN id END ASC,
+            DATETIME(created_at) DESC,
+            id DESC
+        LIMIT ?7
         "#,
-        user_id
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
     )
     .fetch_all(pool)
     .await?;
-    Ok(posts)
+    Ok(Page::new(posts, page))
 }
 
-pub async fn delete_post(pool: &SqlitePool, post_id: Uuid, user_id: Uuid) -> Result<()> {
+/// Delete a post its owner, its creator or a delegate allowed to delete the owner's posts asked to delete.
+/// Returns the owner of the deleted post.
+pub async fn delete_post<'c>(
+    conn: impl Acquire<'c, Database = Sqlite>,
+    post_id: Uuid,
+    user_id: Uuid,
+) -> Result<Uuid> {
+    let mut conn = conn.acquire().await?;
     // First check if the user is the owner or creator of the post
-    let result = sqlx::query!(
-        "DELETE FROM posts WHERE id = ? AND (user_id = ? OR created_by = ?)",
+    let owner_id = sqlx::query_scalar!(
+        r#"DELETE FROM posts WHERE id = ? AND (user_id = ? OR created_by = ?) RETURNING user_id AS "user_id: Uuid""#,
         post_id,
         user_id,
         user_id
     )
-    .execute(pool)
+    .fetch_optional(&mut *conn)
     .await?;
 
-    if result.rows_affected() == 0 {
-        // Check if the user has delegation permission to delete posts for the owner
-        let post = sqlx::query!(
-            "SELECT user_id AS \"user_id: Uuid\" FROM posts WHERE id = ?",
-            post_id
-        )
-        .fetch_optional(pool)
-        .await?;
+    if let Some(owner_id) = owner_id {
+        return Ok(owner_id);
+    }
 
-        if let Some(post) = post {
-            // Check if this user has delegation to delete posts for the post owner
-            if let Some(delegation) = check_delegation(pool, post.user_id, user_id).await? {
-                if delegation.can_delete_posts {
-                    // User has delegation to delete posts, so delete the post
-                    let result = sqlx::query!(
-                        "DELETE FROM posts WHERE id = ? AND user_id = ?",
-                        post_id,
-                        post.user_id
-                    )
-                    .execute(pool)
-                    .await?;
+    // Check if the user has delegation permission to delete posts for the owner
+    let post = sqlx::query!(
+        "SELECT user_id AS \"user_id: Uuid\" FROM posts WHERE id = ?",
+        post_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
 
-                    if result.rows_affected() == 0 {
-                        return Err(AppError::AuthError("Post not found".into()));
-                    }
-                    return Ok(());
+    if let Some(post) = post {
+        // Check if this user has delegation to delete posts for the post owner
+        if let Some(delegation) = check_delegation(&mut *conn, post.user_id, user_id).await? {
+            if delegation.can_delete_posts {
+                // User has delegation to delete posts, so delete the post
+                let result = sqlx::query!(
+                    "DELETE FROM posts WHERE id = ? AND user_id = ?",
+                    post_id,
+                    post.user_id
+                )
+                .execute(&mut *conn)
+                .await?;
+
+                if result.rows_affected() == 0 {
+                    return Err(AppError::AuthError("Post not found".into()));
                 }
+                return Ok(post.user_id);
             }
         }
-        return Err(AppError::AuthError("Post not found or unauthorized".into()));
     }
-    Ok(())
+    Err(AppError::AuthError("Post not found or unauthorized".into()))
 }
 
-// ====== Delegation Functions ======
+// ====== Post Interaction Functions ======
 
-pub async fn create_delegation(
-    pool: &SqlitePool,
-    owner_id: Uuid,
-    delegate_id: Uuid,
-    can_post: bool,
-    can_message: bool,
-    can_delete_posts: bool,
-) -> Result<Delegation> {
-    let delegation = sqlx::query_as!(
-        Delegation,
+pub async fn get_post(pool: &SqlitePool, id: Uuid) -> Result<Post> {
+    let Some(post) = sqlx::query_as!(
+        Post,
         r#"
-        INSERT INTO delegations (owner_id, delegate_id, can_post, can_message, can_delete_posts)
-        VALUES (?, ?, ?, ?, ?)
-        RETURNING 
-            owner_id AS "owner_id: _",
-            delegate_id AS "delegate_id: _",
-            can_post,
-            can_message,
-            can_delete_posts,
-            created_at AS "created_at: _"
+        SELECT 
+            id AS "id: _",
+            user_id AS "user_id: _",
+            created_by AS "created_by: _",
+            content AS "content: Json<OneOrMany<UserContent>>",
+            reshare_of AS "reshare_of: _",
+            media AS "media: Json<Vec<Uuid>>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM posts
+        WHERE id = ?
         "#,
-        owner_id,
-        delegate_id,
-        can_post,
-        can_message,
-        can_delete_posts
+        id
     )
-    .fetch_one(pool)
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Post not found!".into(),
+        )));
+    };
+    Ok(post)
+}
+
+/// Like a post, returns `false` if the user already liked it.
+pub async fn like_post(pool: &SqlitePool, post_id: Uuid, user_id: Uuid) -> Result<bool> {
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO post_likes (post_id, user_id) VALUES (?, ?)",
+        post_id,
+        user_id
+    )
+    .execute(pool)
     .await?;
-    Ok(delegation)
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn get_user_delegations(pool: &SqlitePool, owner_id: Uuid) -> Result<Vec<Delegation>> {
-    let delegations = sqlx::query_as!(
-        Delegation,
+pub async fn unlike_post(pool: &SqlitePool, post_id: Uuid, user_id: Uuid) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM post_likes WHERE post_id = ? AND user_id = ?",
+        post_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn create_comment<'c>(
+    conn: impl Acquire<'c, Database = Sqlite>,
+    post_id: Uuid,
+    user_id: Uuid,
+    parent_id: Option<Uuid>,
+    content: OneOrMany<UserContent>,
+) -> Result<Comment> {
+    let mut conn = conn.acquire().await?;
+    if let Some(parent_id) = parent_id {
+        let parent_post_id = sqlx::query_scalar!(
+            r#"SELECT post_id AS "post_id: Uuid" FROM post_comments WHERE id = ?"#,
+            parent_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if parent_post_id != Some(post_id) {
+            return Err(AppError::UserError((
+                LossyError(StatusCode::BAD_REQUEST),
+                "The comment being replied to isn't on this post".into(),
+            )));
+        }
+    }
+
+    let comment_id = Uuid::new_v4();
+    let content = Json(content);
+    let comment = sqlx::query_as!(
+        Comment,
         r#"
-        SELECT 
-            owner_id AS "owner_id: _",
-            delegate_id AS "delegate_id: _",
-            can_post,
-            can_message,
-            can_delete_posts,
-            created_at AS "created_at: _"
-        FROM delegations
-        WHERE owner_id = ?
+        INSERT INTO post_comments (id, post_id, user_id, parent_id, content)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", post_id AS "post_id: _", user_id AS "user_id: _", parent_id AS "parent_id: _", content AS "content: Json<OneOrMany<UserContent>>", created_at AS "created_at: _", updated_at AS "updated_at: _"
         "#,
-        owner_id
+        comment_id,
+        post_id,
+        user_id,
+        parent_id,
+        content
     )
-    .fetch_all(pool)
+    .fetch_one(&mut *conn)
     .await?;
-    Ok(delegations)
+    Ok(comment)
 }
 
-pub async fn get_delegated_to_user(
+/// Get the comments on a post, newest first. Threads are rebuilt from `parent_id`.
+pub async fn get_post_comments(
     pool: &SqlitePool,
-    delegate_id: Uuid,
-) -> Result<Vec<Delegation>> {
-    let delegations = sqlx::query_as!(
-        Delegation,
+    post_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<Comment>> {
+    let bounds = page.bounds();
+    let comments = sqlx::query_as!(
+        Comment,
         r#"
-        SELECT 
-            owner_id AS "owner_id: _",
-            delegate_id AS "delegate_id: _",
-            can_post,
-            can_message,
-            can_delete_posts,
-            created_at AS "created_at: _"
-        FROM delegations
-        WHERE delegate_id = ?
+        SELECT id AS "id: _", post_id AS "post_id: _", user_id AS "user_id: _", parent_id AS "parent_id: _", content AS "content: Json<OneOrMany<UserContent>>", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM post_comments
+        WHERE post_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN id END ASC,
+            DATETIME(created_at) DESC,
+            id DESC
+        LIMIT ?7
         "#,
-        delegate_id
+        post_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
     )
     .fetch_all(pool)
     .await?;
-    Ok(delegations)
+    Ok(Page::new(comments, page))
 }
 
-pub async fn check_delegation(
+/// Reshare a post as `user_id`. Reshares of reshares point at the original post.
+pub async fn create_reshare(
     pool: &SqlitePool,
-    owner_id: Uuid,
-    delegate_id: Uuid,
-) -> Result<Option<Delegation>> {
-    let delegation = sqlx::query_as!(
-        Delegation,
+    user_id: Uuid,
+    original: &Post,
+    content: OneOrMany<UserContent>,
+) -> Result<Post> {
+    let post_id = Uuid::new_v4();
+    let reshare_of = original.reshare_of.unwrap_or(original.id);
+    let content_json = Json(content);
+    let post = sqlx::query_as!(
+        Post,
         r#"
-        SELECT 
-            owner_id AS "owner_id: _",
-            delegate_id AS "delegate_id: _",
-            can_post,
-            can_message,
-            can_delete_posts,
-            created_at AS "created_at: _"
-        FROM delegations
-        WHERE owner_id = ? AND delegate_id = ?
+        INSERT INTO posts (id, user_id, created_by, content, reshare_of)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING 
+            id AS "id: _",
+            user_id AS "user_id: _",
+            created_by AS "created_by: _",
+            content AS "content: Json<OneOrMany<UserContent>>",
+            reshare_of AS "reshare_of: _",
+            media AS "media: Json<Vec<Uuid>>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
         "#,
-        owner_id,
-        delegate_id
+        post_id,
+        user_id,
+        user_id,
+        content_json,
+        reshare_of
     )
-    .fetch_optional(pool)
+    .fetch_one(pool)
     .await?;
-    Ok(delegation)
+    Ok(post)
 }
 
-pub async fn delete_delegation(pool: &SqlitePool, owner_id: Uuid, delegate_id: Uuid) -> Result<()> {
-    sqlx::query!(
-        "DELETE FROM delegations WHERE owner_id = ? AND delegate_id = ?",
-        owner_id,
-        delegate_id
+// ====== Scheduled Post Functions ======
+
+pub async fn create_scheduled_post(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    created_by: Uuid,
+    content: OneOrMany<UserContent>,
+    media: &[Uuid],
+    scheduled_at: DateTime<Utc>,
+) -> Result<ScheduledPost> {
+    let scheduled_post_id = Uuid::new_v4();
+    let content = Json(content);
+    let media = Json(media);
+    Ok(sqlx::query_as!(
+        ScheduledPost,
+        r#"
+        INSERT INTO scheduled_posts (id, user_id, created_by, content, media, scheduled_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", user_id AS "user_id: _", created_by AS "created_by: _", content AS "content: Json<OneOrMany<UserContent>>", scheduled_at AS "scheduled_at: _", status AS "status: _", post_id AS "post_id: _", error, media AS "media: Json<Vec<Uuid>>", attempts, retry_at AS "retry_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        "#,
+        scheduled_post_id,
+        user_id,
+        created_by,
+        content,
+        media,
+        scheduled_at
     )
-    .execute(pool)
-    .await?;
-    Ok(())
+    .fetch_one(pool)
+    .await?)
 }
 
-// ====== User Search Functions ======
-
-pub async fn search_users(pool: &SqlitePool, query: &str) -> Result<Vec<User>> {
-    let search_pattern = format!("%{}%", query);
-    let users = sqlx::query_as!(
-        User,
+/// Get the scheduled posts a user will publish or has scheduled for someone else, soonest first.
+pub async fn get_user_scheduled_posts(
+    pool: &SqlitePool,
+    user_id: Uuid,
+) -> Result<Vec<ScheduledPost>> {
+    Ok(sqlx::query_as!(
+        ScheduledPost,
         r#"
-        SELECT 
-            id AS "id: _",
-            username,
-            password,
-            created_at AS "created_at: _",
-            updated_at AS "updated_at: _"
-        FROM users
-        WHERE username LIKE ?
-        ORDER BY username
-        LIMIT 20
+        SELECT id AS "id: _", user_id AS "user_id: _", created_by AS "created_by: _", content AS "content: Json<OneOrMany<UserContent>>", scheduled_at AS "scheduled_at: _", status AS "status: _", post_id AS "post_id: _", error, media AS "media: Json<Vec<Uuid>>", attempts, retry_at AS "retry_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM scheduled_posts
+        WHERE user_id = ? OR created_by = ?
+        ORDER BY DATETIME(scheduled_at) ASC
         "#,
-        search_pattern
+        user_id,
+        user_id
     )
     .fetch_all(pool)
-    .await?;
-    Ok(users)
+    .await?)
 }
 
-pub async fn get_all_users(pool: &SqlitePool, limit: i64, offset: i64) -> Result<Vec<User>> {
-    let users = sqlx::query_as!(
-        User,
+/// Get the pending posts whose time has come, or whose next attempt is due after a failed one.
+pub async fn get_due_scheduled_posts(pool: &SqlitePool, limit: i64) -> Result<Vec<ScheduledPost>> {
+    Ok(sqlx::query_as!(
+        ScheduledPost,
         r#"
-        SELECT 
-            id AS "id: _",
-            username,
-            password,
-            created_at AS "created_at: _",
-            updated_at AS "updated_at: _"
-        FROM users
-        ORDER BY username
-        LIMIT ? OFFSET ?
+        SELECT id AS "id: _", user_id AS "user_id: _", created_by AS "created_by: _", content AS "content: Json<OneOrMany<UserContent>>", scheduled_at AS "scheduled_at: _", status AS "status: _", post_id AS "post_id: _", error, media AS "media: Json<Vec<Uuid>>", attempts, retry_at AS "retry_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM scheduled_posts
+        WHERE status = 'pending' AND DATETIME(COALESCE(retry_at, scheduled_at)) <= CURRENT_TIMESTAMP
+        ORDER BY DATETIME(COALESCE(retry_at, scheduled_at)) ASC
+        LIMIT ?
         "#,
-        limit,
-        offset
+        limit
     )
     .fetch_all(pool)
+    .await?)
+}
+
+/// Take a pending scheduled post so it's only ever published once.
+/// Returns `false` if it was no longer pending, e.g. because it was cancelled in the meantime.
+/// Has to run in the same transaction as creating the post and `set_scheduled_post_published`,
+/// or a crash in between would leave the job published without a post.
+pub async fn claim_scheduled_post(executor: impl SqliteExecutor<'_>, id: Uuid) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE scheduled_posts SET status = 'published', updated_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND status = 'pending'
+        "#,
+        id
+    )
+    .execute(executor)
     .await?;
-    Ok(users)
+    Ok(result.rows_affected() > 0)
 }
 
-// ====== Read Tracking Functions ======
+pub async fn set_scheduled_post_published(
+    executor: impl SqliteExecutor<'_>,
+    id: Uuid,
+    post_id: Uuid,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE scheduled_posts SET post_id = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        post_id,
+        id
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
 
-pub async fn mark_conversation_as_read(
+/// Record a failed attempt to publish a pending scheduled post.
+/// It's tried again at `retry_at`, or given up on and marked failed if that's `None`.
+pub async fn set_scheduled_post_attempt_failed(
     pool: &SqlitePool,
-    user_id: Uuid,
-    conversation_id: Uuid,
+    id: Uuid,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
 ) -> Result<()> {
     sqlx::query!(
         r#"
-        UPDATE conversation_participants
-        SET last_read_at = CURRENT_TIMESTAMP
-        WHERE user_id = ? AND conversation_id = ?
+        UPDATE scheduled_posts
+        SET status = CASE WHEN ?3 IS NULL THEN 'failed' ELSE status END,
+            error = ?2, retry_at = ?3, attempts = attempts + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE id = ?1 AND status = 'pending'
         "#,
-        user_id,
-        conversation_id
+        id,
+        error,
+        retry_at
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn get_last_read_time(
+/// Cancel a pending scheduled post. Both the user it's published as and whoever scheduled it can.
+pub async fn cancel_scheduled_post(
     pool: &SqlitePool,
     user_id: Uuid,
-    conversation_id: Uuid,
-) -> Result<Option<DateTime<Utc>>> {
-    let result = sqlx::query!(
+    id: Uuid,
+) -> Result<ScheduledPost> {
+    let Some(scheduled_post) = sqlx::query_as!(
+        ScheduledPost,
         r#"
-        SELECT last_read_at AS "last_read_at: DateTime<Utc>"
-        FROM conversation_participants
-        WHERE user_id = ? AND conversation_id = ?
+        UPDATE scheduled_posts SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND (user_id = ? OR created_by = ?) AND status = 'pending'
+        RETURNING id AS "id: _", user_id AS "user_id: _", created_by AS "created_by: _", content AS "content: Json<OneOrMany<UserContent>>", scheduled_at AS "scheduled_at: _", status AS "status: _", post_id AS "post_id: _", error, media AS "media: Json<Vec<Uuid>>", attempts, retry_at AS "retry_at: _", created_at AS "created_at: _", updated_at AS "updated_at: _"
         "#,
+        id,
         user_id,
-        conversation_id
+        user_id
     )
     .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Pending scheduled post not found!".into(),
+        )));
+    };
+    Ok(scheduled_post)
+}
+
+// ====== Follow Functions ======
+
+/// Follow `followee_id`, returns `false` if they were already followed.
+pub async fn follow_user(pool: &SqlitePool, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+    if follower_id == followee_id {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "You can't follow yourself".into(),
+        )));
+    }
+    // Make sure the user exists so a missing user is a 404 instead of a constraint error
+    get_user_by_id(pool, followee_id).await?;
+
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO follows (follower_id, followee_id) VALUES (?, ?)",
+        follower_id,
+        followee_id
+    )
+    .execute(pool)
     .await?;
-    
-    Ok(result.and_then(|r| r.last_read_at))
+    Ok(result.rows_affected() > 0)
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub async fn unfollow_user(pool: &SqlitePool, follower_id: Uuid, followee_id: Uuid) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM follows WHERE follower_id = ? AND followee_id = ?",
+        follower_id,
+        followee_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Get the users following `user_id`, most recent followers first.
+pub async fn get_followers(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<FollowedUser>> {
+    let bounds = page.bounds();
+    let users = sqlx::query_as!(
+        FollowedUser,
+        r#"
+        SELECT u.id AS "id: _", u.username, u.display_name, u.bio, u.links AS "links: Json<Vec<ProfileLink>>", u.avatar_updated_at AS "avatar_updated_at: _", u.created_at AS "created_at: _", f.created_at AS "followed_at: _"
+        FROM users u
+        JOIN follows f ON u.id = f.follower_id
+        WHERE f.followee_id = ?1
+        AND (?2 IS NULL OR (DATETIME(f.created_at), u.id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(f.created_at), u.id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(f.created_at) END ASC,
+            CASE WHEN ?6 THEN u.id END ASC,
+            DATETIME(f.created_at) DESC,
+            u.id DESC
+        LIMIT ?7
+        "#,
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::new(users, page))
+}
+
+/// Get the users `user_id` follows, most recently followed first.
+pub async fn get_following(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<FollowedUser>> {
+    let bounds = page.bounds();
+    let users = sqlx::query_as!(
+        FollowedUser,
+        r#"
+        SELECT u.id AS "id: _", u.username, u.display_name, u.bio, u.links AS "links: Json<Vec<ProfileLink>>", u.avatar_updated_at AS "avatar_updated_at: _", u.created_at AS "created_at: _", f.created_at AS "followed_at: _"
+        FROM users u
+        JOIN follows f ON u.id = f.followee_id
+        WHERE f.follower_id = ?1
+        AND (?2 IS NULL OR (DATETIME(f.created_at), u.id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(f.created_at), u.id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(f.created_at) END ASC,
+            CASE WHEN ?6 THEN u.id END ASC,
+            DATETIME(f.created_at) DESC,
+            u.id DESC
+        LIMIT ?7
+        "#,
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::new(users, page))
+}
+
+/// Get the home feed of `user_id`: their own posts and the posts of everyone they follow,
+/// newest first, with interaction counts.
+pub async fn get_home_feed(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<FeedPost>> {
+    let bounds = page.bounds();
+    let posts = sqlx::query_as!(
+        FeedPost,
+        r#"
+        SELECT 
+            p.id AS "id: _",
+            p.user_id AS "user_id: _",
+            p.created_by AS "created_by: _",
+            p.content AS "content: Json<OneOrMany<UserContent>>",
+            p.reshare_of AS "reshare_of: _",
+            p.media AS "media: Json<Vec<Uuid>>",
+            p.created_at AS "created_at: _",
+            p.updated_at AS "updated_at: _",
+            (SELECT COUNT(*) FROM post_likes l WHERE l.post_id = p.id) AS "like_count!: i64",
+            (SELECT COUNT(*) FROM post_comments c WHERE c.post_id = p.id) AS "comment_count!: i64",
+            (SELECT COUNT(*) FROM posts r WHERE r.reshare_of = p.id) AS "reshare_count!: i64",
+            EXISTS(SELECT 1 FROM post_likes l WHERE l.post_id = p.id AND l.user_id = ?1) AS "liked!: bool"
+        FROM posts p
+        WHERE (p.user_id = ?1
+            OR p.user_id IN (SELECT followee_id FROM follows WHERE follower_id = ?1))
+        AND (?2 IS NULL OR (DATETIME(p.created_at), p.id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(p.created_at), p.id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(p.created_at) END ASC,
+            CASE WHEN ?6 THEN p.id END ASC,
+            DATETIME(p.created_at) DESC,
+            p.id DESC
+        LIMIT ?7
+        "#,
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::new(posts, page))
+}
+
+// ====== Media Functions ======
+
+/// Most media that can be attached to a single post or message.
+pub const MAX_ATTACHED_MEDIA: usize = 10;
+
+/// Attach media to a post or to a message in a conversation, which makes it visible
+/// to whoever can see that post or conversation. Media can only be attached once,
+/// and only by the user who uploaded it.
+async fn attach_media(
+    conn: &mut SqliteConnection,
+    owner_id: Uuid,
+    media: &[Uuid],
+    post_id: Option<Uuid>,
+    conversation_id: Option<Uuid>,
+) -> Result<()> {
+    if media.len() > MAX_ATTACHED_MEDIA {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            format!("At most {MAX_ATTACHED_MEDIA} media can be attached"),
+        )));
+    }
+    for media_id in media {
+        let result = sqlx::query!(
+            r#"
+            UPDATE media SET post_id = ?, conversation_id = ?
+            WHERE id = ? AND owner_id = ? AND post_id IS NULL AND conversation_id IS NULL
+            "#,
+            post_id,
+            conversation_id,
+            media_id,
+            owner_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserError((
+                LossyError(StatusCode::BAD_REQUEST),
+                format!("Media {media_id} doesn't exist or is already attached"),
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check that `owner_id` can attach media later on, when a scheduled post is published.
+pub async fn ensure_media_attachable(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    media: &[Uuid],
+) -> Result<()> {
+    if media.len() > MAX_ATTACHED_MEDIA {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            format!("At most {MAX_ATTACHED_MEDIA} media can be attached"),
+        )));
+    }
+    for media_id in media {
+        let attachable = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM media
+                WHERE id = ? AND owner_id = ? AND post_id IS NULL AND conversation_id IS NULL
+            )
+            "#,
+            media_id,
+            owner_id
+        )
+        .fetch_one(pool)
+        .await?;
+        if attachable == 0 {
+            return Err(AppError::UserError((
+                LossyError(StatusCode::BAD_REQUEST),
+                format!("Media {media_id} doesn't exist or is already attached"),
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub async fn create_media(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    sha256: &str,
+    mime_type: &str,
+    size: i64,
+) -> Result<Media> {
+    let media_id = Uuid::new_v4();
+    let media = sqlx::query_as!(
+        Media,
+        r#"
+        INSERT INTO media (id, owner_id, sha256, mime_type, size)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", owner_id AS "owner_id: _", sha256, mime_type, size, post_id AS "post_id: _", conversation_id AS "conversation_id: _", created_at AS "created_at: _"
+        "#,
+        media_id,
+        owner_id,
+        sha256,
+        mime_type,
+        size
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(media)
+}
+
+pub async fn get_media(pool: &SqlitePool, id: Uuid) -> Result<Option<Media>> {
+    Ok(sqlx::query_as!(
+        Media,
+        r#"
+        SELECT id AS "id: _", owner_id AS "owner_id: _", sha256, mime_type, size, post_id AS "post_id: _", conversation_id AS "conversation_id: _", created_at AS "created_at: _"
+        FROM media
+        WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+// ====== Delegation Functions ======
+
+/// Let `delegate_id` act for `owner_id`, refusing to replace an existing delegation.
+pub async fn create_delegation(
+    executor: impl SqliteExecutor<'_>,
+    owner_id: Uuid,
+    delegate_id: Uuid,
+    settings: &DelegationSettings,
+) -> Result<Delegation> {
+    let conversation_ids = settings.conversation_ids.as_ref().map(Json);
+    let active_hours = settings.active_hours.as_ref().map(Json);
+    let Some(delegation) = sqlx::query_as!(
+        Delegation,
+        r#"
+        INSERT INTO delegations (owner_id, delegate_id, can_post, can_message, can_read_messages, can_delete_posts, can_comment, conversation_ids, expires_at, active_hours)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (owner_id, delegate_id) DO NOTHING
+        RETURNING
+            owner_id AS "owner_id: _",
+            delegate_id AS "delegate_id: _",
+            can_post,
+            can_message,
+            can_read_messages,
+            can_delete_posts,
+            can_comment,
+            conversation_ids AS "conversation_ids: Json<Vec<Uuid>>",
+            expires_at AS "expires_at: _",
+            active_hours AS "active_hours: Json<ActiveHours>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        "#,
+        owner_id,
+        delegate_id,
+        settings.can_post,
+        settings.can_message,
+        settings.can_read_messages,
+        settings.can_delete_posts,
+        settings.can_comment,
+        conversation_ids,
+        settings.expires_at,
+        active_hours
+    )
+    .fetch_optional(executor)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::CONFLICT),
+            "You already delegated to this user, update the delegation instead".into(),
+        )));
+    };
+    Ok(delegation)
+}
+
+pub async fn update_delegation(
+    executor: impl SqliteExecutor<'_>,
+    owner_id: Uuid,
+    delegate_id: Uuid,
+    settings: &DelegationSettings,
+) -> Result<Delegation> {
+    let conversation_ids = settings.conversation_ids.as_ref().map(Json);
+    let active_hours = settings.active_hours.as_ref().map(Json);
+    let Some(delegation) = sqlx::query_as!(
+        Delegation,
+        r#"
+        UPDATE delegations
+        SET can_post = ?, can_message = ?, can_read_messages = ?, can_delete_posts = ?, can_comment = ?,
+            conversation_ids = ?, expires_at = ?, active_hours = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE owner_id = ? AND delegate_id = ?
+        RETURNING
+            owner_id AS "owner_id: _",
+            delegate_id AS "delegate_id: _",
+            can_post,
+            can_message,
+            can_read_messages,
+            can_delete_posts,
+            can_comment,
+            conversation_ids AS "conversation_ids: Json<Vec<Uuid>>",
+            expires_at AS "expires_at: _",
+            active_hours AS "active_hours: Json<ActiveHours>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        "#,
+        settings.can_post,
+        settings.can_message,
+        settings.can_read_messages,
+        settings.can_delete_posts,
+        settings.can_comment,
+        conversation_ids,
+        settings.expires_at,
+        active_hours,
+        owner_id,
+        delegate_id
+    )
+    .fetch_optional(executor)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Delegation not found!".into(),
+        )));
+    };
+    Ok(delegation)
+}
+
+/// The delegations `owner_id` granted, newest first, including the ones that aren't in effect right now.
+pub async fn get_user_delegations(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<Delegation>> {
+    let bounds = page.bounds();
+    let delegations = sqlx::query_as!(
+        Delegation,
+        r#"
+        SELECT
+            owner_id AS "owner_id: _",
+            delegate_id AS "delegate_id: _",
+            can_post,
+            can_message,
+            can_read_messages,
+            can_delete_posts,
+            can_comment,
+            conversation_ids AS "conversation_ids: Json<Vec<Uuid>>",
+            expires_at AS "expires_at: _",
+            active_hours AS "active_hours: Json<ActiveHours>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM delegations
+        WHERE owner_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), delegate_id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), delegate_id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN delegate_id END ASC,
+            DATETIME(created_at) DESC,
+            delegate_id DESC
+        LIMIT ?7
+        "#,
+        owner_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::with_cursor(delegations, page, |delegation| Cursor {
+        created_at: delegation.created_at,
+        id: delegation.delegate_id,
+    }))
+}
+
+/// The delegations `delegate_id` was granted, newest first, including the ones that aren't in effect right now.
+pub async fn get_delegated_to_user(
+    pool: &SqlitePool,
+    delegate_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<Delegation>> {
+    let bounds = page.bounds();
+    let delegations = sqlx::query_as!(
+        Delegation,
+        r#"
+        SELECT
+            owner_id AS "owner_id: _",
+            delegate_id AS "delegate_id: _",
+            can_post,
+            can_message,
+            can_read_messages,
+            can_delete_posts,
+            can_comment,
+            conversation_ids AS "conversation_ids: Json<Vec<Uuid>>",
+            expires_at AS "expires_at: _",
+            active_hours AS "active_hours: Json<ActiveHours>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM delegations
+        WHERE delegate_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), owner_id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), owner_id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN owner_id END ASC,
+            DATETIME(created_at) DESC,
+            owner_id DESC
+        LIMIT ?7
+        "#,
+        delegate_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::with_cursor(delegations, page, |delegation| Cursor {
+        created_at: delegation.created_at,
+        id: delegation.owner_id,
+    }))
+}
+
+/// Everyone `owner_id` delegated to, including the ones whose delegation isn't in effect right now.
+pub async fn get_delegate_ids(pool: &SqlitePool, owner_id: Uuid) -> Result<Vec<Uuid>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT delegate_id AS "delegate_id: Uuid" FROM delegations WHERE owner_id = ?"#,
+        owner_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn get_delegation(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    delegate_id: Uuid,
+) -> Result<Delegation> {
+    let Some(delegation) = sqlx::query_as!(
+        Delegation,
+        r#"
+        SELECT
+            owner_id AS "owner_id: _",
+            delegate_id AS "delegate_id: _",
+            can_post,
+            can_message,
+            can_read_messages,
+            can_delete_posts,
+            can_comment,
+            conversation_ids AS "conversation_ids: Json<Vec<Uuid>>",
+            expires_at AS "expires_at: _",
+            active_hours AS "active_hours: Json<ActiveHours>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM delegations
+        WHERE owner_id = ? AND delegate_id = ?
+        "#,
+        owner_id,
+        delegate_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Delegation not found!".into(),
+        )));
+    };
+    Ok(delegation)
+}
+
+/// The delegation that lets `delegate_id` act for `owner_id` right now.
+/// Delegations that expired or are outside their active hours don't count,
+/// so every permission check has to go through here.
+pub async fn check_delegation(
+    executor: impl SqliteExecutor<'_>,
+    owner_id: Uuid,
+    delegate_id: Uuid,
+) -> Result<Option<Delegation>> {
+    let delegation = sqlx::query_as!(
+        Delegation,
+        r#"
+        SELECT
+            owner_id AS "owner_id: _",
+            delegate_id AS "delegate_id: _",
+            can_post,
+            can_message,
+            can_read_messages,
+            can_delete_posts,
+            can_comment,
+            conversation_ids AS "conversation_ids: Json<Vec<Uuid>>",
+            expires_at AS "expires_at: _",
+            active_hours AS "active_hours: Json<ActiveHours>",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM delegations
+        WHERE owner_id = ? AND delegate_id = ?
+        "#,
+        owner_id,
+        delegate_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(delegation.filter(|delegation| delegation.is_in_effect(Utc::now())))
+}
+
+/// Returns whether there was a delegation to delete.
+pub async fn delete_delegation(
+    executor: impl SqliteExecutor<'_>,
+    owner_id: Uuid,
+    delegate_id: Uuid,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM delegations WHERE owner_id = ? AND delegate_id = ?",
+        owner_id,
+        delegate_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// ====== Kill Switch Functions ======
+
+/// Get a user's kill switch, or the global one when passed `GLOBAL_KILL_SWITCH`.
+/// Switches that have never been flipped are disengaged.
+pub async fn get_kill_switch(pool: &SqlitePool, user_id: Uuid) -> Result<KillSwitch> {
+    let switch = sqlx::query_as!(
+        KillSwitch,
+        r#"
+        SELECT engaged, updated_by AS "updated_by: _", updated_at AS "updated_at: _"
+        FROM kill_switches
+        WHERE user_id = ?
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(switch.unwrap_or_default())
+}
+
+pub async fn set_kill_switch(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    engaged: bool,
+    updated_by: Uuid,
+) -> Result<KillSwitch> {
+    let switch = sqlx::query_as!(
+        KillSwitch,
+        r#"
+        INSERT INTO kill_switches (user_id, engaged, updated_by)
+        VALUES (?, ?, ?)
+        ON CONFLICT(user_id) DO UPDATE SET
+            engaged = excluded.engaged,
+            updated_by = excluded.updated_by,
+            updated_at = CURRENT_TIMESTAMP
+        RETURNING engaged, updated_by AS "updated_by: _", updated_at AS "updated_at: _"
+        "#,
+        user_id,
+        engaged,
+        updated_by
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(switch)
+}
+
+/// Whether agents are currently allowed to act for this user,
+/// checking both the user's own switch and the global one.
+pub async fn is_kill_switch_engaged(pool: &SqlitePool, user_id: Uuid) -> Result<bool> {
+    let engaged = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM kill_switches WHERE engaged = 1 AND user_id IN (?, ?)",
+        user_id,
+        GLOBAL_KILL_SWITCH
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(engaged > 0)
+}
+
+// ====== Category Functions ======
+
+/// Give a new user the built-in categories.
+async fn create_default_categories(conn: &mut SqliteConnection, user_id: Uuid) -> Result<()> {
+    for (position, (key, name, description)) in DEFAULT_CATEGORIES.into_iter().enumerate() {
+        let position = position as i64;
+        sqlx::query!(
+            "INSERT INTO categories (user_id, key, name, description, position) VALUES (?, ?, ?, ?, ?)",
+            user_id,
+            key,
+            name,
+            description,
+            position
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Get all of a user's categories in the order they're listed in.
+pub async fn get_user_categories(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<Category>> {
+    Ok(sqlx::query_as!(
+        Category,
+        r#"
+        SELECT key AS "key: _", name, description, position, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM categories
+        WHERE user_id = ?
+        ORDER BY position ASC, DATETIME(created_at) ASC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Make sure `category` is one of the user's categories before something refers to it.
+pub async fn ensure_category_exists(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    category: &MessageCategory,
+) -> Result<()> {
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM categories WHERE user_id = ? AND key = ?)",
+        user_id,
+        category
+    )
+    .fetch_one(pool)
+    .await?;
+    if exists == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            format!("You don't have a category called `{category}`"),
+        )));
+    }
+    Ok(())
+}
+
+pub async fn create_category(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    key: &MessageCategory,
+    category: &CategoryRequest,
+) -> Result<Category> {
+    let Some(category) = sqlx::query_as!(
+        Category,
+        r#"
+        INSERT INTO categories (user_id, key, name, description, position)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(user_id, key) DO NOTHING
+        RETURNING key AS "key: _", name, description, position, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        "#,
+        user_id,
+        key,
+        category.name,
+        category.description,
+        category.position
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::CONFLICT),
+            "You already have a category with this name!".into(),
+        )));
+    };
+    Ok(category)
+}
+
+pub async fn update_category(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    key: &MessageCategory,
+    category: &CategoryRequest,
+) -> Result<Category> {
+    let Some(category) = sqlx::query_as!(
+        Category,
+        r#"
+        UPDATE categories
+        SET name = ?, description = ?, position = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE user_id = ? AND key = ?
+        RETURNING key AS "key: _", name, description, position, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        "#,
+        category.name,
+        category.description,
+        category.position,
+        user_id,
+        key
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Category not found!".into(),
+        )));
+    };
+    Ok(category)
+}
+
+/// Delete a category along with the user's rules and responder policy for it.
+/// Messages in the category become uncategorized, corrections to it are kept as history.
+/// The last category can't be deleted, since the categorizer needs something to choose from.
+pub async fn delete_category(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    key: &MessageCategory,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        "DELETE FROM categories WHERE user_id = ? AND key = ?",
+        user_id,
+        key
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Category not found!".into(),
+        )));
+    }
+
+    let remaining =
+        sqlx::query_scalar!("SELECT COUNT(*) FROM categories WHERE user_id = ?", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+    if remaining == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::CONFLICT),
+            "You can't delete your last category!".into(),
+        )));
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// ====== Categorization Rule Functions ======
+
+pub async fn create_categorization_rule(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    rule: &CategorizationRuleRequest,
+) -> Result<CategorizationRule> {
+    let rule_id = Uuid::new_v4();
+    let condition = Json(&rule.condition);
+    let rule = sqlx::query_as!(
+        CategorizationRule,
+        r#"
+        INSERT INTO categorization_rules (id, user_id, name, condition, category, reasoning, priority, enabled)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", user_id AS "user_id: _", name, condition AS "condition: Json<RuleCondition>", category AS "category: _", reasoning, priority, enabled, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        "#,
+        rule_id,
+        user_id,
+        rule.name,
+        condition,
+        rule.category,
+        rule.reasoning,
+        rule.priority,
+        rule.enabled
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rule)
+}
+
+/// Get all of a user's rules in the order they're evaluated in.
+pub async fn get_user_categorization_rules(
+    pool: &SqlitePool,
+    user_id: Uuid,
+) -> Result<Vec<CategorizationRule>> {
+    Ok(sqlx::query_as!(
+        CategorizationRule,
+        r#"
+        SELECT id AS "id: _", user_id AS "user_id: _", name, condition AS "condition: Json<RuleCondition>", category AS "category: _", reasoning, priority, enabled, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        FROM categorization_rules
+        WHERE user_id = ?
+        ORDER BY priority ASC, DATETIME(created_at) ASC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Whether `user_id` has heard from `message`'s sender before it arrived, in any of their conversations,
+/// or has written to the sender themselves.
+pub async fn has_corresponded_with_sender(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message: &ChatMessage,
+) -> Result<bool> {
+    let known = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1
+            FROM messages m
+            JOIN conversation_participants mine
+                ON mine.conversation_id = m.conversation_id AND mine.user_id = ?1
+            WHERE m.id != ?3
+            AND DATETIME(m.created_at) <= DATETIME(?4)
+            AND (
+                m.sender_id = ?2
+                OR (
+                    m.sender_id = ?1
+                    AND EXISTS (
+                        SELECT 1 FROM conversation_participants theirs
+                        WHERE theirs.conversation_id = m.conversation_id AND theirs.user_id = ?2
+                    )
+                )
+            )
+        )
+        "#,
+        user_id,
+        message.sender_id,
+        message.id,
+        message.created_at
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(known != 0)
+}
+
+pub async fn update_categorization_rule(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    id: Uuid,
+    rule: &CategorizationRuleRequest,
+) -> Result<CategorizationRule> {
+    let condition = Json(&rule.condition);
+    let Some(rule) = sqlx::query_as!(
+        CategorizationRule,
+        r#"
+        UPDATE categorization_rules
+        SET name = ?, condition = ?, category = ?, reasoning = ?, priority = ?, enabled = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND user_id = ?
+        RETURNING id AS "id: _", user_id AS "user_id: _", name, condition AS "condition: Json<RuleCondition>", category AS "category: _", reasoning, priority, enabled, created_at AS "created_at: _", updated_at AS "updated_at: _"
+        "#,
+        rule.name,
+        condition,
+        rule.category,
+        rule.reasoning,
+        rule.priority,
+        rule.enabled,
+        id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Categorization rule not found!".into(),
+        )));
+    };
+    Ok(rule)
+}
+
+pub async fn delete_categorization_rule(pool: &SqlitePool, user_id: Uuid, id: Uuid) -> Result<()> {
+    let result = sqlx::query!(
+        "DELETE FROM categorization_rules WHERE id = ? AND user_id = ?",
+        id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Categorization rule not found!".into(),
+        )));
+    }
+    Ok(())
+}
+
+// ====== Responder Policy Functions ======
+
+pub async fn get_responder_policies(
+    pool: &SqlitePool,
+    user_id: Uuid,
+) -> Result<Vec<ResponderPolicy>> {
+    Ok(sqlx::query_as!(
+        ResponderPolicy,
+        r#"
+        SELECT p.category AS "category: _", p.action AS "action: _", p.instructions, p.updated_at AS "updated_at: _"
+        FROM responder_policies p
+        JOIN categories c ON c.user_id = p.user_id AND c.key = p.category
+        WHERE p.user_id = ?
+        ORDER BY c.position ASC, DATETIME(c.created_at) ASC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Get a user's policy for a category, `None` means the category is ignored.
+pub async fn get_responder_policy(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    category: MessageCategory,
+) -> Result<Option<ResponderPolicy>> {
+    Ok(sqlx::query_as!(
+        ResponderPolicy,
+        r#"
+        SELECT category AS "category: _", action AS "action: _", instructions, updated_at AS "updated_at: _"
+        FROM responder_policies
+        WHERE user_id = ? AND category = ?
+        "#,
+        user_id,
+        category
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn set_responder_policy(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    category: MessageCategory,
+    action: ResponderAction,
+    instructions: Option<&str>,
+) -> Result<ResponderPolicy> {
+    Ok(sqlx::query_as!(
+        ResponderPolicy,
+        r#"
+        INSERT INTO responder_policies (user_id, category, action, instructions)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(user_id, category) DO UPDATE SET
+            action = excluded.action,
+            instructions = excluded.instructions,
+            updated_at = CURRENT_TIMESTAMP
+        RETURNING category AS "category: _", action AS "action: _", instructions, updated_at AS "updated_at: _"
+        "#,
+        user_id,
+        category,
+        action,
+        instructions
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+// ====== Draft Functions ======
+
+pub async fn create_draft(executor: impl SqliteExecutor<'_>, draft: &NewDraft) -> Result<Draft> {
+    let draft_id = Uuid::new_v4();
+    let content = Json(&draft.content);
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        INSERT INTO drafts (id, owner_id, created_by, kind, source, conversation_id, in_reply_to, content, scheduled_at, prompt_template_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", owner_id AS "owner_id: _", created_by AS "created_by: _", kind AS "kind: _", source AS "source: _", conversation_id AS "conversation_id: _", in_reply_to AS "in_reply_to: _", content AS "content: Json<OneOrMany<UserContent>>", status AS "status: _", scheduled_at AS "scheduled_at: _", result_id AS "result_id: _", prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _", resolved_at AS "resolved_at: _"
+        "#,
+        draft_id,
+        draft.owner_id,
+        draft.created_by,
+        draft.kind,
+        draft.source,
+        draft.conversation_id,
+        draft.in_reply_to,
+        content,
+        draft.scheduled_at,
+        draft.prompt_template_id
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(draft)
+}
+
+/// Get a user's drafts, newest first, optionally only those with `status`.
+pub async fn get_user_drafts(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    status: Option<DraftStatus>,
+    page: &PageQuery,
+) -> Result<Page<Draft>> {
+    let bounds = page.bounds();
+    let drafts = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT id AS "id: _", owner_id AS "owner_id: _", created_by AS "created_by: _", kind AS "kind: _", source AS "source: _", conversation_id AS "conversation_id: _", in_reply_to AS "in_reply_to: _", content AS "content: Json<OneOrMany<UserContent>>", status AS "status: _", scheduled_at AS "scheduled_at: _", result_id AS "result_id: _", prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _", resolved_at AS "resolved_at: _"
+        FROM drafts
+        WHERE owner_id = ?1 AND (?8 IS NULL OR status = ?8)
+        AND (?2 IS NULL OR (DATETIME(created_at), id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN id END ASC,
+            DATETIME(created_at) DESC,
+            id DESC
+        LIMIT ?7
+        "#,
+        owner_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch,
+        status
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Page::new(drafts, page))
+}
+
+/// Replace the content of a draft that is still pending.
+pub async fn update_draft_content(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    id: Uuid,
+    content: OneOrMany<UserContent>,
+) -> Result<Draft> {
+    let content = Json(content);
+    let Some(draft) = sqlx::query_as!(
+        Draft,
+        r#"
+        UPDATE drafts SET content = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND owner_id = ? AND status = 'pending'
+        RETURNING id AS "id: _", owner_id AS "owner_id: _", created_by AS "created_by: _", kind AS "kind: _", source AS "source: _", conversation_id AS "conversation_id: _", in_reply_to AS "in_reply_to: _", content AS "content: Json<OneOrMany<UserContent>>", status AS "status: _", scheduled_at AS "scheduled_at: _", result_id AS "result_id: _", prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _", resolved_at AS "resolved_at: _"
+        "#,
+        content,
+        id,
+        owner_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Pending draft not found!".into(),
+        )));
+    };
+    Ok(draft)
+}
+
+/// Move a pending draft to `status`.
+/// Only one caller can resolve a draft, so this doubles as a lock when approving it.
+pub async fn resolve_draft(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    id: Uuid,
+    status: DraftStatus,
+) -> Result<Draft> {
+    let Some(draft) = sqlx::query_as!(
+        Draft,
+        r#"
+        UPDATE drafts SET status = ?, resolved_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND owner_id = ? AND status = 'pending'
+        RETURNING id AS "id: _", owner_id AS "owner_id: _", created_by AS "created_by: _", kind AS "kind: _", source AS "source: _", conversation_id AS "conversation_id: _", in_reply_to AS "in_reply_to: _", content AS "content: Json<OneOrMany<UserContent>>", status AS "status: _", scheduled_at AS "scheduled_at: _", result_id AS "result_id: _", prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _", resolved_at AS "resolved_at: _"
+        "#,
+        status,
+        id,
+        owner_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Pending draft not found!".into(),
+        )));
+    };
+    Ok(draft)
+}
+
+/// Put a draft back into the queue, used when approving it failed half way.
+pub async fn reopen_draft(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    sqlx::query!(
+        "UPDATE drafts SET status = 'pending', resolved_at = NULL WHERE id = ?",
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_draft_result(pool: &SqlitePool, id: Uuid, result_id: Uuid) -> Result<Draft> {
+    Ok(sqlx::query_as!(
+        Draft,
+        r#"
+        UPDATE drafts SET result_id = ?
+        WHERE id = ?
+        RETURNING id AS "id: _", owner_id AS "owner_id: _", created_by AS "created_by: _", kind AS "kind: _", source AS "source: _", conversation_id AS "conversation_id: _", in_reply_to AS "in_reply_to: _", content AS "content: Json<OneOrMany<UserContent>>", status AS "status: _", scheduled_at AS "scheduled_at: _", result_id AS "result_id: _", prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _", updated_at AS "updated_at: _", resolved_at AS "resolved_at: _"
+        "#,
+        result_id,
+        id
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+// ====== Prompt Template Functions ======
+
+/// Save the built-in body of a template as its first version, unless it already has one.
+pub async fn seed_prompt_template(
+    pool: &SqlitePool,
+    name: PromptTemplateName,
+    body: &str,
+) -> Result<()> {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO prompt_templates (id, name, version, body)
+        SELECT ?1, ?2, 1, ?3
+        WHERE NOT EXISTS (SELECT 1 FROM prompt_templates WHERE name = ?2)
+        "#,
+        id,
+        name,
+        body
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Save `body` as the next version of a template, which makes it the one agents use.
+/// `created_by` is `None` for built-in templates.
+pub async fn create_prompt_template_version(
+    pool: &SqlitePool,
+    name: PromptTemplateName,
+    body: &str,
+    created_by: Option<Uuid>,
+) -> Result<PromptTemplate> {
+    let id = Uuid::new_v4();
+    // A single statement, so two edits can't both claim the same version
+    Ok(sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        INSERT INTO prompt_templates (id, name, version, body, created_by)
+        SELECT ?1, ?2, COALESCE(MAX(version), 0) + 1, ?3, ?4
+        FROM prompt_templates WHERE name = ?2
+        RETURNING id AS "id: _", name AS "name: _", version, body, created_by AS "created_by: _", created_at AS "created_at: _"
+        "#,
+        id,
+        name,
+        body,
+        created_by
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// The version of a template agents currently use.
+pub async fn get_active_prompt_template(
+    pool: &SqlitePool,
+    name: PromptTemplateName,
+) -> Result<PromptTemplate> {
+    sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        SELECT id AS "id: _", name AS "name: _", version, body, created_by AS "created_by: _", created_at AS "created_at: _"
+        FROM prompt_templates
+        WHERE name = ?
+        ORDER BY version DESC
+        LIMIT 1
+        "#,
+        name
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        AppError::Generic(LossyError(eyre!(
+            "The {name:?} prompt template has no versions"
+        )))
+    })
+}
+
+/// Every version of a template, newest first.
+pub async fn get_prompt_template_versions(
+    pool: &SqlitePool,
+    name: PromptTemplateName,
+) -> Result<Vec<PromptTemplate>> {
+    Ok(sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        SELECT id AS "id: _", name AS "name: _", version, body, created_by AS "created_by: _", created_at AS "created_at: _"
+        FROM prompt_templates
+        WHERE name = ?
+        ORDER BY version DESC
+        "#,
+        name
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn get_prompt_template_version(
+    pool: &SqlitePool,
+    name: PromptTemplateName,
+    version: i64,
+) -> Result<Option<PromptTemplate>> {
+    Ok(sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        SELECT id AS "id: _", name AS "name: _", version, body, created_by AS "created_by: _", created_at AS "created_at: _"
+        FROM prompt_templates
+        WHERE name = ? AND version = ?
+        "#,
+        name,
+        version
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+// ====== User Search Functions ======
+
+pub async fn search_users(pool: &SqlitePool, query: &str) -> Result<Vec<User>> {
+    let search_pattern = format!("%{}%", query);
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT 
+            id AS "id: _",
+            username,
+            password,
+            display_name,
+            bio,
+            links AS "links: Json<Vec<ProfileLink>>",
+            avatar_updated_at AS "avatar_updated_at: _",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM users
+        WHERE username LIKE ?
+        ORDER BY username
+        LIMIT 20
+        "#,
+        search_pattern
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(users)
+}
+
+pub async fn get_all_users(pool: &SqlitePool, limit: i64, offset: i64) -> Result<Vec<User>> {
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT 
+            id AS "id: _",
+            username,
+            password,
+            display_name,
+            bio,
+            links AS "links: Json<Vec<ProfileLink>>",
+            avatar_updated_at AS "avatar_updated_at: _",
+            created_at AS "created_at: _",
+            updated_at AS "updated_at: _"
+        FROM users
+        ORDER BY username
+        LIMIT ? OFFSET ?
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(users)
+}
+
+// ====== Read Tracking Functions ======
+
+pub async fn mark_conversation_as_read(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    conversation_id: Uuid,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE conversation_participants
+        SET last_read_at = CURRENT_TIMESTAMP
+        WHERE user_id = ? AND conversation_id = ?
+        "#,
+        user_id,
+        conversation_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_last_read_time(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    conversation_id: Uuid,
+) -> Result<Option<DateTime<Utc>>> {
+    let result = sqlx::query!(
+        r#"
+        SELECT last_read_at AS "last_read_at: DateTime<Utc>"
+        FROM conversation_participants
+        WHERE user_id = ? AND conversation_id = ?
+        "#,
+        user_id,
+        conversation_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(result.and_then(|r| r.last_read_at))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadMessage {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub sender_id: Uuid,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub conversation_title: Option<String>,
+    pub sender_username: String,
+}
+
+pub async fn get_unread_messages(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<UnreadMessage>> {
+    let bounds = page.bounds();
+    let messages = sqlx::query_as!(
+        UnreadMessage,
+        r#"
+        SELECT 
+            m.id AS "id: _",
+            m.conversation_id AS "conversation_id: _",
+            m.sender_id AS "sender_id: _",
+            m.content,
+            m.created_at AS "created_at: _",
+            c.title AS "conversation_title",
+            u.username AS "sender_username!"
+        FROM messages m
+        JOIN conversations c ON m.conversation_id = c.id
+        JOIN conversation_participants cp ON cp.conversation_id = c.id
+        JOIN users u ON m.sender_id = u.id
+        WHERE cp.user_id = ?1
+        AND m.sender_id != ?1
+        AND (cp.last_read_at IS NULL OR DATETIME(m.created_at) > DATETIME(cp.last_read_at))
+        AND (?2 IS NULL OR (DATETIME(m.created_at), m.id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(m.created_at), m.id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(m.created_at) END ASC,
+            CASE WHEN ?6 THEN m.id END ASC,
+            DATETIME(m.created_at) DESC,
+            m.id DESC
+        LIMIT ?7
+        "#,
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Page::new(messages, page))
+}
+
+// ====== Pinned Message Functions ======
+
+/// Why a message was pinned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum PinReason {
+    /// It was categorized as urgent, unread ones are escalated
+    Urgent,
+    /// The user pinned it themselves
+    Manual,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedMessage {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub sender_id: Uuid,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub reason: PinReason,
+    pub pinned_at: DateTime<Utc>,
+    /// When the user was notified again because the message stayed unread
+    pub escalated_at: Option<DateTime<Utc>>,
+}
+
+/// How a user wants to be reminded of urgent messages they haven't read.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EscalationPolicy {
+    pub enabled: bool,
+    /// How long an urgent message can stay unread before the user is notified again
+    pub after_minutes: i64,
+    /// Also POSTed every escalation, if set
+    pub webhook_url: Option<String>,
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            after_minutes: 15,
+            webhook_url: None,
+        }
+    }
+}
+
+/// An urgent message that stayed unread for longer than its recipient's policy allows.
+#[derive(Clone, Debug, FromRow)]
+pub struct DueEscalation {
+    pub user_id: Uuid,
+    pub message_id: Uuid,
+    pub webhook_url: Option<String>,
+}
+
+/// Pin a message for `user_id`. Returns `false` if it was already pinned, the existing pin is kept.
+pub async fn pin_message(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+    reason: PinReason,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO pinned_messages (user_id, message_id, reason)
+        VALUES (?, ?, ?)
+        ON CONFLICT(user_id, message_id) DO NOTHING
+        "#,
+        user_id,
+        message_id,
+        reason
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Unpin a message for `user_id`, only if it was pinned for `reason` when that is given.
+/// Returns `false` if nothing was unpinned.
+pub async fn unpin_message(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+    reason: Option<PinReason>,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM pinned_messages
+        WHERE user_id = ?1 AND message_id = ?2 AND (?3 IS NULL OR reason = ?3)
+        "#,
+        user_id,
+        message_id,
+        reason
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_pinned_message(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+) -> Result<Option<PinnedMessage>> {
+    Ok(sqlx::query_as!(
+        PinnedMessage,
+        r#"
+        SELECT m.id AS "id: _", m.conversation_id AS "conversation_id: _", m.sender_id AS "sender_id: _", m.content, m.created_at AS "created_at: _", p.reason AS "reason: _", p.pinned_at AS "pinned_at: _", p.escalated_at AS "escalated_at: _"
+        FROM pinned_messages p
+        JOIN messages m ON m.id = p.message_id
+        WHERE p.user_id = ? AND p.message_id = ?
+        "#,
+        user_id,
+        message_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// A user's pinned messages, most recently pinned first.
+pub async fn get_pinned_messages(pool: &SqlitePool, user_id: Uuid) -> Result<Vec<PinnedMessage>> {
+    Ok(sqlx::query_as!(
+        PinnedMessage,
+        r#"
+        SELECT m.id AS "id: _", m.conversation_id AS "conversation_id: _", m.sender_id AS "sender_id: _", m.content, m.created_at AS "created_at: _", p.reason AS "reason: _", p.pinned_at AS "pinned_at: _", p.escalated_at AS "escalated_at: _"
+        FROM pinned_messages p
+        JOIN messages m ON m.id = p.message_id
+        WHERE p.user_id = ?
+        ORDER BY DATETIME(p.pinned_at) DESC, m.id DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Get a user's escalation policy, the defaults if they never set one.
+pub async fn get_escalation_policy(pool: &SqlitePool, user_id: Uuid) -> Result<EscalationPolicy> {
+    let policy = sqlx::query_as!(
+        EscalationPolicy,
+        "SELECT enabled, after_minutes, webhook_url FROM escalation_policies WHERE user_id = ?",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(policy.unwrap_or_default())
+}
+
+pub async fn set_escalation_policy(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    policy: &EscalationPolicy,
+) -> Result<EscalationPolicy> {
+    Ok(sqlx::query_as!(
+        EscalationPolicy,
+        r#"
+        INSERT INTO escalation_policies (user_id, enabled, after_minutes, webhook_url)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(user_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            after_minutes = excluded.after_minutes,
+            webhook_url = excluded.webhook_url,
+            updated_at = CURRENT_TIMESTAMP
+        RETURNING enabled, after_minutes, webhook_url
+        "#,
+        user_id,
+        policy.enabled,
+        policy.after_minutes,
+        policy.webhook_url
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Urgent pins that haven't been escalated yet although their message is still unread
+/// after the recipient's `after_minutes`. Users without a policy wait `default_after_minutes`.
+pub async fn get_due_escalations(
+    pool: &SqlitePool,
+    default_after_minutes: i64,
+    limit: i64,
+) -> Result<Vec<DueEscalation>> {
+    Ok(sqlx::query_as!(
+        DueEscalation,
+        r#"
+        SELECT p.user_id AS "user_id: _", p.message_id AS "message_id: _", e.webhook_url
+        FROM pinned_messages p
+        JOIN messages m ON m.id = p.message_id
+        JOIN conversation_participants cp ON cp.conversation_id = m.conversation_id AND cp.user_id = p.user_id
+        LEFT JOIN escalation_policies e ON e.user_id = p.user_id
+        WHERE p.reason = 'urgent' AND p.escalated_at IS NULL
+        AND COALESCE(e.enabled, 1)
+        AND DATETIME(p.pinned_at, '+' || COALESCE(e.after_minutes, ?1) || ' minutes') <= CURRENT_TIMESTAMP
+        AND (cp.last_read_at IS NULL OR DATETIME(m.created_at) > DATETIME(cp.last_read_at))
+        ORDER BY DATETIME(p.pinned_at) ASC
+        LIMIT ?2
+        "#,
+        default_after_minutes,
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Mark an urgent pin as escalated so it's only ever escalated once.
+/// Returns `false` if it was already escalated or unpinned in the meantime.
+pub async fn claim_escalation(pool: &SqlitePool, user_id: Uuid, message_id: Uuid) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE pinned_messages SET escalated_at = CURRENT_TIMESTAMP
+        WHERE user_id = ? AND message_id = ? AND escalated_at IS NULL
+        "#,
+        user_id,
+        message_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// ====== Digest Functions ======
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct UnreadMessage {
+#[sqlx(rename_all = "camelCase")]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Digest {
     pub id: Uuid,
+    pub user_id: Uuid,
+    pub period: DigestPeriod,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    /// Written by the scheduler rather than on demand
+    pub scheduled: bool,
+    #[schema(value_type = DigestStats)]
+    pub stats: Json<DigestStats>,
+    /// Narrative written by the summarizer, unset if it couldn't run
+    pub summary: Option<String>,
+    /// The prompt template version the summary was written with
+    pub prompt_template_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The numbers a digest is made of.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DigestStats {
+    /// Messages other people sent the user during the period
+    pub messages_received: i64,
+    /// How many of them ended up in each of the user's categories
+    pub categories: Vec<CategoryCount>,
+    /// Conversations with urgent messages the user still hasn't read, whenever they were sent
+    pub unread_urgent_threads: Vec<UrgentThread>,
+    /// Posts delegates published as the user during the period
+    pub delegate_posts: Vec<DelegatePost>,
+    /// Replies the DM responder sent as the user during the period, by conversation
+    pub agent_replies: Vec<AgentReplies>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryCount {
+    pub category: MessageCategory,
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UrgentThread {
     pub conversation_id: Uuid,
-    pub sender_id: Uuid,
-    pub content: String,
+    pub conversation_title: Option<String>,
+    pub unread_urgent: i64,
+    pub latest_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DelegatePost {
+    pub post_id: Uuid,
+    pub created_by: Uuid,
+    pub created_by_username: String,
+    /// The text parts of the post, see [`message_text`]
+    pub text: String,
     pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentReplies {
+    pub conversation_id: Uuid,
     pub conversation_title: Option<String>,
-    pub sender_username: String,
+    pub replies: i64,
 }
 
-pub async fn get_unread_messages(
+/// Gather the numbers for a digest of `user_id`'s activity between `start` and `end`.
+pub async fn get_digest_stats(
     pool: &SqlitePool,
     user_id: Uuid,
-) -> Result<Vec<UnreadMessage>> {
-    let messages = sqlx::query_as!(
-        UnreadMessage,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<DigestStats> {
+    let messages_received = sqlx::query_scalar!(
         r#"
-        SELECT 
-            m.id AS "id: _",
-            m.conversation_id AS "conversation_id: _",
-            m.sender_id AS "sender_id: _",
-            m.content,
-            m.created_at AS "created_at: _",
-            c.title AS "conversation_title",
-            u.username AS "sender_username!"
+        SELECT COUNT(*) AS "count!: i64"
         FROM messages m
-        JOIN conversations c ON m.conversation_id = c.id
-        JOIN conversation_participants cp ON cp.conversation_id = c.id
-        JOIN users u ON m.sender_id = u.id
-        WHERE cp.user_id = ?
-        AND m.sender_id != ?
+        JOIN conversation_participants cp ON cp.conversation_id = m.conversation_id AND cp.user_id = ?1
+        WHERE m.sender_id != ?1
+        AND DATETIME(m.created_at) >= DATETIME(?2) AND DATETIME(m.created_at) < DATETIME(?3)
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let categories = sqlx::query_as!(
+        CategoryCount,
+        r#"
+        SELECT c.key AS "category: _", c.name, COUNT(m.id) AS "count!: i64"
+        FROM categories c
+        LEFT JOIN user_message_metadata meta ON meta.user_id = c.user_id AND meta.category = c.key
+        LEFT JOIN messages m ON m.id = meta.message_id
+            AND DATETIME(m.created_at) >= DATETIME(?2) AND DATETIME(m.created_at) < DATETIME(?3)
+        WHERE c.user_id = ?1
+        GROUP BY c.key
+        ORDER BY c.position ASC, DATETIME(c.created_at) ASC
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let unread_urgent_threads = sqlx::query_as!(
+        UrgentThread,
+        r#"
+        SELECT
+            m.conversation_id AS "conversation_id: _",
+            conv.title AS "conversation_title",
+            COUNT(*) AS "unread_urgent!: i64",
+            MAX(m.created_at) AS "latest_at!: _"
+        FROM user_message_metadata meta
+        JOIN messages m ON m.id = meta.message_id
+        JOIN conversations conv ON conv.id = m.conversation_id
+        JOIN conversation_participants cp ON cp.conversation_id = m.conversation_id AND cp.user_id = meta.user_id
+        WHERE meta.user_id = ?1 AND meta.category = ?2
         AND (cp.last_read_at IS NULL OR DATETIME(m.created_at) > DATETIME(cp.last_read_at))
-        ORDER BY DATETIME(m.created_at) DESC
-        LIMIT 100
+        GROUP BY m.conversation_id
+        ORDER BY MAX(DATETIME(m.created_at)) DESC
+        "#,
+        user_id,
+        MessageCategory::URGENT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // `text` holds the raw post content until it's reduced to its text parts below
+    let mut delegate_posts = sqlx::query_as!(
+        DelegatePost,
+        r#"
+        SELECT p.id AS "post_id: _", p.created_by AS "created_by: _", u.username AS "created_by_username", p.content AS "text", p.created_at AS "created_at: _"
+        FROM posts p
+        JOIN users u ON u.id = p.created_by
+        WHERE p.user_id = ?1 AND p.created_by != ?1
+        AND DATETIME(p.created_at) >= DATETIME(?2) AND DATETIME(p.created_at) < DATETIME(?3)
+        ORDER BY DATETIME(p.created_at) ASC
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+    for post in &mut delegate_posts {
+        post.text = message_text(&post.text);
+    }
+
+    let agent_replies = sqlx::query_as!(
+        AgentReplies,
+        r#"
+        SELECT m.conversation_id AS "conversation_id: _", conv.title AS "conversation_title", COUNT(*) AS "replies!: i64"
+        FROM messages m
+        JOIN conversations conv ON conv.id = m.conversation_id
+        WHERE m.sender_id = ?1 AND m.agent_authored
+        AND DATETIME(m.created_at) >= DATETIME(?2) AND DATETIME(m.created_at) < DATETIME(?3)
+        GROUP BY m.conversation_id
+        ORDER BY COUNT(*) DESC
         "#,
         user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(DigestStats {
+        messages_received,
+        categories,
+        unread_urgent_threads,
+        delegate_posts,
+        agent_replies,
+    })
+}
+
+/// Save a digest. Returns `None` if the scheduler already wrote one for the same user and period.
+pub async fn create_digest(pool: &SqlitePool, digest: &NewDigest) -> Result<Option<Digest>> {
+    let id = Uuid::new_v4();
+    let stats = Json(&digest.stats);
+    Ok(sqlx::query_as!(
+        Digest,
+        r#"
+        INSERT OR IGNORE INTO digests (id, user_id, period, period_start, period_end, scheduled, stats, summary, prompt_template_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", user_id AS "user_id: _", period AS "period: _", period_start AS "period_start: _", period_end AS "period_end: _", scheduled, stats AS "stats: Json<DigestStats>", summary, prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _"
+        "#,
+        id,
+        digest.user_id,
+        digest.period,
+        digest.period_start,
+        digest.period_end,
+        digest.scheduled,
+        stats,
+        digest.summary,
+        digest.prompt_template_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn get_digest(pool: &SqlitePool, user_id: Uuid, id: Uuid) -> Result<Digest> {
+    let Some(digest) = sqlx::query_as!(
+        Digest,
+        r#"
+        SELECT id AS "id: _", user_id AS "user_id: _", period AS "period: _", period_start AS "period_start: _", period_end AS "period_end: _", scheduled, stats AS "stats: Json<DigestStats>", summary, prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _"
+        FROM digests
+        WHERE id = ? AND user_id = ?
+        "#,
+        id,
         user_id
     )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Digest not found!".into(),
+        )));
+    };
+    Ok(digest)
+}
+
+pub async fn get_user_digests(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    page: &PageQuery,
+) -> Result<Page<Digest>> {
+    let bounds = page.bounds();
+    let digests = sqlx::query_as!(
+        Digest,
+        r#"
+        SELECT id AS "id: _", user_id AS "user_id: _", period AS "period: _", period_start AS "period_start: _", period_end AS "period_end: _", scheduled, stats AS "stats: Json<DigestStats>", summary, prompt_template_id AS "prompt_template_id: _", created_at AS "created_at: _"
+        FROM digests
+        WHERE user_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), id) > (DATETIME(?4), ?5))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN id END ASC,
+            DATETIME(created_at) DESC,
+            id DESC
+        LIMIT ?7
+        "#,
+        user_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch
+    )
     .fetch_all(pool)
     .await?;
-    
-    Ok(messages)
+
+    Ok(Page::new(digests, page))
+}
+
+/// Users who existed before `period_end` but don't have a scheduled digest for the period ending then.
+pub async fn get_users_missing_digest(
+    pool: &SqlitePool,
+    period: DigestPeriod,
+    period_end: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<Uuid>> {
+    Ok(sqlx::query_scalar!(
+        r#"
+        SELECT u.id AS "id: Uuid"
+        FROM users u
+        WHERE DATETIME(u.created_at) < DATETIME(?2)
+        AND NOT EXISTS (
+            SELECT 1 FROM digests d
+            WHERE d.user_id = u.id AND d.scheduled AND d.period = ?1 AND DATETIME(d.period_end) = DATETIME(?2)
+        )
+        LIMIT ?3
+        "#,
+        period,
+        period_end,
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+// ====== Audit Log Functions ======
+
+/// Something a delegate or agent did for a user, or a change to who may act for them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum AuditAction {
+    /// A delegate sent a message as the principal
+    DelegatedMessageSent,
+    /// A delegate wrote a post as the principal, it waits for their approval
+    DelegatedPostDrafted,
+    /// A post a delegate wrote as the principal was published
+    DelegatedPostPublished,
+    /// A delegate deleted one of the principal's posts
+    DelegatedPostDeleted,
+    /// A delegate commented on a post as the principal
+    DelegatedCommentCreated,
+    /// The principal let someone act for them
+    DelegationGranted,
+    /// The principal changed what someone may do for them
+    DelegationUpdated,
+    /// The principal stopped someone from acting for them
+    DelegationRevoked,
+    /// The categorizer sorted a message into one of the principal's categories
+    MessageCategorized,
+    /// The DM responder replied as the principal
+    AgentReplySent,
+    /// An agent wrote a message or post that waits for the principal's approval
+    AgentDraftCreated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum AuditTarget {
+    Message,
+    Post,
+    Comment,
+    Draft,
+    User,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: Uuid,
+    /// The delegate who acted, or the principal themselves for their own agents and delegations
+    pub actor_id: Uuid,
+    /// The agent that acted for the actor, unset if a person did
+    pub agent: Option<AgentRole>,
+    /// The user it was done for
+    pub principal_id: Uuid,
+    pub action: AuditAction,
+    pub target_type: AuditTarget,
+    pub target_id: Uuid,
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Json<serde_json::Value>>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn create_audit_entry(
+    executor: impl SqliteExecutor<'_>,
+    entry: &NewAuditEntry,
+) -> Result<AuditEntry> {
+    let id = Uuid::new_v4();
+    let details = entry.details.as_ref().map(Json);
+    Ok(sqlx::query_as!(
+        AuditEntry,
+        r#"
+        INSERT INTO audit_log (id, actor_id, agent, principal_id, action, target_type, target_id, details)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id: _", actor_id AS "actor_id: _", agent AS "agent: _", principal_id AS "principal_id: _", action AS "action: _", target_type AS "target_type: _", target_id AS "target_id: _", details AS "details: Json<serde_json::Value>", created_at AS "created_at: _"
+        "#,
+        id,
+        entry.actor_id,
+        entry.agent,
+        entry.principal_id,
+        entry.action,
+        entry.target_type,
+        entry.target_id,
+        details
+    )
+    .fetch_one(executor)
+    .await?)
+}
+
+/// The audit log of `principal_id`, newest first, narrowed down by `filter`.
+pub async fn get_audit_entries(
+    pool: &SqlitePool,
+    principal_id: Uuid,
+    filter: &AuditFilter,
+    page: &PageQuery,
+) -> Result<Page<AuditEntry>> {
+    let bounds = page.bounds();
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT id AS "id: _", actor_id AS "actor_id: _", agent AS "agent: _", principal_id AS "principal_id: _", action AS "action: _", target_type AS "target_type: _", target_id AS "target_id: _", details AS "details: Json<serde_json::Value>", created_at AS "created_at: _"
+        FROM audit_log
+        WHERE principal_id = ?1
+        AND (?2 IS NULL OR (DATETIME(created_at), id) < (DATETIME(?2), ?3))
+        AND (?4 IS NULL OR (DATETIME(created_at), id) > (DATETIME(?4), ?5))
+        AND (?8 IS NULL OR actor_id = ?8)
+        AND (?9 IS NULL OR action = ?9)
+        AND (?10 IS NULL OR agent = ?10)
+        AND (?11 IS NULL OR (agent IS NOT NULL) = ?11)
+        AND (?12 IS NULL OR target_id = ?12)
+        AND (?13 IS NULL OR DATETIME(created_at) >= DATETIME(?13))
+        AND (?14 IS NULL OR DATETIME(created_at) < DATETIME(?14))
+        ORDER BY
+            CASE WHEN ?6 THEN DATETIME(created_at) END ASC,
+            CASE WHEN ?6 THEN id END ASC,
+            DATETIME(created_at) DESC,
+            id DESC
+        LIMIT ?7
+        "#,
+        principal_id,
+        bounds.before_at,
+        bounds.before_id,
+        bounds.after_at,
+        bounds.after_id,
+        bounds.ascending,
+        bounds.fetch,
+        filter.actor_id,
+        filter.action,
+        filter.agent,
+        filter.by_agent,
+        filter.target_id,
+        filter.since,
+        filter.until
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Page::new(entries, page))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn hours(days: &[Weekday], start: u32, end: u32, utc_offset_minutes: i32) -> ActiveHours {
+        ActiveHours {
+            days: days.to_vec(),
+            start: NaiveTime::from_hms_opt(start, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(end, 0, 0).unwrap(),
+            utc_offset_minutes,
+        }
+    }
+
+    /// A time in May 2024, which started on a Wednesday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn active_hours_during_the_day() {
+        let business = hours(&[Weekday::Mon, Weekday::Tue], 9, 17, 0);
+        // Monday the 13th
+        assert!(business.contains(at(13, 9, 0)));
+        assert!(business.contains(at(13, 16, 59)));
+        assert!(!business.contains(at(13, 8, 59)));
+        assert!(!business.contains(at(13, 17, 0)));
+        // Wednesday the 15th
+        assert!(!business.contains(at(15, 12, 0)));
+    }
+
+    #[test]
+    fn active_hours_past_midnight() {
+        let night = hours(&[Weekday::Fri], 22, 2, 0);
+        // Friday the 17th and the night into Saturday
+        assert!(night.contains(at(17, 23, 0)));
+        assert!(night.contains(at(18, 1, 59)));
+        assert!(!night.contains(at(18, 2, 0)));
+        assert!(!night.contains(at(17, 1, 0)));
+        assert!(!night.contains(at(18, 23, 0)));
+    }
+
+    #[test]
+    fn active_hours_in_local_time() {
+        // 9 to 17 in New York in summer is 13 to 21 UTC
+        let new_york = hours(&[Weekday::Mon], 9, 17, -240);
+        assert!(new_york.contains(at(13, 13, 0)));
+        assert!(new_york.contains(at(13, 20, 59)));
+        assert!(!new_york.contains(at(13, 9, 0)));
+        // Midnight UTC on Tuesday is Monday evening in New York, after the window closed
+        assert!(!new_york.contains(at(14, 0, 0)));
+
+        let invalid_offset = hours(&[Weekday::Mon], 0, 23, 24 * 60);
+        assert!(!invalid_offset.contains(at(13, 12, 0)));
+    }
 }
@@ -4,7 +4,7 @@ use std::ops::Deref;
 
 use axum::{
     Json,
-    extract::rejection::JsonRejection,
+    extract::{multipart::MultipartError, rejection::JsonRejection},
     http::{HeaderMap, StatusCode, header::SET_COOKIE},
     response::{IntoResponse, Response},
 };
@@ -64,6 +64,10 @@ pub enum AppError {
     PromptError(LossyError<PromptError>),
     #[error("Extraction error: {0}")]
     ExtractionError(LossyError<ExtractionError>),
+    #[error("Agents are disabled: {0}")]
+    KillSwitchEngaged(String),
+    #[error("LLM backend error: {0}")]
+    LlmBackendError(String),
 }
 
 /// A JSON response for errors that includes the error type and message
@@ -89,6 +93,8 @@ impl AppError {
             AppError::UserError(_) => "User",
             AppError::PromptError(_) => "PromptError",
             AppError::ExtractionError(_) => "ExtractionError",
+            AppError::KillSwitchEngaged(_) => "KillSwitchEngaged",
+            AppError::LlmBackendError(_) => "LlmBackendError",
         }
     }
 }
@@ -114,6 +120,8 @@ impl IntoResponse for AppError {
             }
             AppError::PromptError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::ExtractionError(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
+            AppError::KillSwitchEngaged(_) => (StatusCode::LOCKED, self.to_string()),
+            AppError::LlmBackendError(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
         };
         // Return a JSON response with the error type and message.
         (status, headers, Json(self)).into_response()
@@ -146,6 +154,19 @@ impl_from_error!(eyre::Error => Generic);
 impl_from_error!(PromptError => PromptError);
 impl_from_error!(ExtractionError => ExtractionError);
 impl_from_error!(std::io::Error);
+impl_from_error!(argon2::password_hash::Error);
 impl_from_error!(sqlx::migrate::MigrateError);
 
+impl From<reqwest::Error> for AppError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::LlmBackendError(err.to_string())
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(err: MultipartError) -> Self {
+        Self::UserError((LossyError(err.status()), err.body_text()))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, AppError>;
@@ -1,6 +1,9 @@
 use crate::{
     auth::SessionAuth,
-    entities::{ChatMessage, Conversation, MessageCategory, Post, get_conversation_participants},
+    entities::{
+        ApiScope, CategorizationSource, ChatMessage, Comment, Conversation, Digest, Draft,
+        MessageCategory, PinnedMessage, Post, get_conversation_participants,
+    },
     error::Result,
     state::{AppState, ClientMap},
 };
@@ -52,10 +55,54 @@ pub enum SseEvent {
         category: MessageCategory,
         /// AI-generated reasoning for the categorization
         reasoning: String,
+        /// Whether the agent, a rule or the user themselves set the category
+        source: CategorizationSource,
+    },
+
+    /// A message was pinned for the user, by them or because it was categorized as urgent
+    MessagePinned(PinnedMessage),
+
+    /// A message was unpinned for the user
+    MessageUnpinned {
+        /// The ID of the message that was unpinned
+        message_id: Uuid,
     },
 
+    /// An urgent message is still unread after the time the user's escalation policy allows
+    UrgentMessageEscalated(PinnedMessage),
+
     /// A new post was created
     NewPost(Post),
+
+    /// Someone liked one of the user's posts
+    PostLiked {
+        /// The ID of the post that was liked
+        post_id: Uuid,
+        /// Who liked it
+        user_id: Uuid,
+    },
+
+    /// Someone commented on one of the user's posts
+    NewComment(Comment),
+
+    /// An agent or delegate drafted a message or post that waits for the owner's approval
+    DraftCreated(Draft),
+
+    /// A draft was approved or rejected by its owner
+    DraftResolved(Draft),
+
+    /// A daily, weekly or on demand digest of the user's activity was written
+    DigestCreated(Digest),
+
+    /// An agent kill switch was engaged or released
+    KillSwitchChanged {
+        /// Whether this is the global switch rather than the user's own
+        global: bool,
+        /// Whether agents are now stopped by this switch
+        engaged: bool,
+        /// Who flipped the switch
+        updated_by: Uuid,
+    },
 }
 
 /// Example SSE event structure that will be sent to clients.
@@ -87,7 +134,16 @@ pub struct SseEventExample {
 /// - `newConversation`: User was added to a new conversation
 /// - `editConversation`: Conversation details were updated
 /// - `usersAddedToConversation`: New users joined a conversation
-/// - `messageCategorized`: A message was categorized for the user
+/// - `messageCategorized`: A message was categorized for the user, or the user corrected its category
+/// - `messagePinned`: A message was pinned, by the user or because it is urgent
+/// - `messageUnpinned`: A message was unpinned
+/// - `urgentMessageEscalated`: An urgent message is still unread, see the user's escalation policy
+/// - `draftCreated`: A message or post is waiting for the user's approval
+/// - `draftResolved`: A draft was approved or rejected
+/// - `digestCreated`: A digest of the user's activity is ready
+/// - `postLiked`: Someone liked one of the user's posts
+/// - `newComment`: Someone commented on one of the user's posts
+/// - `killSwitchChanged`: The user's or the global agent kill switch was flipped
 #[utoipa::path(
     get,
     path = "/api/events",
@@ -102,6 +158,7 @@ pub struct SseEventExample {
                  "conversationId": "550e8400-e29b-41d4-a716-446655440001",
                  "senderId": "550e8400-e29b-41d4-a716-446655440002",
                  "content": "Hello, world!",
+                 "agentAuthored": false,
                  "createdAt": "2024-01-01T00:00:00Z",
                  "updatedAt": "2024-01-01T00:00:00Z"
              }
@@ -116,7 +173,8 @@ pub struct SseEventExample {
 pub async fn events_handler(
     session: SessionAuth,
     State(state): State<AppState>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse> {
+    session.require_scope(ApiScope::MessagesRead)?;
     let user_id = session.0.id;
 
     // Create a new broadcast channel for this user.
@@ -136,8 +194,8 @@ pub async fn events_handler(
         .map(Ok::<_, Infallible>);
 
     // Return the SSE response, keeping the connection alive.
-    Sse::new(stream)
-        .keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(15)))
+    Ok(Sse::new(stream)
+        .keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(15))))
 }
 
 /// A helper function to broadcast an event to a list of users.
@@ -153,6 +211,14 @@ pub async fn broadcast_event(clients: &ClientMap, recipients: &[Uuid], event: &S
     }
 }
 
+/// A helper function to broadcast an event to every connected user.
+pub async fn broadcast_all(clients: &ClientMap, event: &SseEvent) {
+    let guard = clients.guard();
+    for (_, tx) in clients.iter(&guard) {
+        let _ = tx.send(event.clone());
+    }
+}
+
 /// Documentation module for SSE event examples
 pub mod sse_examples {
     use super::*;
@@ -167,6 +233,7 @@ pub mod sse_examples {
                 "conversationId": "conv_456",
                 "senderId": "user_789",
                 "content": "[{\"type\":\"text\",\"content\":\"Hello!\"}]",
+                "agentAuthored": false,
                 "createdAt": "2024-01-01T12:00:00Z",
                 "updatedAt": "2024-01-01T12:00:00Z"
             }
@@ -180,7 +247,8 @@ pub mod sse_examples {
             "data": {
                 "messageId": "msg_123",
                 "category": "Important",
-                "reasoning": "This message contains a direct question requiring a response."
+                "reasoning": "This message contains a direct question requiring a response.",
+                "source": "agent"
             }
         })
     }
@@ -0,0 +1,125 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::SessionAuth,
+    entities::{ApiScope, GLOBAL_KILL_SWITCH, KillSwitch, get_kill_switch, set_kill_switch},
+    error::{ErrorResponse, Result},
+    events::{SseEvent, broadcast_all, broadcast_event},
+    state::AppState,
+};
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetKillSwitchRequest {
+    /// `true` to stop all agent activity, `false` to let agents run again
+    pub engaged: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KillSwitchStatus {
+    /// The current user's own kill switch
+    pub user: KillSwitch,
+    /// The admin controlled switch that stops agents for everyone
+    pub global: KillSwitch,
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/agents/kill_switch",
+    description = "Get the state of the current user's and the global agent kill switch",
+    responses(
+        (status = OK, description = "Kill switch state", body = KillSwitchStatus),
+    )
+)]
+pub async fn get_kill_switch_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    let status = KillSwitchStatus {
+        user: get_kill_switch(&state.pool, session.0.id).await?,
+        global: get_kill_switch(&state.pool, GLOBAL_KILL_SWITCH).await?,
+    };
+    Ok((StatusCode::OK, Json(status)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/agents/kill_switch",
+    description = "Engage or release the current user's agent kill switch. \
+        Engaging it cancels any agent work already running for the user.",
+    request_body = SetKillSwitchRequest,
+    responses(
+        (status = OK, description = "Kill switch updated", body = KillSwitch),
+    )
+)]
+pub async fn set_kill_switch_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<SetKillSwitchRequest>,
+) -> Result<Response> {
+    // Deliberately no scope check, anything acting for the user should be able to stop agents
+    let user_id = session.0.id;
+    let switch = set_kill_switch(&state.pool, user_id, payload.engaged, user_id).await?;
+    if switch.engaged {
+        state.agent_tasks.cancel_user(user_id);
+    }
+
+    let event = SseEvent::KillSwitchChanged {
+        global: false,
+        engaged: switch.engaged,
+        updated_by: user_id,
+    };
+    broadcast_event(&state.clients, &[user_id], &event).await;
+
+    Ok((StatusCode::OK, Json(switch)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/agents/kill_switch/global",
+    description = "Engage or release the global agent kill switch. Only available to admins.",
+    request_body = SetKillSwitchRequest,
+    responses(
+        (status = OK, description = "Kill switch updated", body = KillSwitch),
+        (status = FORBIDDEN, description = "User is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn set_global_kill_switch_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<SetKillSwitchRequest>,
+) -> Result<Response> {
+    session.require_admin()?;
+    let switch = set_kill_switch(
+        &state.pool,
+        GLOBAL_KILL_SWITCH,
+        payload.engaged,
+        session.0.id,
+    )
+    .await?;
+    if switch.engaged {
+        state.agent_tasks.cancel_all();
+    }
+
+    let event = SseEvent::KillSwitchChanged {
+        global: true,
+        engaged: switch.engaged,
+        updated_by: session.0.id,
+    };
+    broadcast_all(&state.clients, &event).await;
+
+    Ok((StatusCode::OK, Json(switch)).into_response())
+}
@@ -17,6 +17,7 @@ use axum::{
             SET_COOKIE,
         },
     },
+    middleware,
 };
 use color_eyre::eyre::eyre;
 use lazy_regex::{Lazy, lazy_regex};
@@ -38,19 +39,34 @@ use tracing::{Level, info, warn};
 use url::Url;
 use utoipa::{
     Modify, OpenApi,
-    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
 };
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_swagger_ui::SwaggerUi;
 
 mod agents;
+mod audit;
 mod auth;
+mod categories;
+mod digests;
+mod drafts;
 mod entities;
 mod error;
 mod events;
+mod kill_switch;
+mod llm;
+mod media;
 mod messaging;
+mod pagination;
+mod pins;
 mod posts;
+mod prompts;
+mod responder;
+mod rules;
 mod state;
+#[cfg(test)]
+mod testing;
+mod tokens;
 mod users;
 mod utoipa_compat;
 
@@ -108,6 +124,22 @@ pub static AVATAR_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     path
 });
 
+/// Largest file that can be uploaded, in bytes.
+/// Set with `CLONEOPS_MAX_UPLOAD_BYTES`, defaults to 25 MiB.
+pub static MAX_UPLOAD_BYTES: LazyLock<u64> = LazyLock::new(|| {
+    let default = 25 * 1024 * 1024;
+    match std::env::var("CLONEOPS_MAX_UPLOAD_BYTES") {
+        Ok(bytes) => match bytes.parse::<u64>() {
+            Ok(bytes) if bytes > 0 => bytes,
+            _ => {
+                warn!("Invalid CLONEOPS_MAX_UPLOAD_BYTES `{bytes}`, using the default of 25 MiB");
+                default
+            }
+        },
+        Err(_) => default,
+    }
+});
+
 static ORIGIN_REGEX: Lazy<Regex> = lazy_regex!(r"^https?://localhost:\d+/?$");
 
 /// Website host
@@ -115,18 +147,88 @@ pub static HOST: LazyLock<String> = LazyLock::new(|| {
     std::env::var("CLONEOPS_HOST").unwrap_or("cloneops.cyanistic.com".to_string())
 });
 
+/// Usernames (case-insensitive) of the users allowed to use admin endpoints,
+/// such as the global agent kill switch.
+/// Set with `CLONEOPS_ADMINS` as a comma separated list.
+pub static ADMIN_USERNAMES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("CLONEOPS_ADMINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+});
+
+/// How long a session stays valid without being renewed.
+/// Set with `CLONEOPS_SESSION_TTL_SECS`, defaults to 7 days.
+pub static SESSION_TTL: LazyLock<chrono::Duration> = LazyLock::new(|| {
+    let default = chrono::Duration::days(7);
+    match std::env::var("CLONEOPS_SESSION_TTL_SECS") {
+        Ok(secs) => match secs.parse::<i64>() {
+            Ok(secs) if secs > 0 => chrono::Duration::seconds(secs),
+            _ => {
+                warn!("Invalid CLONEOPS_SESSION_TTL_SECS `{secs}`, using the default of 7 days");
+                default
+            }
+        },
+        Err(_) => default,
+    }
+});
+
+/// Share of a session's lifetime (between 0 and 1) that has to pass before
+/// activity on the session pushes its expiry back out to a full `SESSION_TTL`.
+/// Set with `CLONEOPS_SESSION_RENEW_THRESHOLD`, defaults to 0.5.
+pub static SESSION_RENEW_THRESHOLD: LazyLock<f64> = LazyLock::new(|| {
+    let default = 0.5;
+    match std::env::var("CLONEOPS_SESSION_RENEW_THRESHOLD") {
+        Ok(threshold) => match threshold.parse::<f64>() {
+            Ok(threshold) if (0.0..=1.0).contains(&threshold) => threshold,
+            _ => {
+                warn!(
+                    "Invalid CLONEOPS_SESSION_RENEW_THRESHOLD `{threshold}`, using the default of {default}"
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+});
+
 #[derive(OpenApi)]
 #[openapi(
         modifiers(&SecurityAddon),
         paths(
             users::register,
             users::login,
+            users::logout,
+            users::list_sessions_handler,
+            users::revoke_session_handler,
             users::get_profile,
+            users::update_profile_handler,
+            tokens::create_api_token_handler,
+            tokens::list_api_tokens_handler,
+            tokens::revoke_api_token_handler,
             users::search_users_handler,
             users::get_user_handler,
             users::delete_user_handler,
+            users::follow_user_handler,
+            users::unfollow_user_handler,
+            users::get_followers_handler,
+            users::get_following_handler,
             agents::enhance_prompt,
             agents::research_prompt,
+            agents::enhance_prompt_stream,
+            agents::research_prompt_stream,
+            agents::caption_assist,
+            kill_switch::get_kill_switch_handler,
+            kill_switch::set_kill_switch_handler,
+            kill_switch::set_global_kill_switch_handler,
+            prompts::list_prompt_templates_handler,
+            prompts::get_prompt_template_versions_handler,
+            prompts::update_prompt_template_handler,
+            prompts::rollback_prompt_template_handler,
+            responder::list_responder_policies_handler,
+            responder::set_responder_policy_handler,
             messaging::create_conversation_handler,
             messaging::list_conversations_handler,
             messaging::send_message_handler,
@@ -135,17 +237,53 @@ pub static HOST: LazyLock<String> = LazyLock::new(|| {
             messaging::get_conversation_handler,
             messaging::get_messages_handler,
             messaging::get_categorized_messages_handler,
+            messaging::correct_category_handler,
+            messaging::get_category_corrections_handler,
             messaging::mark_conversation_read_handler,
             messaging::get_unread_messages_handler,
             messaging::get_messages_with_status_handler,
+            pins::get_pinned_messages_handler,
+            pins::pin_message_handler,
+            pins::unpin_message_handler,
+            pins::get_escalation_policy_handler,
+            pins::set_escalation_policy_handler,
+            digests::list_digests_handler,
+            digests::create_digest_handler,
+            digests::get_digest_handler,
+            drafts::list_drafts_handler,
+            drafts::edit_draft_handler,
+            drafts::approve_draft_handler,
+            drafts::reject_draft_handler,
+            categories::list_categories_handler,
+            categories::create_category_handler,
+            categories::update_category_handler,
+            categories::delete_category_handler,
+            rules::list_rules_handler,
+            rules::create_rule_handler,
+            rules::update_rule_handler,
+            rules::delete_rule_handler,
+            media::upload_media_handler,
+            media::get_media_handler,
+            media::upload_avatar_handler,
+            media::get_avatar_handler,
             posts::create_post_handler,
             posts::get_posts_handler,
             posts::delete_post_handler,
+            posts::get_scheduled_posts_handler,
+            posts::cancel_scheduled_post_handler,
+            posts::like_post_handler,
+            posts::unlike_post_handler,
+            posts::get_comments_handler,
+            posts::create_comment_handler,
+            posts::reshare_post_handler,
             posts::create_delegation_handler,
             posts::get_delegations_handler,
             posts::get_received_delegations_handler,
+            posts::update_delegation_handler,
             posts::revoke_delegation_handler,
             posts::get_feed_handler,
+            audit::get_audit_log_handler,
+            audit::export_audit_log_handler,
             events::events_handler,
         ),
         components(
@@ -153,22 +291,68 @@ pub static HOST: LazyLock<String> = LazyLock::new(|| {
                 events::SseEvent,
                 events::SseEventExample,
                 entities::MessageCategory,
+                entities::Category,
                 entities::ChatMessage,
                 entities::Conversation,
                 entities::ConversationWithParticipants,
                 entities::ChatMessageWithMetadata,
+                entities::CategorizationSource,
+                entities::CategoryCorrection,
                 entities::Post,
+                entities::FeedPost,
+                entities::Comment,
+                entities::Media,
+                entities::ProfileLink,
                 entities::Delegation,
+                entities::ActiveHours,
+                entities::AuditAction,
+                entities::AuditTarget,
+                entities::AuditEntry,
+                llm::AgentRole,
                 entities::UnreadMessage,
-                posts::FeedResponse,
+                entities::PinReason,
+                entities::PinnedMessage,
+                entities::EscalationPolicy,
+                entities::DigestPeriod,
+                entities::Digest,
+                entities::DigestStats,
+                entities::CategoryCount,
+                entities::UrgentThread,
+                entities::DelegatePost,
+                entities::AgentReplies,
+                entities::RuleCondition,
+                entities::CategorizationRule,
+                entities::ResponderAction,
+                entities::ResponderPolicy,
+                entities::Draft,
+                entities::DraftKind,
+                entities::DraftSource,
+                entities::DraftStatus,
+                entities::PromptTemplateName,
+                entities::PromptTemplate,
+                entities::ScheduledPost,
+                entities::ScheduledPostStatus,
+                posts::PendingPost,
                 messaging::MessageWithReadStatus,
+                messaging::CorrectCategoryRequest,
+                digests::CreateDigestRequest,
             )
         ),
         tags(
             (name = "users", description = "User related operations"),
+            (name = "tokens", description = "Personal API tokens for scripts and external agents"),
             (name = "agents", description = "Agent related operations"),
+            (name = "kill_switch", description = "Emergency stop for all agent activity"),
+            (name = "responder", description = "DM responder that replies to categorized messages"),
             (name = "messaging", description = "Messaging and conversation operations"),
+            (name = "pins", description = "Pinned messages and escalation of unread urgent ones"),
+            (name = "digests", description = "Daily, weekly and on demand summaries of inbox, agent and delegate activity"),
+            (name = "drafts", description = "Messages and posts waiting for their owner's approval"),
+            (name = "categories", description = "User defined categories that incoming messages are sorted into"),
+            (name = "rules", description = "User defined rules that categorize messages before the LLM"),
+            (name = "media", description = "Uploaded images and videos for posts, messages and avatars"),
             (name = "posts", description = "Social media posts and delegation management"),
+            (name = "audit", description = "Append-only log of what delegates and agents did for a user"),
             (name = "events", description = "Real-time event streaming via Server-Sent Events (SSE)"),
         )
     )]
@@ -182,7 +366,20 @@ impl Modify for SecurityAddon {
             components.add_security_scheme(
                 "lokr_session_cookie",
                 SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("session"))),
-            )
+            );
+            components.add_security_scheme(
+                "api_token",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .bearer_format("cloneops_<token>")
+                        .description(Some(
+                            "Personal API token created through `/api/tokens`. \
+                            Limited to the scopes it was created with.",
+                        ))
+                        .build(),
+                ),
+            );
         }
     }
 }
@@ -248,19 +445,60 @@ pub async fn start_server(pool: SqlitePool) -> Result<()> {
             HeaderValue::from_static("application/octet-stream"),
         );
 
-    let state = AppState::new(pool.clone());
+    let llm = llm::LlmRegistry::from_env()?;
+    info!("Using LLM backends: {llm:?}");
+    let state = AppState::new(pool.clone(), llm);
+
+    // Clean up expired sessions in the background
+    tokio::spawn(auth::sweep_expired_sessions(pool.clone()));
+    // Publish scheduled posts once they're due
+    tokio::spawn(posts::run_post_scheduler(state.clone()));
+    // Remind users of urgent messages they haven't read
+    tokio::spawn(pins::run_escalation_worker(state.clone()));
+    // Write daily and weekly digests once the day or week is over
+    tokio::spawn(digests::run_digest_scheduler(state.clone()));
 
     // Setup the router along with the OpenApi documentation router
     // for easy docs generation.
     let (api_router, open_api): (Router, _) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .routes(routes!(users::register))
         .routes(routes!(users::login))
-        .routes(routes!(users::get_profile))
+        .routes(routes!(users::logout))
+        .routes(routes!(users::list_sessions_handler))
+        .routes(routes!(users::revoke_session_handler))
+        .routes(routes!(users::get_profile, users::update_profile_handler))
+        .routes(routes!(
+            tokens::create_api_token_handler,
+            tokens::list_api_tokens_handler
+        ))
+        .routes(routes!(tokens::revoke_api_token_handler))
         .routes(routes!(users::search_users_handler))
         .routes(routes!(users::get_user_handler))
         .routes(routes!(users::delete_user_handler))
+        .routes(routes!(
+            users::follow_user_handler,
+            users::unfollow_user_handler
+        ))
+        .routes(routes!(users::get_followers_handler))
+        .routes(routes!(users::get_following_handler))
         .routes(routes!(agents::enhance_prompt))
         .routes(routes!(agents::research_prompt))
+        .routes(routes!(agents::enhance_prompt_stream))
+        .routes(routes!(agents::research_prompt_stream))
+        .routes(routes!(agents::caption_assist))
+        .routes(routes!(
+            kill_switch::get_kill_switch_handler,
+            kill_switch::set_kill_switch_handler
+        ))
+        .routes(routes!(kill_switch::set_global_kill_switch_handler))
+        .routes(routes!(prompts::list_prompt_templates_handler))
+        .routes(routes!(
+            prompts::get_prompt_template_versions_handler,
+            prompts::update_prompt_template_handler
+        ))
+        .routes(routes!(prompts::rollback_prompt_template_handler))
+        .routes(routes!(responder::list_responder_policies_handler))
+        .routes(routes!(responder::set_responder_policy_handler))
         .routes(routes!(messaging::create_conversation_handler))
         .routes(routes!(messaging::list_conversations_handler))
         .routes(routes!(messaging::send_message_handler))
@@ -269,18 +507,78 @@ pub async fn start_server(pool: SqlitePool) -> Result<()> {
         .routes(routes!(messaging::get_conversation_handler))
         .routes(routes!(messaging::get_messages_handler))
         .routes(routes!(messaging::get_categorized_messages_handler))
+        .routes(routes!(messaging::correct_category_handler))
+        .routes(routes!(messaging::get_category_corrections_handler))
         .routes(routes!(messaging::mark_conversation_read_handler))
         .routes(routes!(messaging::get_unread_messages_handler))
         .routes(routes!(messaging::get_messages_with_status_handler))
+        .routes(routes!(pins::get_pinned_messages_handler))
+        .routes(routes!(
+            pins::pin_message_handler,
+            pins::unpin_message_handler
+        ))
+        .routes(routes!(
+            pins::get_escalation_policy_handler,
+            pins::set_escalation_policy_handler
+        ))
+        .routes(routes!(
+            digests::list_digests_handler,
+            digests::create_digest_handler
+        ))
+        .routes(routes!(digests::get_digest_handler))
+        .routes(routes!(drafts::list_drafts_handler))
+        .routes(routes!(drafts::edit_draft_handler))
+        .routes(routes!(drafts::approve_draft_handler))
+        .routes(routes!(drafts::reject_draft_handler))
+        .routes(routes!(
+            categories::list_categories_handler,
+            categories::create_category_handler
+        ))
+        .routes(routes!(
+            categories::update_category_handler,
+            categories::delete_category_handler
+        ))
+        .routes(routes!(
+            rules::list_rules_handler,
+            rules::create_rule_handler
+        ))
+        .routes(routes!(
+            rules::update_rule_handler,
+            rules::delete_rule_handler
+        ))
+        .routes(routes!(media::upload_media_handler))
+        .routes(routes!(media::get_media_handler))
+        .routes(routes!(media::upload_avatar_handler))
+        .routes(routes!(media::get_avatar_handler))
         .routes(routes!(posts::create_post_handler))
         .routes(routes!(posts::get_posts_handler))
         .routes(routes!(posts::delete_post_handler))
+        .routes(routes!(posts::get_scheduled_posts_handler))
+        .routes(routes!(posts::cancel_scheduled_post_handler))
+        .routes(routes!(
+            posts::like_post_handler,
+            posts::unlike_post_handler
+        ))
+        .routes(routes!(
+            posts::get_comments_handler,
+            posts::create_comment_handler
+        ))
+        .routes(routes!(posts::reshare_post_handler))
         .routes(routes!(posts::create_delegation_handler))
         .routes(routes!(posts::get_delegations_handler))
         .routes(routes!(posts::get_received_delegations_handler))
-        .routes(routes!(posts::revoke_delegation_handler))
+        .routes(routes!(
+            posts::update_delegation_handler,
+            posts::revoke_delegation_handler
+        ))
         .routes(routes!(posts::get_feed_handler))
+        .routes(routes!(audit::get_audit_log_handler))
+        .routes(routes!(audit::export_audit_log_handler))
         .routes(routes!(events::events_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::session_renewal,
+        ))
         .route_layer(DefaultBodyLimit::max(1_000_000_000))
         .layer(cors)
         .with_state(state)
@@ -348,5 +646,6 @@ pub async fn init_db(db_url: &Url) -> Result<SqlitePool> {
         Err(e) => return Err(e.into()),
         _ => {}
     }
+    prompts::seed_prompt_templates(&pool).await?;
     Ok(pool)
 }
@@ -0,0 +1,782 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+
+use color_eyre::eyre::eyre;
+use futures_util::{
+    Stream, StreamExt as _, TryStreamExt as _,
+    future::BoxFuture,
+    stream::{self, BoxStream},
+};
+use rig::{
+    agent::MultiTurnStreamItem,
+    client::CompletionClient,
+    completion::Prompt,
+    message::{DocumentSourceKind, Message, MimeType, UserContent},
+    providers::gemini::{
+        self,
+        completion::{
+            GEMINI_2_0_FLASH,
+            gemini_api_types::{AdditionalParameters, GenerationConfig},
+        },
+    },
+    streaming::{StreamedAssistantContent, StreamingPrompt},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use tracing::{Level, instrument};
+
+use crate::{
+    entities::MessageCategory,
+    error::{AppError, LossyError, Result},
+};
+
+/// The jobs agents are used for.
+/// Each role can be pointed at a different provider and model.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema,
+)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(rename_all = "camelCase")]
+pub enum AgentRole {
+    Enhancer,
+    Researcher,
+    Categorizer,
+    Responder,
+    Captioner,
+    Summarizer,
+}
+
+impl AgentRole {
+    pub const ALL: [AgentRole; 6] = [
+        AgentRole::Enhancer,
+        AgentRole::Researcher,
+        AgentRole::Categorizer,
+        AgentRole::Responder,
+        AgentRole::Captioner,
+        AgentRole::Summarizer,
+    ];
+
+    /// The prefix of the environment variables that configure this role,
+    /// e.g. `CLONEOPS_ENHANCER_PROVIDER` and `CLONEOPS_ENHANCER_MODEL`.
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            AgentRole::Enhancer => "CLONEOPS_ENHANCER",
+            AgentRole::Researcher => "CLONEOPS_RESEARCHER",
+            AgentRole::Categorizer => "CLONEOPS_CATEGORIZER",
+            AgentRole::Responder => "CLONEOPS_RESPONDER",
+            AgentRole::Captioner => "CLONEOPS_CAPTIONER",
+            AgentRole::Summarizer => "CLONEOPS_SUMMARIZER",
+        }
+    }
+}
+
+/// A single request to a model.
+#[derive(Clone, Debug)]
+pub struct LlmRequest {
+    /// The name of the agent making the request, used for logging
+    pub agent_name: &'static str,
+    /// System instructions to send ahead of the prompt
+    pub preamble: Option<String>,
+    pub prompt: Message,
+    /// The user supplied text the prompt was built from.
+    /// Real models ignore this, the mock backend reacts to it instead of the full prompt.
+    pub input: Option<String>,
+    /// Ask the provider to only reply with a JSON object
+    pub json: bool,
+}
+
+impl LlmRequest {
+    pub fn new(agent_name: &'static str, prompt: impl Into<Message>) -> Self {
+        Self {
+            agent_name,
+            preamble: None,
+            prompt: prompt.into(),
+            input: None,
+            json: false,
+        }
+    }
+
+    pub fn with_input(mut self, input: impl Into<String>) -> Self {
+        self.input = Some(input.into());
+        self
+    }
+}
+
+/// A model's text reply, a piece at a time as it's generated.
+pub type TextStream = BoxStream<'static, Result<String>>;
+
+/// A model provider that agents can send prompts to.
+///
+/// Implementations only need to turn a request into a completion,
+/// structured output is built on top of that by [`LlmBackend::extract`].
+pub trait LlmBackend: Send + Sync {
+    /// A human readable `provider/model` description, used for logging
+    fn describe(&self) -> String;
+
+    /// Send the request and return the model's text reply
+    fn complete(&self, request: LlmRequest) -> BoxFuture<'_, Result<String>>;
+
+    /// Send the request and stream the model's text reply as it's generated.
+    /// Dropping the stream cancels the request.
+    fn stream(&self, request: LlmRequest) -> TextStream;
+}
+
+impl dyn LlmBackend + '_ {
+    /// Prompt the model for a JSON object matching `T`'s schema and parse its reply.
+    #[instrument(skip(self, request), fields(agent = request.agent_name), err(level = Level::WARN))]
+    pub async fn extract<T: JsonSchema + DeserializeOwned>(
+        &self,
+        request: LlmRequest,
+    ) -> Result<T> {
+        let schema = serde_json::to_string_pretty(&schemars::schema_for!(T))?;
+        let instructions = format!(
+            "Respond with only a single JSON object that matches this JSON schema, \
+            without any surrounding text:\n```json\n{schema}\n```"
+        );
+        let preamble = match request.preamble {
+            Some(preamble) => format!("{preamble}\n\n{instructions}"),
+            None => instructions,
+        };
+
+        let reply = self
+            .complete(LlmRequest {
+                preamble: Some(preamble),
+                json: true,
+                ..request
+            })
+            .await?;
+        Ok(serde_json::from_str(strip_code_fence(&reply))?)
+    }
+}
+
+/// Models like to wrap JSON in a markdown code block even when told not to.
+fn strip_code_fence(reply: &str) -> &str {
+    let reply = reply.trim();
+    reply
+        .strip_prefix("```json")
+        .or_else(|| reply.strip_prefix("```"))
+        .and_then(|inner| inner.strip_suffix("```"))
+        .map(str::trim)
+        .unwrap_or(reply)
+}
+
+/// The providers that can back an agent role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LlmProvider {
+    Gemini,
+    /// Any server implementing the OpenAI chat completions API,
+    /// including local model servers like llama.cpp, vLLM or Ollama
+    OpenAi,
+    /// Scripted replies without any network access, for tests and offline development
+    Mock,
+}
+
+impl FromStr for LlmProvider {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "gemini" => Ok(LlmProvider::Gemini),
+            "openai" | "openai-compatible" => Ok(LlmProvider::OpenAi),
+            "mock" => Ok(LlmProvider::Mock),
+            other => Err(AppError::Generic(LossyError(eyre!(
+                "Unknown LLM provider `{other}`, expected `gemini`, `openai` or `mock`"
+            )))),
+        }
+    }
+}
+
+/// The backend used for each agent role, built from the environment:
+///
+/// - `CLONEOPS_LLM_PROVIDER` / `CLONEOPS_LLM_MODEL`: defaults for every role
+/// - `CLONEOPS_<ROLE>_PROVIDER` / `CLONEOPS_<ROLE>_MODEL`: overrides for `ENHANCER`,
+///   `RESEARCHER`, `CATEGORIZER`, `RESPONDER`, `CAPTIONER` or `SUMMARIZER`. The captioner is
+///   sent images, so it needs a model that accepts them.
+/// - `GEMINI_API_KEY`: key for the `gemini` provider
+/// - `CLONEOPS_OPENAI_BASE_URL` / `CLONEOPS_OPENAI_API_KEY`: server and optional key for the
+///   `openai` provider
+/// - `CLONEOPS_MOCK_SCRIPT`: optional path to a JSON [`MockScript`] for the `mock` provider
+pub struct LlmRegistry {
+    enhancer: Arc<dyn LlmBackend>,
+    researcher: Arc<dyn LlmBackend>,
+    categorizer: Arc<dyn LlmBackend>,
+    responder: Arc<dyn LlmBackend>,
+    captioner: Arc<dyn LlmBackend>,
+    summarizer: Arc<dyn LlmBackend>,
+}
+
+impl LlmRegistry {
+    pub fn from_env() -> Result<Self> {
+        let [
+            enhancer,
+            researcher,
+            categorizer,
+            responder,
+            captioner,
+            summarizer,
+        ] = AgentRole::ALL.map(backend_from_env);
+        Ok(Self {
+            enhancer: enhancer?,
+            researcher: researcher?,
+            categorizer: categorizer?,
+            responder: responder?,
+            captioner: captioner?,
+            summarizer: summarizer?,
+        })
+    }
+
+    /// Every agent gets a mock backend following `script`.
+    #[cfg(test)]
+    pub fn mock(script: MockScript) -> Self {
+        let backend =
+            |role| Arc::new(MockBackend::new(role, script.clone())) as Arc<dyn LlmBackend>;
+        Self {
+            enhancer: backend(AgentRole::Enhancer),
+            researcher: backend(AgentRole::Researcher),
+            categorizer: backend(AgentRole::Categorizer),
+            responder: backend(AgentRole::Responder),
+            captioner: backend(AgentRole::Captioner),
+            summarizer: backend(AgentRole::Summarizer),
+        }
+    }
+
+    pub fn backend(&self, role: AgentRole) -> &dyn LlmBackend {
+        match role {
+            AgentRole::Enhancer => self.enhancer.as_ref(),
+            AgentRole::Researcher => self.researcher.as_ref(),
+            AgentRole::Categorizer => self.categorizer.as_ref(),
+            AgentRole::Responder => self.responder.as_ref(),
+            AgentRole::Captioner => self.captioner.as_ref(),
+            AgentRole::Summarizer => self.summarizer.as_ref(),
+        }
+    }
+}
+
+impl fmt::Debug for LlmRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LlmRegistry")
+            .field("enhancer", &self.enhancer.describe())
+            .field("researcher", &self.researcher.describe())
+            .field("categorizer", &self.categorizer.describe())
+            .field("responder", &self.responder.describe())
+            .field("captioner", &self.captioner.describe())
+            .field("summarizer", &self.summarizer.describe())
+            .finish()
+    }
+}
+
+fn backend_from_env(role: AgentRole) -> Result<Arc<dyn LlmBackend>> {
+    let prefix = role.env_prefix();
+    let setting = |name: &str| {
+        std::env::var(format!("{prefix}_{name}"))
+            .or_else(|_| std::env::var(format!("CLONEOPS_LLM_{name}")))
+            .ok()
+    };
+
+    let provider = match setting("PROVIDER") {
+        Some(provider) => provider.parse()?,
+        None => LlmProvider::Gemini,
+    };
+    let model = setting("MODEL");
+
+    Ok(match provider {
+        LlmProvider::Gemini => Arc::new(GeminiBackend::from_env(
+            model.unwrap_or_else(|| GEMINI_2_0_FLASH.to_string()),
+        )),
+        LlmProvider::OpenAi => Arc::new(OpenAiCompatibleBackend::from_env(
+            model.unwrap_or_else(|| "gpt-4o-mini".to_string()),
+        )),
+        LlmProvider::Mock => Arc::new(MockBackend::from_env(role)?),
+    })
+}
+
+// ====== Gemini ======
+
+pub struct GeminiBackend {
+    /// `None` when `GEMINI_API_KEY` isn't set, in which case every request fails
+    client: Option<gemini::Client>,
+    model: String,
+}
+
+impl GeminiBackend {
+    pub fn from_env(model: String) -> Self {
+        let client = std::env::var("GEMINI_API_KEY")
+            .ok()
+            .map(|key| gemini::Client::new(&key));
+        Self { client, model }
+    }
+
+    fn generation_config(json: bool) -> Result<serde_json::Value> {
+        let gen_cfg = GenerationConfig {
+            thinking_config: None,
+            response_mime_type: json.then(|| "application/json".to_string()),
+            ..Default::default()
+        };
+        let cfg = AdditionalParameters::default().with_config(gen_cfg);
+        Ok(serde_json::to_value(cfg)?)
+    }
+}
+
+impl LlmBackend for GeminiBackend {
+    fn describe(&self) -> String {
+        format!("gemini/{}", self.model)
+    }
+
+    fn complete(&self, request: LlmRequest) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let Some(client) = &self.client else {
+                return Err(AppError::LlmBackendError(
+                    "GEMINI_API_KEY is not set, set it or configure another provider".into(),
+                ));
+            };
+
+            let mut builder = client
+                .agent(&self.model)
+                .name(request.agent_name)
+                .additional_params(Self::generation_config(request.json)?);
+            if let Some(preamble) = &request.preamble {
+                builder = builder.preamble(preamble);
+            }
+
+            Ok(builder.build().prompt(request.prompt).await?)
+        })
+    }
+
+    fn stream(&self, request: LlmRequest) -> TextStream {
+        let Some(client) = &self.client else {
+            return stream::once(async {
+                Err(AppError::LlmBackendError(
+                    "GEMINI_API_KEY is not set, set it or configure another provider".into(),
+                ))
+            })
+            .boxed();
+        };
+        let generation_config = match Self::generation_config(request.json) {
+            Ok(generation_config) => generation_config,
+            Err(e) => return stream::once(async { Err(e) }).boxed(),
+        };
+
+        let mut builder = client
+            .agent(&self.model)
+            .name(request.agent_name)
+            .additional_params(generation_config);
+        if let Some(preamble) = &request.preamble {
+            builder = builder.preamble(preamble);
+        }
+        let agent = builder.build();
+
+        stream::once(async move { agent.stream_prompt(request.prompt).await })
+            .flatten()
+            .filter_map(|item| async move {
+                match item {
+                    Ok(MultiTurnStreamItem::StreamItem(StreamedAssistantContent::Text(text))) => {
+                        Some(Ok(text.text))
+                    }
+                    // Reasoning and the final summary aren't part of the reply
+                    Ok(_) => None,
+                    Err(e) => Some(Err(AppError::LlmBackendError(e.to_string()))),
+                }
+            })
+            .boxed()
+    }
+}
+
+// ====== OpenAI compatible ======
+
+/// Talks to any server that implements the OpenAI `/chat/completions` endpoint.
+pub struct OpenAiCompatibleBackend {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatCompletionChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionMessage {
+    content: Option<String>,
+}
+
+/// One server-sent event of a streamed chat completion.
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    choices: Vec<ChatCompletionChunkChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionChunkChoice {
+    delta: ChatCompletionMessage,
+}
+
+impl OpenAiCompatibleBackend {
+    pub fn from_env(model: String) -> Self {
+        let base_url = std::env::var("CLONEOPS_OPENAI_BASE_URL")
+            .unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+        let api_key = std::env::var("CLONEOPS_OPENAI_API_KEY")
+            .or_else(|_| std::env::var("OPENAI_API_KEY"))
+            .ok();
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+        }
+    }
+
+    /// Build the `messages` array for a chat completion request.
+    fn messages(request: &LlmRequest) -> Result<Vec<serde_json::Value>> {
+        let mut messages = Vec::new();
+        if let Some(preamble) = &request.preamble {
+            messages.push(json!({ "role": "system", "content": preamble }));
+        }
+
+        let Message::User { content } = &request.prompt else {
+            return Err(AppError::LlmBackendError(
+                "Only user prompts can be sent to the model".into(),
+            ));
+        };
+        let parts = content
+            .iter()
+            .map(|part| match part {
+                UserContent::Text(text) => Ok(json!({ "type": "text", "text": text.text })),
+                UserContent::Image(image) => {
+                    let url = match &image.data {
+                        DocumentSourceKind::Url(url) => url.clone(),
+                        DocumentSourceKind::Base64(data) => {
+                            let mime = image
+                                .media_type
+                                .as_ref()
+                                .map(|media_type| media_type.to_mime_type())
+                                .unwrap_or("image/png");
+                            format!("data:{mime};base64,{data}")
+                        }
+                        _ => {
+                            return Err(AppError::LlmBackendError(
+                                "Images must be sent as a URL or base64 data".into(),
+                            ));
+                        }
+                    };
+                    Ok(json!({ "type": "image_url", "image_url": { "url": url } }))
+                }
+                _ => Err(AppError::LlmBackendError(
+                    "Only text and images can be sent to the model".into(),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        messages.push(json!({ "role": "user", "content": parts }));
+
+        Ok(messages)
+    }
+
+    /// Build the chat completion request, asking for server-sent events when `stream` is set.
+    fn chat_request(&self, request: &LlmRequest, stream: bool) -> Result<reqwest::RequestBuilder> {
+        let mut body = json!({
+            "model": self.model,
+            "messages": Self::messages(request)?,
+            "stream": stream,
+        });
+        if request.json {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
+
+        let mut http_request = self
+            .http
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&body);
+        if let Some(api_key) = &self.api_key {
+            http_request = http_request.bearer_auth(api_key);
+        }
+        Ok(http_request)
+    }
+}
+
+/// Send a request to the model server and turn error statuses into errors.
+async fn send_checked(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let error = response.text().await.unwrap_or_default();
+        return Err(AppError::LlmBackendError(format!(
+            "The model server responded with {status}: {error}"
+        )));
+    }
+    Ok(response)
+}
+
+/// Split a response body into lines, the way server-sent events are framed.
+fn body_lines<B: AsRef<[u8]> + Send + 'static>(
+    body: impl Stream<Item = reqwest::Result<B>> + Send + 'static,
+) -> BoxStream<'static, Result<String>> {
+    stream::try_unfold(
+        (body.boxed(), Vec::new()),
+        |(mut body, mut buffer)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                    return Ok(Some((line, (body, buffer))));
+                }
+                match body.next().await.transpose()? {
+                    Some(chunk) => buffer.extend_from_slice(chunk.as_ref()),
+                    // The last line doesn't have to end with a newline
+                    None if !buffer.is_empty() => {
+                        let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+                        buffer.clear();
+                        return Ok(Some((line, (body, buffer))));
+                    }
+                    None => return Ok(None),
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+impl LlmBackend for OpenAiCompatibleBackend {
+    fn describe(&self) -> String {
+        format!("openai/{} ({})", self.model, self.base_url)
+    }
+
+    fn complete(&self, request: LlmRequest) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let response = send_checked(self.chat_request(&request, false)?).await?;
+            let completion: ChatCompletion = response.json().await?;
+            completion
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .ok_or_else(|| {
+                    AppError::LlmBackendError("The model server returned an empty reply".into())
+                })
+        })
+    }
+
+    fn stream(&self, request: LlmRequest) -> TextStream {
+        let http_request = match self.chat_request(&request, true) {
+            Ok(http_request) => http_request,
+            Err(e) => return stream::once(async { Err(e) }).boxed(),
+        };
+
+        stream::once(async move {
+            let response = send_checked(http_request).await?;
+            let deltas = body_lines(response.bytes_stream()).try_filter_map(|line| async move {
+                // Everything but the `data` lines is keep-alive noise,
+                // and the stream ends with a `[DONE]` marker
+                let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                    return Ok(None);
+                };
+                if data == "[DONE]" {
+                    return Ok(None);
+                }
+                let chunk: ChatCompletionChunk = serde_json::from_str(data)?;
+                Ok(chunk
+                    .choices
+                    .into_iter()
+                    .next()
+                    .and_then(|choice| choice.delta.content)
+                    .filter(|content| !content.is_empty()))
+            });
+            Ok::<_, AppError>(deltas)
+        })
+        .try_flatten()
+        .boxed()
+    }
+}
+
+// ====== Mock ======
+
+/// Maps messages containing any of `keywords` to `category`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MockCategoryRule {
+    /// Matched case insensitively against the message content
+    pub keywords: Vec<String>,
+    pub category: MessageCategory,
+}
+
+/// The scripted behaviour of the mock backend.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MockScript {
+    /// Canned replies by agent name, `{input}` is replaced with the user's text
+    pub responses: HashMap<String, String>,
+    /// Reply for agents without an entry in `responses`
+    pub default_response: String,
+    /// Checked in order, the first rule with a matching keyword wins
+    pub categories: Vec<MockCategoryRule>,
+    /// Category for messages that no rule matches
+    pub default_category: MessageCategory,
+    /// How long to wait before every reply
+    pub latency_ms: u64,
+    /// Fail any request whose input contains one of these, case insensitively
+    pub error_keywords: Vec<String>,
+    /// Fail every request, as if the model server was down
+    pub always_fail: bool,
+}
+
+impl Default for MockScript {
+    fn default() -> Self {
+        let rule = |keywords: &[&str], category: &str| MockCategoryRule {
+            keywords: keywords.iter().map(ToString::to_string).collect(),
+            category: MessageCategory::new(category),
+        };
+        Self {
+            responses: HashMap::new(),
+            default_response: "{input}".to_string(),
+            categories: vec![
+                rule(&["urgent", "asap", "emergency"], MessageCategory::URGENT),
+                rule(&["sponsor", "paid", "brand deal"], "sponsorship"),
+                rule(&["free money", "click here", "winner"], "spam"),
+                rule(&["collaborate", "connect", "advice"], "networking"),
+            ],
+            default_category: MessageCategory::new("generalInquiry"),
+            latency_ms: 0,
+            error_keywords: vec!["[mock-error]".to_string()],
+            always_fail: false,
+        }
+    }
+}
+
+/// The JSON the mock replies with when asked to categorize a message.
+/// This is the shape of [`crate::agents::MessageCategorization`].
+#[derive(Serialize)]
+struct MockCategorization {
+    reasoning: String,
+    category: MessageCategory,
+}
+
+/// The JSON the mock replies with when asked for captions.
+/// This is the shape of [`crate::agents::CaptionSuggestions`].
+#[derive(Serialize)]
+struct MockCaptions {
+    captions: Vec<MockCaption>,
+}
+
+#[derive(Serialize)]
+struct MockCaption {
+    caption: String,
+    hashtags: Vec<String>,
+}
+
+/// A deterministic backend that never leaves the process.
+/// Text prompts get canned replies, structured prompts get captions echoing the user's description
+/// when the backend serves the captioner and are categorized by keyword otherwise.
+pub struct MockBackend {
+    /// The agent the backend serves, which decides the shape of structured replies
+    role: AgentRole,
+    script: MockScript,
+}
+
+impl MockBackend {
+    pub fn new(role: AgentRole, script: MockScript) -> Self {
+        Self { role, script }
+    }
+
+    pub fn from_env(role: AgentRole) -> Result<Self> {
+        let script = match std::env::var("CLONEOPS_MOCK_SCRIPT") {
+            Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            Err(_) => MockScript::default(),
+        };
+        Ok(Self::new(role, script))
+    }
+
+    fn reply(&self, request: &LlmRequest) -> Result<String> {
+        let input = request.input.as_deref().unwrap_or_default();
+        let lowercase_input = input.to_lowercase();
+        let contains_any = |keywords: &[String]| {
+            keywords
+                .iter()
+                .any(|keyword| lowercase_input.contains(&keyword.to_lowercase()))
+        };
+
+        if self.script.always_fail || contains_any(&self.script.error_keywords) {
+            return Err(AppError::LlmBackendError(format!(
+                "Simulated failure for {}",
+                request.agent_name
+            )));
+        }
+
+        if request.json && self.role == AgentRole::Captioner {
+            let captions = MockCaptions {
+                captions: (1..=3)
+                    .map(|n| MockCaption {
+                        caption: format!("Mock caption {n}: {input}"),
+                        hashtags: vec!["#mock".to_string()],
+                    })
+                    .collect(),
+            };
+            return Ok(serde_json::to_string(&captions)?);
+        }
+
+        if request.json {
+            let categorization = match self
+                .script
+                .categories
+                .iter()
+                .find(|rule| contains_any(&rule.keywords))
+            {
+                Some(rule) => MockCategorization {
+                    reasoning: "The message matched a mock keyword rule.".to_string(),
+                    category: rule.category.clone(),
+                },
+                None => MockCategorization {
+                    reasoning: "The message didn't match any mock keyword rule.".to_string(),
+                    category: self.script.default_category.clone(),
+                },
+            };
+            return Ok(serde_json::to_string(&categorization)?);
+        }
+
+        let template = self
+            .script
+            .responses
+            .get(request.agent_name)
+            .unwrap_or(&self.script.default_response);
+        Ok(template.replace("{input}", input))
+    }
+}
+
+impl LlmBackend for MockBackend {
+    fn describe(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn complete(&self, request: LlmRequest) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            if self.script.latency_ms > 0 {
+                tokio::time::sleep(Duration::from_millis(self.script.latency_ms)).await;
+            }
+            self.reply(&request)
+        })
+    }
+
+    /// Streams the canned reply a word at a time.
+    fn stream(&self, request: LlmRequest) -> TextStream {
+        let latency = Duration::from_millis(self.script.latency_ms);
+        let reply = self.reply(&request);
+        stream::once(async move {
+            if !latency.is_zero() {
+                tokio::time::sleep(latency).await;
+            }
+            reply.map(|reply| {
+                let words = reply
+                    .split_inclusive(' ')
+                    .map(|word| Ok(word.to_string()))
+                    .collect::<Vec<_>>();
+                stream::iter(words)
+            })
+        })
+        .try_flatten()
+        .boxed()
+    }
+}
@@ -0,0 +1,602 @@
+use std::{
+    io::SeekFrom,
+    path::{Path as FsPath, PathBuf},
+};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Multipart, Path, Query, State, multipart::Field},
+    http::{
+        HeaderMap, StatusCode,
+        header::{
+            ACCEPT_RANGES, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::eyre;
+use image::{ImageFormat, ImageReader, Limits, imageops::FilterType};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    AVATAR_DIR, MAX_UPLOAD_BYTES, UPLOAD_DIR,
+    auth::SessionAuth,
+    entities::{
+        ApiScope, Media, create_media, get_media, is_user_in_conversation, set_user_avatar_updated,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    state::AppState,
+};
+
+/// Avatars are small images, so they get a much lower limit than other uploads.
+const MAX_AVATAR_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Widths (and heights) avatars are stored in, from smallest to largest.
+const AVATAR_SIZES: [u32; 3] = [48, 128, 512];
+const DEFAULT_AVATAR_SIZE: u32 = 128;
+
+/// Keeps small but huge-dimension images from taking all the memory when decoded.
+const MAX_AVATAR_DIMENSION: u32 = 8192;
+
+/// Enough of the start of a file to recognize its type.
+const SNIFF_LEN: usize = 16;
+
+/// `ftyp` major brands of plain MP4 video. The same box starts HEIC, AVIF and QuickTime
+/// files too, which browsers won't play as `video/mp4`.
+const MP4_BRANDS: [&[u8; 4]; 10] = [
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"dash", b"M4V ",
+];
+
+// ====== Request/Response Structs ======
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadForm {
+    /// The file to upload
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+// ====== Upload Storage ======
+
+/// The type of a file going by its first bytes rather than what the client claims.
+/// Only the types we're willing to serve back are recognized.
+fn sniff_mime_type(head: &[u8]) -> Option<&'static str> {
+    match head {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => Some("image/webp"),
+        [_, _, _, _, b'f', b't', b'y', b'p', a, b, c, d, ..]
+            if MP4_BRANDS.contains(&&[*a, *b, *c, *d]) =>
+        {
+            Some("video/mp4")
+        }
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some("video/webm"),
+        _ => None,
+    }
+}
+
+/// A file streamed to disk from an upload.
+pub(crate) struct StoredUpload {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub mime_type: &'static str,
+    pub size: u64,
+}
+
+/// Stream the `file` field of a multipart upload into a temporary file in `dir`,
+/// hashing it on the way and enforcing `max_size`.
+async fn receive_upload(
+    mut multipart: Multipart,
+    dir: &FsPath,
+    max_size: u64,
+) -> Result<StoredUpload> {
+    let mut field = loop {
+        match multipart.next_field().await? {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue,
+            None => {
+                return Err(AppError::UserError((
+                    LossyError(StatusCode::BAD_REQUEST),
+                    "The upload needs a `file` field".into(),
+                )));
+            }
+        }
+    };
+
+    receive_file(&mut field, dir, max_size).await
+}
+
+/// Stream a single multipart field into a temporary file in `dir`,
+/// hashing it on the way and enforcing `max_size`.
+pub(crate) async fn receive_file(
+    field: &mut Field<'_>,
+    dir: &FsPath,
+    max_size: u64,
+) -> Result<StoredUpload> {
+    let path = dir.join(format!(".{}.part", Uuid::new_v4()));
+    match write_field(field, &path, max_size).await {
+        Ok((sha256, head, size)) => {
+            let Some(mime_type) = sniff_mime_type(&head) else {
+                fs::remove_file(&path).await?;
+                return Err(AppError::UserError((
+                    LossyError(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+                    "Only PNG, JPEG, GIF and WebP images and MP4 and WebM videos can be uploaded"
+                        .into(),
+                )));
+            };
+            Ok(StoredUpload {
+                path,
+                sha256,
+                mime_type,
+                size,
+            })
+        }
+        Err(e) => {
+            // The file may not have been created if the upload failed right away
+            let _ = fs::remove_file(&path).await;
+            Err(e)
+        }
+    }
+}
+
+async fn write_field(
+    field: &mut Field<'_>,
+    path: &FsPath,
+    max_size: u64,
+) -> Result<(String, Vec<u8>, u64)> {
+    let mut file = fs::File::create(path).await?;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let mut size = 0u64;
+
+    while let Some(chunk) = field.chunk().await? {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(AppError::UserError((
+                LossyError(StatusCode::PAYLOAD_TOO_LARGE),
+                format!("Uploads can be at most {max_size} bytes"),
+            )));
+        }
+        if head.len() < SNIFF_LEN {
+            let missing = SNIFF_LEN - head.len();
+            head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    if size == 0 {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "The uploaded file is empty".into(),
+        )));
+    }
+    Ok((format!("{:x}", hasher.finalize()), head, size))
+}
+
+/// Path of an upload in the content addressed store.
+pub(crate) fn upload_path(sha256: &str) -> PathBuf {
+    UPLOAD_DIR.join(sha256)
+}
+
+fn avatar_path(user_id: Uuid, size: u32) -> PathBuf {
+    AVATAR_DIR.join(format!("{}_{size}.png", user_id.simple()))
+}
+
+/// The part of a file asked for with a `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// Inclusive on both ends
+    Partial {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a file of `size` bytes. Only a single byte range is supported,
+/// anything else gets the whole file as the header may be ignored.
+fn requested_range(range: Option<&str>, size: u64) -> ByteRange {
+    let Some((first, last)) = range
+        .and_then(|range| range.trim().strip_prefix("bytes="))
+        .filter(|ranges| !ranges.contains(','))
+        .and_then(|range| range.trim().split_once('-'))
+    else {
+        return ByteRange::Full;
+    };
+
+    if first.is_empty() {
+        // The last `last` bytes
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial {
+                start: size.saturating_sub(suffix),
+                end: size - 1,
+            },
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = first.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let end = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return ByteRange::Full,
+        }
+    };
+    if start >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial {
+            start,
+            end: end.min(size - 1),
+        }
+    }
+}
+
+/// Serve a stored file. The permission checks already happened, but the response
+/// may still be cached by the browser, so it's marked private.
+fn file_response(bytes: Vec<u8>, mime_type: &str) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, mime_type),
+            (CACHE_CONTROL, "private, max-age=86400"),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+pub(crate) fn media_not_found() -> AppError {
+    AppError::UserError((LossyError(StatusCode::NOT_FOUND), "Media not found!".into()))
+}
+
+/// Move an upload into the content addressed store and record it as media owned by `owner_id`.
+pub(crate) async fn store_upload(
+    pool: &SqlitePool,
+    owner_id: Uuid,
+    upload: StoredUpload,
+) -> Result<Media> {
+    let path = upload_path(&upload.sha256);
+    if fs::try_exists(&path).await? {
+        // Someone uploaded the same file before
+        fs::remove_file(&upload.path).await?;
+    } else {
+        fs::rename(&upload.path, &path).await?;
+    }
+
+    create_media(
+        pool,
+        owner_id,
+        &upload.sha256,
+        upload.mime_type,
+        upload.size as i64,
+    )
+    .await
+}
+
+/// Whether `session` may see `media`. Media attached to a post is public, media attached to a
+/// message is visible to the conversation's members and unattached media only to its uploader.
+pub(crate) async fn can_view_media(
+    pool: &SqlitePool,
+    session: Option<&SessionAuth>,
+    media: &Media,
+) -> Result<bool> {
+    if media.post_id.is_some() {
+        return Ok(true);
+    }
+    let Some(session) = session else {
+        return Ok(false);
+    };
+    if session.0.id == media.owner_id {
+        Ok(true)
+    } else if let Some(conversation_id) = media.conversation_id {
+        session.require_scope(ApiScope::MessagesRead)?;
+        is_user_in_conversation(pool, session.0.id, conversation_id).await
+    } else {
+        Ok(false)
+    }
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    post,
+    path = "/api/media",
+    description = "Upload an image or video to attach to posts and messages by its ID. \
+        The media stays private to the uploader until it's attached.",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = CREATED, description = "Media uploaded", body = Media),
+        (status = BAD_REQUEST, description = "The upload has no file", body = ErrorResponse),
+        (status = PAYLOAD_TOO_LARGE, description = "The file is too large", body = ErrorResponse),
+        (status = UNSUPPORTED_MEDIA_TYPE, description = "The file type isn't supported", body = ErrorResponse),
+    )
+)]
+pub async fn upload_media_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    multipart: Multipart,
+) -> Result<Response> {
+    // Media is only useful attached to a post or a message
+    if session.require_scope(ApiScope::PostsWrite).is_err() {
+        session.require_scope(ApiScope::MessagesWrite)?;
+    }
+
+    let upload = receive_upload(multipart, &UPLOAD_DIR, *MAX_UPLOAD_BYTES).await?;
+    let media = store_upload(&state.pool, session.0.id, upload).await?;
+    Ok((StatusCode::CREATED, Json(media)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/media/{id}",
+    description = "Download uploaded media. Media attached to a post is public, media attached to \
+        a message can only be seen by the conversation's members and unattached media only by its uploader.",
+    params(
+        ("id" = Uuid, Path, description = "ID of the media to download")
+    ),
+    responses(
+        (status = OK, description = "The media file"),
+        (status = PARTIAL_CONTENT, description = "The part of the media file asked for with `Range`"),
+        (status = NOT_FOUND, description = "Media not found", body = ErrorResponse),
+        (status = RANGE_NOT_SATISFIABLE, description = "The range is past the end of the file"),
+    )
+)]
+pub async fn get_media_handler(
+    State(state): State<AppState>,
+    session: Option<SessionAuth>,
+    Path(media_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let media = get_media(&state.pool, media_id)
+        .await?
+        .ok_or_else(media_not_found)?;
+
+    // Media the user can't see looks the same as media that doesn't exist
+    if !can_view_media(&state.pool, session.as_ref(), &media).await? {
+        return Err(media_not_found());
+    }
+
+    // Videos can be large, so they're streamed rather than read into memory
+    let mut file = fs::File::open(upload_path(&media.sha256)).await?;
+    let size = file.metadata().await?.len();
+    let range = headers.get(RANGE).and_then(|range| range.to_str().ok());
+    let (status, start, end) = match requested_range(range, size) {
+        ByteRange::Full => (StatusCode::OK, 0, size.saturating_sub(1)),
+        ByteRange::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end),
+        ByteRange::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{size}"))],
+            )
+                .into_response());
+        }
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+    file.seek(SeekFrom::Start(start)).await?;
+    let body = Body::from_stream(ReaderStream::new(file.take(length)));
+
+    let content_length = length.to_string();
+    let headers = [
+        (CONTENT_TYPE, media.mime_type.as_str()),
+        (CONTENT_LENGTH, content_length.as_str()),
+        (ACCEPT_RANGES, "bytes"),
+        (CACHE_CONTROL, "private, max-age=86400"),
+        (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    ];
+    Ok(if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {start}-{end}/{size}");
+        (status, headers, [(CONTENT_RANGE, content_range)], body).into_response()
+    } else {
+        (status, headers, body).into_response()
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/me/avatar",
+    description = "Replace the current user's avatar with an uploaded image. \
+        The image is cropped to a square and stored in a few fixed sizes.",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = NO_CONTENT, description = "Avatar updated"),
+        (status = BAD_REQUEST, description = "The image couldn't be read", body = ErrorResponse),
+        (status = PAYLOAD_TOO_LARGE, description = "The image is too large", body = ErrorResponse),
+        (status = UNSUPPORTED_MEDIA_TYPE, description = "The file isn't a supported image", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn upload_avatar_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    multipart: Multipart,
+) -> Result<Response> {
+    session.require_session()?;
+    let user_id = session.0.id;
+    let upload = receive_upload(multipart, &AVATAR_DIR, MAX_AVATAR_BYTES).await?;
+    if !upload.mime_type.starts_with("image/") {
+        fs::remove_file(&upload.path).await?;
+        return Err(AppError::UserError((
+            LossyError(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            "Avatars have to be images".into(),
+        )));
+    }
+
+    // Decoding and resizing is CPU heavy, so keep it off the async workers
+    let source = upload.path.clone();
+    let resized = tokio::task::spawn_blocking(move || resize_avatar(&source, user_id))
+        .await
+        .map_err(|e| AppError::Generic(LossyError(eyre!(e))))?;
+    fs::remove_file(&upload.path).await?;
+    resized?;
+
+    set_user_avatar_updated(&state.pool, user_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Crop an uploaded image to a square and write it out in each of the `AVATAR_SIZES`.
+fn resize_avatar(source: &FsPath, user_id: Uuid) -> Result<()> {
+    let unreadable = |e: image::ImageError| {
+        AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            format!("The image couldn't be read: {e}"),
+        ))
+    };
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_AVATAR_DIMENSION);
+    limits.max_image_height = Some(MAX_AVATAR_DIMENSION);
+    let mut reader = ImageReader::open(source)?.with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode().map_err(unreadable)?;
+
+    for size in AVATAR_SIZES {
+        let path = avatar_path(user_id, size);
+        let part = path.with_extension("part");
+        image
+            .resize_to_fill(size, size, FilterType::Lanczos3)
+            .save_with_format(&part, ImageFormat::Png)
+            .map_err(|e| AppError::Generic(LossyError(eyre!(e))))?;
+        std::fs::rename(&part, &path)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AvatarQuery {
+    /// Smallest width and height wanted, in pixels. The closest stored size is returned.
+    pub size: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/avatar",
+    params(
+        ("id" = Uuid, Path, description = "User ID to get the avatar of"),
+        AvatarQuery
+    ),
+    responses(
+        (status = OK, description = "The avatar as a square PNG", content_type = "image/png"),
+        (status = NOT_FOUND, description = "The user has no avatar", body = ErrorResponse),
+    )
+)]
+pub async fn get_avatar_handler(
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<AvatarQuery>,
+) -> Result<Response> {
+    let wanted = query.size.unwrap_or(DEFAULT_AVATAR_SIZE);
+    let size = AVATAR_SIZES
+        .into_iter()
+        .find(|size| *size >= wanted)
+        .unwrap_or(AVATAR_SIZES[AVATAR_SIZES.len() - 1]);
+
+    let bytes = match fs::read(avatar_path(user_id, size)).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AppError::UserError((
+                LossyError(StatusCode::NOT_FOUND),
+                "This user has no avatar".into(),
+            )));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(file_response(bytes, "image/png"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        [&[0, 0, 0, 0x20][..], b"ftyp", brand, &[0, 0, 0, 0]].concat()
+    }
+
+    #[test]
+    fn only_mp4_brands_are_sniffed_as_mp4() {
+        assert_eq!(sniff_mime_type(&ftyp(b"isom")), Some("video/mp4"));
+        assert_eq!(sniff_mime_type(&ftyp(b"mp42")), Some("video/mp4"));
+        for brand in [b"heic", b"heix", b"mif1", b"avif", b"qt  "] {
+            assert_eq!(sniff_mime_type(&ftyp(brand)), None, "{brand:?}");
+        }
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        let partial = |start, end| ByteRange::Partial { start, end };
+        assert_eq!(requested_range(None, 100), ByteRange::Full);
+        assert_eq!(requested_range(Some("bytes=0-9"), 100), partial(0, 9));
+        assert_eq!(requested_range(Some("bytes=90-"), 100), partial(90, 99));
+        assert_eq!(requested_range(Some("bytes=90-500"), 100), partial(90, 99));
+        assert_eq!(requested_range(Some("bytes=-10"), 100), partial(90, 99));
+        assert_eq!(requested_range(Some("bytes=-500"), 100), partial(0, 99));
+    }
+
+    #[test]
+    fn unsupported_ranges_get_the_whole_file() {
+        for range in [
+            "items=0-9",
+            "bytes=0-9,20-29",
+            "bytes=9-0",
+            "bytes=a-b",
+            "bytes=-",
+        ] {
+            assert_eq!(
+                requested_range(Some(range), 100),
+                ByteRange::Full,
+                "{range}"
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(
+            requested_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            requested_range(Some("bytes=-0"), 100),
+            ByteRange::Unsatisfiable
+        );
+    }
+}
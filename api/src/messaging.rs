@@ -6,24 +6,32 @@ use axum::{
 };
 use rig::{OneOrMany, message::UserContent};
 use serde::Deserialize;
+use serde_json::json;
 use std::collections::HashSet;
+use tracing::warn;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    agents,
+    agents::{self, MAX_CATEGORIZATION_HISTORY},
+    audit::{NewAuditEntry, record_audit_entry},
     auth::SessionAuth,
     entities::{
-        ChatMessage, ChatMessageWithMetadata, Conversation, ConversationWithParticipants,
-        UnreadMessage, add_users_to_conversation, categorize_message, check_delegation,
-        create_chat_message, create_conversation, get_chat_messages, get_conversation,
-        get_conversation_messages, get_conversation_messages_chronological,
-        get_conversation_participants, get_conversation_with_participants,
-        get_last_read_time, get_unread_messages, get_user_conversations,
+        ApiScope, AuditAction, AuditTarget, CategorizationSource, CategoryCorrection, ChatMessage,
+        ChatMessageWithMetadata, Conversation, ConversationWithParticipants,
+        MANUAL_CATEGORY_REASONING, MessageCategory, UnreadMessage, add_users_to_conversation,
+        categorize_message, check_delegation, correct_message_category, create_chat_message,
+        create_conversation, ensure_category_exists, get_category_corrections, get_chat_message,
+        get_chat_messages, get_conversation, get_conversation_messages,
+        get_conversation_participants, get_conversation_with_participants, get_last_read_time,
+        get_latest_conversation_messages, get_unread_messages, get_user_conversations,
         is_user_in_conversation, mark_conversation_as_read, update_conversation_title,
     },
-    error::{AppError, Result},
+    error::{AppError, ErrorResponse, LossyError, Result},
     events::{SseEvent, broadcast_event},
+    llm::AgentRole,
+    pagination::{Page, PageQuery},
+    pins, responder,
     state::AppState,
     utoipa_compat,
 };
@@ -47,6 +55,9 @@ pub struct EditConversationRequest {
 pub struct SendMessageRequest {
     #[schema(value_type = Vec<utoipa_compat::UserContent>)]
     pub content: OneOrMany<UserContent>,
+    /// IDs of media uploaded by the current user to attach to the message
+    #[serde(default)]
+    pub media: Vec<Uuid>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -58,13 +69,21 @@ pub struct AddUsersToConversationRequest {
 #[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ActAsQuery {
-    /// Send message on behalf of another user (requires delegation)
+    /// Send or read messages on behalf of another user (requires delegation)
     pub act_as: Option<Uuid>,
 }
 
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CorrectCategoryRequest {
+    pub category: MessageCategory,
+    /// Why the message belongs in this category, shown to the categorizer with later messages
+    pub reasoning: Option<String>,
+}
+
 // ====== Helper Functions ======
 
-async fn broadcast_to_conversation(
+pub async fn broadcast_to_conversation(
     pool: &sqlx::SqlitePool,
     clients: &crate::state::ClientMap,
     conversation_id: Uuid,
@@ -78,6 +97,46 @@ async fn broadcast_to_conversation(
     Ok(())
 }
 
+/// Store the category a rule or the categorizer agent picked for `user_id`,
+/// together with its audit entry when the agent picked it.
+/// Returns `false` if the user had set the category themselves.
+async fn store_categorization(
+    pool: &sqlx::SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+    categorization: &agents::MessageCategorization,
+    source: CategorizationSource,
+    prompt_template_id: Option<Uuid>,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let stored = categorize_message(
+        &mut *tx,
+        user_id,
+        message_id,
+        categorization.category.clone(),
+        categorization.reasoning.clone(),
+        source,
+        prompt_template_id,
+    )
+    .await?;
+    if stored && source == CategorizationSource::Agent {
+        record_audit_entry(
+            &mut tx,
+            NewAuditEntry::agent(
+                AgentRole::Categorizer,
+                user_id,
+                AuditAction::MessageCategorized,
+                AuditTarget::Message,
+                message_id,
+            )
+            .with_details(json!({ "category": categorization.category })),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(stored)
+}
+
 // ====== Endpoint Handlers ======
 
 #[utoipa::path(
@@ -94,6 +153,7 @@ pub async fn create_conversation_handler(
     session: SessionAuth,
     Json(mut payload): Json<CreateConversationRequest>,
 ) -> Result<impl IntoResponse> {
+    session.require_scope(ApiScope::MessagesWrite)?;
     // Ensure the current user is part of the conversation
     payload.user_ids.insert(session.0.id);
     // Remove duplicates
@@ -132,6 +192,7 @@ pub async fn send_message_handler(
     Query(query): Query<ActAsQuery>,
     Json(payload): Json<SendMessageRequest>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
     let actual_sender = session.0.id;
 
     // Determine who we're sending as
@@ -143,6 +204,11 @@ pub async fn send_message_handler(
                     "You don't have permission to send messages as this user".into(),
                 ));
             }
+            if !delegation.covers_conversation(conversation_id) {
+                return Err(AppError::AuthError(
+                    "Your delegation doesn't cover this conversation".into(),
+                ));
+            }
             // Check if the act_as user is part of the conversation
             if !is_user_in_conversation(&state.pool, act_as_id, conversation_id).await? {
                 return Err(AppError::AuthError(
@@ -165,12 +231,56 @@ pub async fn send_message_handler(
         actual_sender
     };
 
+    if !payload.media.is_empty() && sender_id != actual_sender {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Media can't be sent on behalf of another user".into(),
+        )));
+    }
+
     // The `UserContent` needs to be serialized to a string to be stored.
-    let message =
-        create_chat_message(&state.pool, conversation_id, sender_id, payload.content).await?;
+    let mut tx = state.pool.begin().await?;
+    let message = create_chat_message(
+        &mut *tx,
+        conversation_id,
+        sender_id,
+        payload.content,
+        false,
+        &payload.media,
+    )
+    .await?;
+    if sender_id != actual_sender {
+        record_audit_entry(
+            &mut tx,
+            NewAuditEntry::new(
+                actual_sender,
+                sender_id,
+                AuditAction::DelegatedMessageSent,
+                AuditTarget::Message,
+                message.id,
+            )
+            .with_details(json!({ "conversationId": conversation_id })),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    deliver_message(&state, &message).await?;
+
+    Ok((StatusCode::CREATED, Json(message)).into_response())
+}
+
+/// Deliver a message that was just stored, however it was sent.
+/// It's categorized for every other participant in the background, which also pins it when urgent
+/// and lets their DM responder act on it, and everyone in the conversation is sent the message.
+pub async fn deliver_message(state: &AppState, message: &ChatMessage) -> Result<()> {
+    let conversation_id = message.conversation_id;
+    let sender_id = message.sender_id;
 
     // Get message history for categorization context (chronological order for AI)
-    let history = get_conversation_messages_chronological(&state.pool, conversation_id).await?;
+    let history =
+        get_latest_conversation_messages(&state.pool, conversation_id, MAX_CATEGORIZATION_HISTORY)
+            .await?;
 
     // Wrap shared data in Arc to avoid unnecessary cloning
     let message_arc = std::sync::Arc::new(message.clone());
@@ -180,34 +290,78 @@ pub async fn send_message_handler(
     let participants = get_conversation_participants(&state.pool, conversation_id).await?;
     for participant in participants {
         if participant.id != sender_id {
-            // Run categorization asynchronously for each recipient
+            // Run categorization asynchronously for each recipient.
+            // The task is tracked so that the kill switch can stop it mid-flight.
+            let state_clone = state.clone();
             let pool_clone = state.pool.clone();
             let clients_clone = state.clients.clone();
             let message_clone = message_arc.clone();
             let history_clone = history_arc.clone();
             let recipient_id = participant.id;
 
-            tokio::spawn(async move {
+            state.agent_tasks.spawn(recipient_id, async move {
                 // Try to categorize, but don't fail if it doesn't work
-                if let Ok(categorization) =
-                    agents::categorize_message((*message_clone).clone(), &history_clone).await
+                if let Ok((categorization, prompt_template_id)) = agents::categorize_message(
+                    &state_clone,
+                    recipient_id,
+                    (*message_clone).clone(),
+                    &history_clone,
+                )
+                .await
                 {
-                    if let Ok(_) = categorize_message(
+                    // Only the LLM is asked with a prompt template
+                    let source = match prompt_template_id {
+                        Some(_) => CategorizationSource::Agent,
+                        None => CategorizationSource::Rule,
+                    };
+                    // Skipped if the recipient set the category themselves in the meantime
+                    let stored = store_categorization(
                         &pool_clone,
                         recipient_id,
                         message_clone.id,
-                        categorization.category.clone(),
-                        categorization.reasoning.clone(),
+                        &categorization,
+                        source,
+                        prompt_template_id,
                     )
                     .await
-                    {
+                    .inspect_err(|e| {
+                        warn!(
+                            "Failed to store the category of {} for {recipient_id}: {e}",
+                            message_clone.id
+                        )
+                    });
+                    if let Ok(true) = stored {
                         // Send SSE event for the categorization
                         let event = SseEvent::MessageCategorized {
                             message_id: message_clone.id,
-                            category: categorization.category,
+                            category: categorization.category.clone(),
                             reasoning: categorization.reasoning,
+                            source,
                         };
                         broadcast_event(&clients_clone, &[recipient_id], &event).await;
+
+                        if let Err(e) = pins::sync_urgent_pin(
+                            &state_clone,
+                            recipient_id,
+                            message_clone.id,
+                            &categorization.category,
+                        )
+                        .await
+                        {
+                            warn!("Failed to pin urgent message for {recipient_id}: {e}");
+                        }
+
+                        // Let the recipient's DM responder act on the categorized message
+                        if let Err(e) = responder::respond_to_message(
+                            &state_clone,
+                            recipient_id,
+                            &message_clone,
+                            categorization.category,
+                        )
+                        .await
+                        {
+                            warn!("DM responder for {recipient_id} failed: {e}");
+                        }
                     }
                 }
             });
@@ -223,7 +377,7 @@ pub async fn send_message_handler(
     )
     .await?;
 
-    Ok((StatusCode::CREATED, Json(message)).into_response())
+    Ok(())
 }
 
 #[utoipa::path(
@@ -244,6 +398,7 @@ pub async fn edit_conversation_handler(
     Path(conversation_id): Path<Uuid>,
     Json(payload): Json<EditConversationRequest>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
     // Authorize: Check if the user is part of the conversation
     if !is_user_in_conversation(&state.pool, session.0.id, conversation_id).await? {
         return Err(AppError::AuthError(
@@ -284,6 +439,7 @@ pub async fn add_users_to_conversation_handler(
     Path(conversation_id): Path<Uuid>,
     Json(payload): Json<AddUsersToConversationRequest>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
     // Authorize: Check if the user is part of the conversation
     if !is_user_in_conversation(&state.pool, session.0.id, conversation_id).await? {
         return Err(AppError::AuthError(
@@ -328,6 +484,7 @@ pub async fn get_conversation_handler(
     session: SessionAuth,
     Path(conversation_id): Path<Uuid>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
     // Authorize: Check if the user is part of the conversation
     if !is_user_in_conversation(&state.pool, session.0.id, conversation_id).await? {
         return Err(AppError::AuthError(
@@ -345,10 +502,12 @@ pub async fn get_conversation_handler(
     get,
     path = "/api/conversations/{id}/messages",
     params(
-        ("id" = Uuid, Path, description = "ID of the conversation to get messages for")
+        ("id" = Uuid, Path, description = "ID of the conversation to get messages for"),
+        ("act_as" = Option<Uuid>, Query, description = "Read the conversation as this user (requires delegation)"),
+        PageQuery
     ),
     responses(
-        (status = OK, description = "Messages retrieved successfully", body = Vec<ChatMessage>),
+        (status = OK, description = "Messages retrieved successfully, newest first", body = Page<ChatMessage>),
         (status = FORBIDDEN, description = "User is not part of the conversation"),
     )
 )]
@@ -356,15 +515,33 @@ pub async fn get_messages_handler(
     State(state): State<AppState>,
     session: SessionAuth,
     Path(conversation_id): Path<Uuid>,
+    Query(query): Query<ActAsQuery>,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let reader_id = if let Some(act_as_id) = query.act_as {
+        let Some(delegation) = check_delegation(&state.pool, act_as_id, session.0.id).await? else {
+            return Err(AppError::AuthError(
+                "You don't have delegation from this user".into(),
+            ));
+        };
+        if !delegation.can_read_messages || !delegation.covers_conversation(conversation_id) {
+            return Err(AppError::AuthError(
+                "You don't have permission to read this conversation as this user".into(),
+            ));
+        }
+        act_as_id
+    } else {
+        session.0.id
+    };
     // Authorize: Check if the user is part of the conversation
-    if !is_user_in_conversation(&state.pool, session.0.id, conversation_id).await? {
+    if !is_user_in_conversation(&state.pool, reader_id, conversation_id).await? {
         return Err(AppError::AuthError(
             "You are not a member of this conversation.".into(),
         ));
     }
 
-    let messages = get_conversation_messages(&state.pool, conversation_id).await?;
+    let messages = get_conversation_messages(&state.pool, conversation_id, &page).await?;
 
     Ok((StatusCode::OK, Json(messages)).into_response())
 }
@@ -373,10 +550,11 @@ pub async fn get_messages_handler(
     get,
     path = "/api/conversations/{id}/messages/categorized",
     params(
-        ("id" = Uuid, Path, description = "ID of the conversation to get categorized messages for")
+        ("id" = Uuid, Path, description = "ID of the conversation to get categorized messages for"),
+        PageQuery
     ),
     responses(
-        (status = OK, description = "Categorized messages retrieved successfully", body = Vec<ChatMessageWithMetadata>),
+        (status = OK, description = "Categorized messages retrieved successfully, newest first", body = Page<ChatMessageWithMetadata>),
         (status = FORBIDDEN, description = "User is not part of the conversation"),
     )
 )]
@@ -384,7 +562,9 @@ pub async fn get_categorized_messages_handler(
     State(state): State<AppState>,
     session: SessionAuth,
     Path(conversation_id): Path<Uuid>,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
     let user_id = session.0.id;
     // Authorize: Check if the user is part of the conversation
     if !is_user_in_conversation(&state.pool, user_id, conversation_id).await? {
@@ -394,11 +574,105 @@ pub async fn get_categorized_messages_handler(
     }
 
     // Get messages with user-specific categorization metadata
-    let messages = get_chat_messages(&state.pool, conversation_id, user_id).await?;
+    let messages = get_chat_messages(&state.pool, conversation_id, user_id, &page).await?;
 
     Ok((StatusCode::OK, Json(messages)).into_response())
 }
 
+/// Find a message in one of the user's conversations.
+/// Messages in other conversations are reported as not found, so their IDs can't be probed.
+pub async fn get_message_for_user(
+    pool: &sqlx::SqlitePool,
+    user_id: Uuid,
+    message_id: Uuid,
+) -> Result<ChatMessage> {
+    if let Some(message) = get_chat_message(pool, message_id).await? {
+        if is_user_in_conversation(pool, user_id, message.conversation_id).await? {
+            return Ok(message);
+        }
+    }
+    Err(AppError::UserError((
+        LossyError(StatusCode::NOT_FOUND),
+        "Message not found!".into(),
+    )))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/messages/{id}/category",
+    description = "Override the category of a message for the current user. \
+        Every correction is kept, and the latest ones teach the categorizer what the user wants.",
+    request_body = CorrectCategoryRequest,
+    params(
+        ("id" = Uuid, Path, description = "ID of the message to recategorize")
+    ),
+    responses(
+        (status = OK, description = "The recorded correction", body = CategoryCorrection),
+        (status = BAD_REQUEST, description = "Unknown category", body = ErrorResponse),
+        (status = NOT_FOUND, description = "Message not found", body = ErrorResponse),
+    )
+)]
+pub async fn correct_category_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(message_id): Path<Uuid>,
+    Json(payload): Json<CorrectCategoryRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    let user_id = session.0.id;
+    get_message_for_user(&state.pool, user_id, message_id).await?;
+    ensure_category_exists(&state.pool, user_id, &payload.category).await?;
+
+    let reasoning = payload
+        .reasoning
+        .as_deref()
+        .map(str::trim)
+        .filter(|reasoning| !reasoning.is_empty());
+    let correction = correct_message_category(
+        &state.pool,
+        user_id,
+        message_id,
+        payload.category,
+        reasoning,
+    )
+    .await?;
+
+    let event = SseEvent::MessageCategorized {
+        message_id,
+        category: correction.category.clone(),
+        reasoning: reasoning.unwrap_or(MANUAL_CATEGORY_REASONING).to_string(),
+        source: CategorizationSource::Manual,
+    };
+    broadcast_event(&state.clients, &[user_id], &event).await;
+    pins::sync_urgent_pin(&state, user_id, message_id, &correction.category).await?;
+
+    Ok((StatusCode::OK, Json(correction)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/messages/{id}/category/corrections",
+    params(
+        ("id" = Uuid, Path, description = "ID of the message to get the corrections of")
+    ),
+    responses(
+        (status = OK, description = "The current user's corrections of the message's category, newest first", body = Vec<CategoryCorrection>),
+        (status = NOT_FOUND, description = "Message not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_category_corrections_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(message_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let user_id = session.0.id;
+    get_message_for_user(&state.pool, user_id, message_id).await?;
+
+    let corrections = get_category_corrections(&state.pool, user_id, message_id).await?;
+    Ok((StatusCode::OK, Json(corrections)).into_response())
+}
+
 
 // ====== List Conversations Endpoint ======
 
@@ -413,10 +687,11 @@ pub struct ConversationsQuery {
     get,
     path = "/api/conversations",
     params(
-        ("user_id" = Option<Uuid>, Query, description = "Filter by user ID (requires access)")
+        ("user_id" = Option<Uuid>, Query, description = "Filter by user ID (requires access)"),
+        PageQuery
     ),
     responses(
-        (status = OK, description = "List of conversations", body = Vec<Conversation>),
+        (status = OK, description = "List of conversations, most recently active first", body = Page<Conversation>),
         (status = FORBIDDEN, description = "No access to these conversations"),
     )
 )]
@@ -424,7 +699,11 @@ pub async fn list_conversations_handler(
     State(state): State<AppState>,
     session: SessionAuth,
     Query(query): Query<ConversationsQuery>,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    // Delegates may only see the conversations their delegation covers
+    let mut only = None;
     let user_id = if let Some(requested_user_id) = query.user_id {
         // Check if user has access to view this user's conversations
         if requested_user_id == session.0.id {
@@ -433,11 +712,12 @@ pub async fn list_conversations_handler(
         } else {
             // Check for delegation
             if let Some(delegation) = check_delegation(&state.pool, requested_user_id, session.0.id).await? {
-                if !delegation.can_message {
+                if !delegation.can_read_messages {
                     return Err(AppError::AuthError(
                         "You don't have permission to view this user's conversations".into(),
                     ));
                 }
+                only = delegation.conversation_ids.map(|conversation_ids| conversation_ids.0);
                 requested_user_id
             } else {
                 return Err(AppError::AuthError(
@@ -450,7 +730,8 @@ pub async fn list_conversations_handler(
         session.0.id
     };
 
-    let conversations = get_user_conversations(&state.pool, user_id).await?;
+    let conversations =
+        get_user_conversations(&state.pool, user_id, only.as_deref(), &page).await?;
     Ok((StatusCode::OK, Json(conversations)).into_response())
 }
 
@@ -473,6 +754,7 @@ pub async fn mark_conversation_read_handler(
     session: SessionAuth,
     Path(conversation_id): Path<Uuid>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
     // Verify user is part of the conversation
     if !is_user_in_conversation(&state.pool, session.0.id, conversation_id).await? {
         return Err(AppError::AuthError(
@@ -488,16 +770,19 @@ pub async fn mark_conversation_read_handler(
 #[utoipa::path(
     get,
     path = "/api/messages/unread",
+    params(PageQuery),
     responses(
-        (status = OK, description = "List of unread messages", body = Vec<UnreadMessage>),
+        (status = OK, description = "Unread messages, newest first", body = Page<UnreadMessage>),
         (status = FORBIDDEN, description = "Not authenticated"),
     )
 )]
 pub async fn get_unread_messages_handler(
     State(state): State<AppState>,
     session: SessionAuth,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
-    let unread_messages = get_unread_messages(&state.pool, session.0.id).await?;
+    session.require_scope(ApiScope::MessagesRead)?;
+    let unread_messages = get_unread_messages(&state.pool, session.0.id, &page).await?;
     Ok((StatusCode::OK, Json(unread_messages)).into_response())
 }
 
@@ -514,10 +799,11 @@ pub struct MessageWithReadStatus {
     get,
     path = "/api/conversations/{id}/messages-with-status",
     params(
-        ("id" = Uuid, Path, description = "ID of the conversation")
+        ("id" = Uuid, Path, description = "ID of the conversation"),
+        PageQuery
     ),
     responses(
-        (status = OK, description = "Messages with read status", body = Vec<MessageWithReadStatus>),
+        (status = OK, description = "Messages with read status, newest first", body = Page<MessageWithReadStatus>),
         (status = FORBIDDEN, description = "User is not part of the conversation"),
     )
 )]
@@ -525,7 +811,9 @@ pub async fn get_messages_with_status_handler(
     State(state): State<AppState>,
     session: SessionAuth,
     Path(conversation_id): Path<Uuid>,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
     let user_id = session.0.id;
     
     // Verify user is part of the conversation
@@ -535,22 +823,180 @@ pub async fn get_messages_with_status_handler(
         ));
     }
 
-    let messages = get_conversation_messages(&state.pool, conversation_id).await?;
+    let messages = get_conversation_messages(&state.pool, conversation_id, &page).await?;
     let last_read = get_last_read_time(&state.pool, user_id, conversation_id).await?;
     
-    let messages_with_status: Vec<MessageWithReadStatus> = messages
-        .into_iter()
-        .map(|msg| {
-            let is_read = msg.sender_id == user_id || // User's own messages are always "read"
-                last_read.map_or(false, |read_time| msg.created_at <= read_time);
-            MessageWithReadStatus {
-                message: msg,
-                is_read,
-            }
-        })
-        .collect();
+    let messages_with_status: Page<MessageWithReadStatus> = messages.map(|msg| {
+        let is_read = msg.sender_id == user_id || // User's own messages are always "read"
+            last_read.map_or(false, |read_time| msg.created_at <= read_time);
+        MessageWithReadStatus {
+            message: msg,
+            is_read,
+        }
+    });
 
     Ok((StatusCode::OK, Json(messages_with_status)).into_response())
 }
 
 use serde::Serialize;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::broadcast, time::timeout};
+
+    use super::*;
+    use crate::{
+        audit::AuditFilter,
+        entities::{
+            PromptTemplateName, create_conversation, get_active_prompt_template, get_audit_entries,
+        },
+        llm::MockScript,
+        testing::{sign_up, subscribe, test_state},
+    };
+
+    /// How long to wait for a categorization that should happen
+    const CATEGORIZED_TIMEOUT: Duration = Duration::from_secs(5);
+    /// How long to wait before deciding a categorization isn't coming
+    const NOT_CATEGORIZED_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Let `sender` message `recipient` and return the conversation and the recipient's events.
+    async fn send(state: &AppState, text: &str) -> (Uuid, Uuid, broadcast::Receiver<SseEvent>) {
+        let sender = sign_up(&state.pool, "sender").await;
+        let recipient = sign_up(&state.pool, "recipient").await;
+        let recipient_id = recipient.0.id;
+        let conversation = create_conversation(&state.pool, &[sender.0.id, recipient_id])
+            .await
+            .unwrap();
+        let events = subscribe(state, recipient_id);
+
+        let response = send_message_handler(
+            State(state.clone()),
+            sender,
+            Path(conversation.id),
+            Query(ActAsQuery { act_as: None }),
+            Json(SendMessageRequest {
+                content: OneOrMany::one(UserContent::text(text)),
+                media: Vec::new(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        (conversation.id, recipient_id, events)
+    }
+
+    /// The next `messageCategorized` event, skipping any other events.
+    async fn next_categorization(
+        events: &mut broadcast::Receiver<SseEvent>,
+        wait: Duration,
+    ) -> Option<(Uuid, MessageCategory, CategorizationSource)> {
+        timeout(wait, async {
+            loop {
+                match events.recv().await {
+                    Ok(SseEvent::MessageCategorized {
+                        message_id,
+                        category,
+                        source,
+                        ..
+                    }) => return Some((message_id, category, source)),
+                    Ok(_) => continue,
+                    Err(_) => return None,
+                }
+            }
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    async fn recipient_view(
+        state: &AppState,
+        conversation_id: Uuid,
+        recipient_id: Uuid,
+    ) -> ChatMessageWithMetadata {
+        let mut messages = get_chat_messages(
+            &state.pool,
+            conversation_id,
+            recipient_id,
+            &PageQuery::default(),
+        )
+        .await
+        .unwrap()
+        .items;
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    #[tokio::test]
+    async fn categorizes_messages_for_the_recipient() {
+        let state = test_state(MockScript::default()).await;
+        let (conversation_id, recipient_id, mut events) =
+            send(&state, "This is urgent, call me asap").await;
+
+        let (message_id, category, source) = next_categorization(&mut events, CATEGORIZED_TIMEOUT)
+            .await
+            .expect("a messageCategorized event");
+        assert_eq!(category, MessageCategory::new(MessageCategory::URGENT));
+        assert_eq!(source, CategorizationSource::Agent);
+
+        let message = recipient_view(&state, conversation_id, recipient_id).await;
+        assert_eq!(message.id, message_id);
+        assert_eq!(message.category, Some(category));
+        assert_eq!(message.category_source, Some(CategorizationSource::Agent));
+        let template = get_active_prompt_template(&state.pool, PromptTemplateName::Categorizer)
+            .await
+            .unwrap();
+        assert_eq!(message.prompt_template_id, Some(template.id));
+
+        let audit = get_audit_entries(
+            &state.pool,
+            recipient_id,
+            &AuditFilter::default(),
+            &PageQuery::default(),
+        )
+        .await
+        .unwrap()
+        .items;
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].action, AuditAction::MessageCategorized);
+        assert_eq!(audit[0].agent, Some(AgentRole::Categorizer));
+        assert_eq!(audit[0].target_id, message_id);
+    }
+
+    #[tokio::test]
+    async fn leaves_messages_uncategorized_when_the_categorizer_fails() {
+        let state = test_state(MockScript::default()).await;
+        let (conversation_id, recipient_id, mut events) =
+            send(&state, "This one breaks the model [mock-error]").await;
+
+        assert!(
+            next_categorization(&mut events, NOT_CATEGORIZED_TIMEOUT)
+                .await
+                .is_none()
+        );
+        let message = recipient_view(&state, conversation_id, recipient_id).await;
+        assert_eq!(message.category, None);
+        assert_eq!(message.category_source, None);
+    }
+
+    #[tokio::test]
+    async fn leaves_messages_uncategorized_when_the_model_is_down() {
+        let state = test_state(MockScript {
+            always_fail: true,
+            ..Default::default()
+        })
+        .await;
+        let (conversation_id, recipient_id, mut events) =
+            send(&state, "This is urgent, call me asap").await;
+
+        assert!(
+            next_categorization(&mut events, NOT_CATEGORIZED_TIMEOUT)
+                .await
+                .is_none()
+        );
+        let message = recipient_view(&state, conversation_id, recipient_id).await;
+        assert_eq!(message.category, None);
+    }
+}
@@ -0,0 +1,336 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::entities::{
+    AuditEntry, ChatMessage, ChatMessageWithMetadata, Comment, Conversation, Digest, Draft,
+    FeedPost, FollowedUser, Post, UnreadMessage,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// ====== Cursor ======
+
+/// A position in a list sorted by `(created_at, id)`.
+/// Clients get it as an opaque string and only ever hand it back to us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        format!(
+            "{:016x}{}",
+            self.created_at.timestamp_micros() as u64,
+            self.id.simple()
+        )
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let micros = u64::from_str_radix(cursor.get(..16)?, 16).ok()?;
+        let id = Uuid::try_parse(cursor.get(16..)?).ok()?;
+        Some(Cursor {
+            created_at: DateTime::from_timestamp_micros(micros as i64)?,
+            id,
+        })
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.encode())
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let cursor = String::deserialize(deserializer)?;
+        Cursor::decode(&cursor).ok_or_else(|| de::Error::custom("invalid cursor"))
+    }
+}
+
+/// Anything that can be listed a page at a time.
+pub trait Paginated {
+    fn cursor(&self) -> Cursor;
+}
+
+impl Paginated for Post {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for FeedPost {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+/// Conversations are listed by their latest activity rather than when they were created.
+impl Paginated for Conversation {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.updated_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for ChatMessage {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for ChatMessageWithMetadata {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for UnreadMessage {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for Comment {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for Draft {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+/// Follower lists are sorted by when the follow started rather than when the user signed up.
+impl Paginated for FollowedUser {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.followed_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for Digest {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+impl Paginated for AuditEntry {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+// ====== Request/Response Structs ======
+
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Number of items to return, at most 100 (default: 50)
+    pub limit: Option<i64>,
+    /// Only return items older than this cursor
+    #[param(value_type = Option<String>)]
+    pub before: Option<Cursor>,
+    /// Only return items newer than this cursor
+    #[param(value_type = Option<String>)]
+    pub after: Option<Cursor>,
+}
+
+/// The bounds of a page, split up so they can be bound to a query.
+pub struct PageBounds {
+    pub before_at: Option<DateTime<Utc>>,
+    pub before_id: Option<Uuid>,
+    pub after_at: Option<DateTime<Utc>>,
+    pub after_id: Option<Uuid>,
+    /// Walk the list from the oldest item, used when paging forward with only `after`
+    pub ascending: bool,
+    /// One more than the page size, to know whether there is another page
+    pub fetch: i64,
+}
+
+impl PageQuery {
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn ascending(&self) -> bool {
+        self.after.is_some() && self.before.is_none()
+    }
+
+    pub fn bounds(&self) -> PageBounds {
+        PageBounds {
+            before_at: self.before.map(|cursor| cursor.created_at),
+            before_id: self.before.map(|cursor| cursor.id),
+            after_at: self.after.map(|cursor| cursor.created_at),
+            after_id: self.after.map(|cursor| cursor.id),
+            ascending: self.ascending(),
+            fetch: self.limit() + 1,
+        }
+    }
+}
+
+/// One page of a list, newest items first.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass this as `before` to get the next page, or as `after` when paging with `after`.
+    /// Unset on the last page.
+    #[schema(value_type = Option<String>)]
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T: Paginated> Page<T> {
+    /// Build a page from rows fetched with `query.bounds()`.
+    pub fn new(rows: Vec<T>, query: &PageQuery) -> Self {
+        Self::with_cursor(rows, query, Paginated::cursor)
+    }
+}
+
+impl<T> Page<T> {
+    /// Build a page from rows fetched with `query.bounds()`,
+    /// for items whose position depends on the list they're in.
+    pub fn with_cursor(mut rows: Vec<T>, query: &PageQuery, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let has_more = rows.len() as i64 > query.limit();
+        rows.truncate(query.limit() as usize);
+        let next_cursor = if has_more {
+            rows.last().map(cursor)
+        } else {
+            None
+        };
+        if query.ascending() {
+            rows.reverse();
+        }
+        Page {
+            items: rows,
+            next_cursor,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item(Cursor);
+
+    impl Paginated for Item {
+        fn cursor(&self) -> Cursor {
+            self.0
+        }
+    }
+
+    fn cursor(seconds: i64) -> Cursor {
+        Cursor {
+            created_at: DateTime::from_timestamp(seconds, 123_456_000).unwrap(),
+            id: Uuid::new_v4(),
+        }
+    }
+
+    fn query(limit: i64, after: Option<Cursor>) -> PageQuery {
+        PageQuery {
+            limit: Some(limit),
+            before: None,
+            after,
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = cursor(1_700_000_000);
+        let encoded = serde_json::to_string(&cursor).unwrap();
+        assert_eq!(serde_json::from_str::<Cursor>(&encoded).unwrap(), cursor);
+    }
+
+    #[test]
+    fn invalid_cursors_are_refused() {
+        for invalid in ["\"\"", "\"0123\"", "\"zzzzzzzzzzzzzzzznot-a-uuid\""] {
+            assert!(
+                serde_json::from_str::<Cursor>(invalid).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        assert_eq!(PageQuery::default().bounds().fetch, DEFAULT_PAGE_SIZE + 1);
+        assert_eq!(query(0, None).bounds().fetch, 2);
+        assert_eq!(query(1_000, None).bounds().fetch, MAX_PAGE_SIZE + 1);
+    }
+
+    #[test]
+    fn pages_point_at_the_next_one() {
+        // Newest first, as the queries return them
+        let cursors = (0..3).rev().map(cursor).collect::<Vec<_>>();
+        let page = Page::new(cursors.iter().copied().map(Item).collect(), &query(2, None));
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.next_cursor, Some(cursors[1]));
+
+        let last = Page::new(cursors.iter().copied().map(Item).collect(), &query(3, None));
+        assert_eq!(last.items.len(), 3);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn pages_after_a_cursor_are_newest_first() {
+        // Oldest first, as the queries return them when paging with only `after`
+        let cursors = (0..3).map(cursor).collect::<Vec<_>>();
+        let page = Page::new(
+            cursors.iter().copied().map(Item).collect(),
+            &query(2, Some(cursor(-1))),
+        );
+        assert_eq!(
+            page.items.iter().map(|item| item.0).collect::<Vec<_>>(),
+            [cursors[1], cursors[0]]
+        );
+        assert_eq!(page.next_cursor, Some(cursors[1]));
+    }
+}
@@ -0,0 +1,504 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::lookup_host;
+use tracing::{debug, warn};
+use url::{Host, Url};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    auth::SessionAuth,
+    entities::{
+        ApiScope, EscalationPolicy, MessageCategory, PinReason, PinnedMessage, claim_escalation,
+        get_due_escalations, get_escalation_policy, get_pinned_message, get_pinned_messages,
+        message_text, pin_message, set_escalation_policy, unpin_message,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    events::{SseEvent, broadcast_event},
+    messaging::get_message_for_user,
+    state::AppState,
+};
+
+const ESCALATION_INTERVAL: Duration = Duration::from_secs(60);
+const ESCALATION_BATCH_SIZE: i64 = 50;
+const MAX_ESCALATION_MINUTES: i64 = 7 * 24 * 60;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetEscalationPolicyRequest {
+    pub enabled: bool,
+    /// How long an urgent message can stay unread before the user is notified again
+    pub after_minutes: i64,
+    /// An `http` or `https` URL that is also POSTed every escalation.
+    /// It has to point to a public address, not to a local or private network.
+    pub webhook_url: Option<String>,
+}
+
+impl SetEscalationPolicyRequest {
+    async fn into_policy(self) -> Result<EscalationPolicy> {
+        let invalid =
+            |message: String| AppError::UserError((LossyError(StatusCode::BAD_REQUEST), message));
+
+        if !(1..=MAX_ESCALATION_MINUTES).contains(&self.after_minutes) {
+            return Err(invalid(format!(
+                "Escalations have to wait between 1 and {MAX_ESCALATION_MINUTES} minutes"
+            )));
+        }
+        let webhook_url = match self.webhook_url.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(url) => match Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+                    resolve_webhook_host(&parsed).await?;
+                    Some(url.to_string())
+                }
+                _ => return Err(invalid("The webhook has to be an http or https URL".into())),
+            },
+        };
+        Ok(EscalationPolicy {
+            enabled: self.enabled,
+            after_minutes: self.after_minutes,
+            webhook_url,
+        })
+    }
+}
+
+/// The body POSTed to a user's webhook when an urgent message is escalated.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EscalationWebhook<'a> {
+    event: &'static str,
+    user_id: Uuid,
+    /// The text parts of the message
+    text: String,
+    message: &'a PinnedMessage,
+}
+
+// ====== Pinning ======
+
+/// Keep the urgent pin of a message in line with the category it was just given for `user_id`.
+/// Urgent messages are pinned, messages recategorized as something else lose their urgent pin.
+/// Pins the user made themselves are never touched.
+pub async fn sync_urgent_pin(
+    state: &AppState,
+    user_id: Uuid,
+    message_id: Uuid,
+    category: &MessageCategory,
+) -> Result<()> {
+    if category.as_str() == MessageCategory::URGENT {
+        if pin_message(&state.pool, user_id, message_id, PinReason::Urgent).await? {
+            if let Some(pinned) = get_pinned_message(&state.pool, user_id, message_id).await? {
+                broadcast_event(&state.clients, &[user_id], &SseEvent::MessagePinned(pinned)).await;
+            }
+        }
+    } else if unpin_message(&state.pool, user_id, message_id, Some(PinReason::Urgent)).await? {
+        broadcast_event(
+            &state.clients,
+            &[user_id],
+            &SseEvent::MessageUnpinned { message_id },
+        )
+        .await;
+    }
+    Ok(())
+}
+
+// ====== Webhooks ======
+
+/// Whether an address is reachable on the public internet.
+/// Webhooks are sent from the server, so anything else would let users probe its own network.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "This network" and carrier-grade NAT
+                || first == 0
+                || (first == 100 && (64..128).contains(&second)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_multicast())
+            }
+        },
+    }
+}
+
+/// Resolve the host of a webhook URL, failing unless every address it resolves to is public.
+async fn resolve_webhook_host(url: &Url) -> Result<Vec<SocketAddr>> {
+    let invalid =
+        |message: String| AppError::UserError((LossyError(StatusCode::BAD_REQUEST), message));
+
+    let (Some(host), Some(port)) = (url.host(), url.port_or_known_default()) else {
+        return Err(invalid("The webhook URL needs a host".into()));
+    };
+    let addresses: Vec<SocketAddr> = match host {
+        Host::Domain(domain) => lookup_host((domain, port))
+            .await
+            .map_err(|_| invalid(format!("Couldn't resolve the webhook host `{domain}`")))?
+            .collect(),
+        Host::Ipv4(ip) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Host::Ipv6(ip) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+    };
+    if addresses.is_empty()
+        || !addresses
+            .iter()
+            .all(|address| is_public_address(address.ip()))
+    {
+        return Err(invalid(
+            "Webhooks can't be sent to local or private addresses".into(),
+        ));
+    }
+    Ok(addresses)
+}
+
+/// POST `body` to a webhook. The host is checked again right before sending since what it
+/// resolves to can change, and the request goes to exactly the addresses that were checked.
+/// Redirects aren't followed, they could point anywhere.
+async fn send_webhook(webhook_url: &str, body: &impl Serialize) -> Result<()> {
+    let url = Url::parse(webhook_url).map_err(|e| {
+        AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            format!("Invalid webhook URL: {e}"),
+        ))
+    })?;
+    let addresses = resolve_webhook_host(&url).await?;
+
+    let mut client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(Policy::none());
+    if let Some(Host::Domain(domain)) = url.host() {
+        client = client.resolve_to_addrs(domain, &addresses);
+    }
+    client
+        .build()?
+        .post(url)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// ====== Escalation ======
+
+async fn escalate(
+    state: &AppState,
+    user_id: Uuid,
+    message_id: Uuid,
+    webhook_url: Option<&str>,
+) -> Result<()> {
+    let Some(pinned) = get_pinned_message(&state.pool, user_id, message_id).await? else {
+        return Ok(());
+    };
+    broadcast_event(
+        &state.clients,
+        &[user_id],
+        &SseEvent::UrgentMessageEscalated(pinned.clone()),
+    )
+    .await;
+
+    if let Some(webhook_url) = webhook_url {
+        let body = EscalationWebhook {
+            event: "urgentMessageEscalated",
+            user_id,
+            text: message_text(&pinned.content),
+            message: &pinned,
+        };
+        match send_webhook(webhook_url, &body).await {
+            Ok(_) => debug!("Sent escalation webhook for {message_id} to {user_id}"),
+            // The user was notified over SSE anyway, so a broken webhook isn't retried
+            Err(e) => warn!("Escalation webhook for {message_id} to {user_id} failed: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Periodically notify users again of urgent messages they still haven't read.
+/// A message counts as read once the user marked its conversation as read after it was sent.
+/// Every urgent message is escalated at most once.
+pub async fn run_escalation_worker(state: AppState) {
+    let default_after_minutes = EscalationPolicy::default().after_minutes;
+    let mut interval = tokio::time::interval(ESCALATION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let due =
+            match get_due_escalations(&state.pool, default_after_minutes, ESCALATION_BATCH_SIZE)
+                .await
+            {
+                Ok(due) => due,
+                Err(e) => {
+                    warn!("Failed to load due escalations: {e}");
+                    continue;
+                }
+            };
+
+        for escalation in due {
+            match claim_escalation(&state.pool, escalation.user_id, escalation.message_id).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!(
+                        "Failed to claim escalation of {} for {}: {e}",
+                        escalation.message_id, escalation.user_id
+                    );
+                    continue;
+                }
+            }
+
+            if let Err(e) = escalate(
+                &state,
+                escalation.user_id,
+                escalation.message_id,
+                escalation.webhook_url.as_deref(),
+            )
+            .await
+            {
+                warn!(
+                    "Failed to escalate {} for {}: {e}",
+                    escalation.message_id, escalation.user_id
+                );
+            }
+        }
+    }
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/messages/pinned",
+    description = "List the current user's pinned messages, most recently pinned first. \
+        Messages categorized as urgent are pinned automatically.",
+    responses(
+        (status = OK, description = "Pinned messages", body = Vec<PinnedMessage>),
+    )
+)]
+pub async fn get_pinned_messages_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let pinned = get_pinned_messages(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(pinned)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/messages/{id}/pin",
+    description = "Pin a message for the current user. Pinning a message that is already pinned keeps the existing pin.",
+    params(
+        ("id" = Uuid, Path, description = "ID of the message to pin")
+    ),
+    responses(
+        (status = OK, description = "The pinned message", body = PinnedMessage),
+        (status = NOT_FOUND, description = "Message not found", body = ErrorResponse),
+    )
+)]
+pub async fn pin_message_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(message_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    let user_id = session.0.id;
+    get_message_for_user(&state.pool, user_id, message_id).await?;
+
+    let pinned_now = pin_message(&state.pool, user_id, message_id, PinReason::Manual).await?;
+    let Some(pinned) = get_pinned_message(&state.pool, user_id, message_id).await? else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Message not found!".into(),
+        )));
+    };
+    if pinned_now {
+        broadcast_event(
+            &state.clients,
+            &[user_id],
+            &SseEvent::MessagePinned(pinned.clone()),
+        )
+        .await;
+    }
+    Ok((StatusCode::OK, Json(pinned)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/messages/{id}/pin",
+    description = "Unpin a message for the current user, including urgent messages that were pinned automatically",
+    params(
+        ("id" = Uuid, Path, description = "ID of the message to unpin")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Message unpinned"),
+        (status = NOT_FOUND, description = "Message isn't pinned", body = ErrorResponse),
+    )
+)]
+pub async fn unpin_message_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(message_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    let user_id = session.0.id;
+    if !unpin_message(&state.pool, user_id, message_id, None).await? {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Pinned message not found!".into(),
+        )));
+    }
+    broadcast_event(
+        &state.clients,
+        &[user_id],
+        &SseEvent::MessageUnpinned { message_id },
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/messages/escalation",
+    description = "Get how the current user is reminded of urgent messages they haven't read",
+    responses(
+        (status = OK, description = "Escalation policy", body = EscalationPolicy),
+    )
+)]
+pub async fn get_escalation_policy_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let policy = get_escalation_policy(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(policy)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/messages/escalation",
+    description = "Set how the current user is reminded of urgent messages they haven't read. \
+        Once an urgent message stayed unread for `afterMinutes`, an `urgentMessageEscalated` event \
+        is sent and the webhook is called, if there is one.",
+    request_body = SetEscalationPolicyRequest,
+    responses(
+        (status = OK, description = "Escalation policy updated", body = EscalationPolicy),
+        (status = BAD_REQUEST, description = "Invalid delay, or a webhook URL that isn't public", body = ErrorResponse),
+    )
+)]
+pub async fn set_escalation_policy_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<SetEscalationPolicyRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    let policy = payload.into_policy().await?;
+    let policy = set_escalation_policy(&state.pool, session.0.id, &policy).await?;
+    Ok((StatusCode::OK, Json(policy)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy_request(after_minutes: i64, webhook_url: Option<&str>) -> SetEscalationPolicyRequest {
+        SetEscalationPolicyRequest {
+            enabled: true,
+            after_minutes,
+            webhook_url: webhook_url.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{ip} is public");
+        }
+    }
+
+    #[test]
+    fn local_and_private_addresses() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "192.0.2.1",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{ip} isn't public");
+        }
+    }
+
+    #[tokio::test]
+    async fn policies_wait_a_bounded_time() {
+        assert!(policy_request(0, None).into_policy().await.is_err());
+        assert!(
+            policy_request(MAX_ESCALATION_MINUTES + 1, None)
+                .into_policy()
+                .await
+                .is_err()
+        );
+        let policy = policy_request(30, Some(" ")).into_policy().await.unwrap();
+        assert_eq!(policy.after_minutes, 30);
+        assert_eq!(policy.webhook_url, None);
+    }
+
+    #[tokio::test]
+    async fn webhooks_have_to_be_public_http_urls() {
+        for url in [
+            "ftp://93.184.216.34/hook",
+            "not a url",
+            "http://127.0.0.1:8080/hook",
+            "http://10.0.0.1/hook",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.0.1]/hook",
+        ] {
+            assert!(
+                policy_request(30, Some(url)).into_policy().await.is_err(),
+                "{url} is refused"
+            );
+        }
+        let policy = policy_request(30, Some("https://93.184.216.34/hook"))
+            .into_policy()
+            .await
+            .unwrap();
+        assert_eq!(
+            policy.webhook_url.as_deref(),
+            Some("https://93.184.216.34/hook")
+        );
+    }
+}
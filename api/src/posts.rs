@@ -4,23 +4,47 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
 use rig::{OneOrMany, message::UserContent};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::time::Duration;
+use tracing::{debug, warn};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
+    audit::{NewAuditEntry, record_audit_entry},
     auth::SessionAuth,
+    drafts::{NewDraft, submit_draft},
     entities::{
-        Delegation, Post, check_delegation, create_delegation, create_post, delete_delegation,
-        delete_post, get_delegated_to_user, get_user_delegations, get_user_posts,
+        ActiveHours, ApiScope, AuditAction, AuditTarget, Comment, Delegation, Draft, DraftKind,
+        DraftSource, FeedPost, Post, ScheduledPost, cancel_scheduled_post, check_delegation,
+        claim_scheduled_post, create_comment, create_delegation, create_post, create_reshare,
+        create_scheduled_post, delete_delegation, delete_post, ensure_media_attachable,
+        get_delegate_ids, get_delegated_to_user, get_delegation, get_due_scheduled_posts,
+        get_home_feed, get_post, get_post_comments, get_user_delegations, get_user_posts,
+        get_user_scheduled_posts, is_user_in_conversation, like_post,
+        set_scheduled_post_attempt_failed, set_scheduled_post_published, unlike_post,
+        update_delegation,
     },
-    error::{AppError, Result},
+    error::{AppError, ErrorResponse, LossyError, Result},
     events::{SseEvent, broadcast_event},
+    pagination::{Page, PageQuery},
     state::AppState,
     utoipa_compat,
 };
 
+const POST_SCHEDULER_INTERVAL: Duration = Duration::from_secs(15);
+const POST_SCHEDULER_BATCH_SIZE: i64 = 50;
+/// How many times publishing a scheduled post is tried before it's marked failed
+const SCHEDULED_POST_MAX_ATTEMPTS: i64 = 5;
+/// How long to wait before the first retry, the delay doubles after every failed one
+const SCHEDULED_POST_RETRY_DELAY: Duration = Duration::from_secs(60);
+const MAX_DELEGATED_CONVERSATIONS: usize = 100;
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
 // ====== Request/Response Structs ======
 
 #[derive(Deserialize, ToSchema)]
@@ -28,6 +52,21 @@ use crate::{
 pub struct CreatePostRequest {
     #[schema(value_type = Vec<utoipa_compat::UserContent>)]
     pub content: OneOrMany<UserContent>,
+    /// Publish the post at this time instead of right away
+    pub scheduled_at: Option<DateTime<Utc>>,
+    /// IDs of media uploaded by the current user to attach to the post
+    #[serde(default)]
+    pub media: Vec<Uuid>,
+}
+
+/// A post that wasn't published right away.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum PendingPost {
+    /// Posted as another user, waiting for their approval
+    Draft(Draft),
+    /// Waiting for its scheduled time
+    Scheduled(ScheduledPost),
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -39,11 +78,168 @@ pub struct ActAsQuery {
 
 #[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct CreateDelegationRequest {
-    pub delegate_id: Uuid,
+pub struct CreateCommentRequest {
+    #[schema(value_type = Vec<utoipa_compat::UserContent>)]
+    pub content: OneOrMany<UserContent>,
+    /// The comment to reply to, omit for a top level comment
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReshareRequest {
+    /// Something to say about the reshared post
+    #[schema(value_type = Option<Vec<utoipa_compat::UserContent>>)]
+    pub content: Option<OneOrMany<UserContent>>,
+}
+
+/// Everything a delegation allows, as it's stored.
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DelegationSettings {
     pub can_post: bool,
+    /// Also lets the delegate read the owner's conversations
     pub can_message: bool,
+    /// Read the owner's conversations without sending messages
+    #[serde(default)]
+    pub can_read_messages: bool,
     pub can_delete_posts: bool,
+    #[serde(default)]
+    pub can_comment: bool,
+    /// Limit reading and messaging to these conversations of the owner, every conversation if unset
+    #[serde(default)]
+    pub conversation_ids: Option<Vec<Uuid>>,
+    /// Stop the delegation at this time, it never expires if unset
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Only let the delegate act during these hours, any time if unset
+    #[serde(default)]
+    pub active_hours: Option<ActiveHours>,
+}
+
+impl DelegationSettings {
+    async fn validate(&mut self, pool: &SqlitePool, owner_id: Uuid) -> Result<()> {
+        let invalid =
+            |message: String| AppError::UserError((LossyError(StatusCode::BAD_REQUEST), message));
+
+        if self.can_message {
+            self.can_read_messages = true;
+        }
+        if let Some(conversation_ids) = &mut self.conversation_ids {
+            conversation_ids.sort_unstable();
+            conversation_ids.dedup();
+            if conversation_ids.is_empty() {
+                return Err(invalid(
+                    "Limit the delegation to at least one conversation, or to none for all of them"
+                        .into(),
+                ));
+            }
+            if conversation_ids.len() > MAX_DELEGATED_CONVERSATIONS {
+                return Err(invalid(format!(
+                    "A delegation can be limited to at most {MAX_DELEGATED_CONVERSATIONS} conversations"
+                )));
+            }
+            for conversation_id in conversation_ids.iter() {
+                if !is_user_in_conversation(pool, owner_id, *conversation_id).await? {
+                    return Err(invalid(format!(
+                        "You aren't a member of conversation {conversation_id}"
+                    )));
+                }
+            }
+        }
+        if let Some(active_hours) = &mut self.active_hours {
+            active_hours
+                .days
+                .sort_unstable_by_key(|day| day.num_days_from_monday());
+            active_hours.days.dedup();
+            if active_hours.days.is_empty() {
+                return Err(invalid("Active hours need at least one day".into()));
+            }
+            if active_hours.start == active_hours.end {
+                return Err(invalid(
+                    "Active hours can't open and close at the same time".into(),
+                ));
+            }
+            if active_hours.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+                return Err(invalid(format!(
+                    "UTC offsets have to be between -{MAX_UTC_OFFSET_MINUTES} and {MAX_UTC_OFFSET_MINUTES} minutes"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Refuse a new expiry that already passed.
+fn ensure_future_expiry(expires_at: Option<DateTime<Utc>>) -> Result<()> {
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Delegations can only expire in the future".into(),
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateDelegationRequest {
+    pub delegate_id: Uuid,
+    #[serde(flatten)]
+    pub settings: DelegationSettings,
+}
+
+/// Fields that are left out keep their value.
+/// `conversationIds`, `expiresAt` and `activeHours` are removed by setting them to `null`.
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateDelegationRequest {
+    pub can_post: Option<bool>,
+    pub can_message: Option<bool>,
+    pub can_read_messages: Option<bool>,
+    pub can_delete_posts: Option<bool>,
+    pub can_comment: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Vec<Uuid>>)]
+    pub conversation_ids: Option<Option<Vec<Uuid>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<ActiveHours>)]
+    pub active_hours: Option<Option<ActiveHours>>,
+}
+
+impl UpdateDelegationRequest {
+    fn apply(self, delegation: Delegation) -> DelegationSettings {
+        DelegationSettings {
+            can_post: self.can_post.unwrap_or(delegation.can_post),
+            can_message: self.can_message.unwrap_or(delegation.can_message),
+            can_read_messages: self
+                .can_read_messages
+                .unwrap_or(delegation.can_read_messages),
+            can_delete_posts: self.can_delete_posts.unwrap_or(delegation.can_delete_posts),
+            can_comment: self.can_comment.unwrap_or(delegation.can_comment),
+            conversation_ids: self.conversation_ids.unwrap_or(
+                delegation
+                    .conversation_ids
+                    .map(|conversation_ids| conversation_ids.0),
+            ),
+            expires_at: self.expires_at.unwrap_or(delegation.expires_at),
+            active_hours: self
+                .active_hours
+                .unwrap_or(delegation.active_hours.map(|active_hours| active_hours.0)),
+        }
+    }
+}
+
+/// Tells a field that was left out (`None`) apart from one that was set to `null` (`Some(None)`).
+fn nullable<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 // ====== Post Endpoints ======
@@ -54,6 +250,8 @@ pub struct CreateDelegationRequest {
     request_body = CreatePostRequest,
     responses(
         (status = CREATED, description = "Post created successfully", body = Post),
+        (status = ACCEPTED, description = "The post was scheduled, or posted as another user and waits for their approval", body = PendingPost),
+        (status = BAD_REQUEST, description = "The scheduled time isn't in the future", body = ErrorResponse),
         (status = FORBIDDEN, description = "Not authorized to post as this user"),
     )
 )]
@@ -63,6 +261,7 @@ pub async fn create_post_handler(
     Query(query): Query<ActAsQuery>,
     Json(payload): Json<CreatePostRequest>,
 ) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
     let created_by = session.0.id;
     let user_id = if let Some(act_as_id) = query.act_as {
         // Check if user has delegation to post as act_as_id
@@ -82,39 +281,105 @@ pub async fn create_post_handler(
         created_by
     };
 
-    let post = create_post(&state.pool, user_id, created_by, payload.content).await?;
+    if payload
+        .scheduled_at
+        .is_some_and(|scheduled_at| scheduled_at <= Utc::now())
+    {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Posts can only be scheduled in the future".into(),
+        )));
+    }
 
-    // Broadcast SSE event for new post
-    let event = SseEvent::NewPost(post.clone());
-    // Send to the post owner and all their delegates
-    let mut recipients = vec![user_id];
+    // Drafts only hold the content until they're approved
+    if !payload.media.is_empty() && user_id != created_by {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Media can only be attached to posts published as yourself".into(),
+        )));
+    }
+
+    // Delegates don't post directly, the owner has to approve what they write
     if user_id != created_by {
-        recipients.push(created_by);
+        let draft = submit_draft(
+            &state,
+            NewDraft {
+                owner_id: user_id,
+                created_by,
+                kind: DraftKind::Post,
+                source: DraftSource::Delegate,
+                conversation_id: None,
+                in_reply_to: None,
+                content: payload.content,
+                scheduled_at: payload.scheduled_at,
+                prompt_template_id: None,
+            },
+        )
+        .await?;
+        return Ok((StatusCode::ACCEPTED, Json(PendingPost::Draft(draft))).into_response());
     }
-    let delegations = get_user_delegations(&state.pool, user_id).await?;
-    for delegation in delegations {
-        recipients.push(delegation.delegate_id);
+
+    if let Some(scheduled_at) = payload.scheduled_at {
+        // The media is only attached once the post is published, but mistakes should show up now
+        ensure_media_attachable(&state.pool, user_id, &payload.media).await?;
+        let scheduled_post = create_scheduled_post(
+            &state.pool,
+            user_id,
+            created_by,
+            payload.content,
+            &payload.media,
+            scheduled_at,
+        )
+        .await?;
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(PendingPost::Scheduled(scheduled_post)),
+        )
+            .into_response());
     }
-    broadcast_event(&state.clients, &recipients, &event).await;
+
+    let post = create_post(
+        &state.pool,
+        user_id,
+        created_by,
+        payload.content,
+        &payload.media,
+    )
+    .await?;
+    broadcast_new_post(&state, &post).await?;
 
     Ok((StatusCode::CREATED, Json(post)).into_response())
 }
 
+/// Let the post owner, whoever created it and all of the owner's delegates know about a new post.
+pub async fn broadcast_new_post(state: &AppState, post: &Post) -> Result<()> {
+    let event = SseEvent::NewPost(post.clone());
+    let mut recipients = vec![post.user_id];
+    if post.user_id != post.created_by {
+        recipients.push(post.created_by);
+    }
+    recipients.extend(get_delegate_ids(&state.pool, post.user_id).await?);
+    broadcast_event(&state.clients, &recipients, &event).await;
+    Ok(())
+}
+
 #[utoipa::path(
     get,
     path = "/api/users/{id}/posts",
     params(
-        ("id" = Uuid, Path, description = "User ID to get posts for")
+        ("id" = Uuid, Path, description = "User ID to get posts for"),
+        PageQuery
     ),
     responses(
-        (status = OK, description = "Posts retrieved successfully", body = Vec<Post>),
+        (status = OK, description = "Posts retrieved successfully", body = Page<Post>),
     )
 )]
 pub async fn get_posts_handler(
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
-    let posts = get_user_posts(&state.pool, user_id).await?;
+    let posts = get_user_posts(&state.pool, user_id, &page).await?;
     Ok((StatusCode::OK, Json(posts)).into_response())
 }
 
@@ -134,10 +399,377 @@ pub async fn delete_post_handler(
     session: SessionAuth,
     Path(post_id): Path<Uuid>,
 ) -> Result<Response> {
-    delete_post(&state.pool, post_id, session.0.id).await?;
+    session.require_scope(ApiScope::PostsWrite)?;
+    let user_id = session.0.id;
+    let mut tx = state.pool.begin().await?;
+    let owner_id = delete_post(&mut *tx, post_id, user_id).await?;
+    if owner_id != user_id {
+        record_audit_entry(
+            &mut tx,
+            NewAuditEntry::new(
+                user_id,
+                owner_id,
+                AuditAction::DelegatedPostDeleted,
+                AuditTarget::Post,
+                post_id,
+            ),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/posts/scheduled",
+    description = "List the posts scheduled to be published as the current user or scheduled by them for someone else",
+    responses(
+        (status = OK, description = "Scheduled posts", body = Vec<ScheduledPost>),
+    )
+)]
+pub async fn get_scheduled_posts_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsRead)?;
+    let scheduled_posts = get_user_scheduled_posts(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(scheduled_posts)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/posts/scheduled/{id}",
+    description = "Cancel a scheduled post before it's published",
+    params(
+        ("id" = Uuid, Path, description = "Scheduled post ID to cancel")
+    ),
+    responses(
+        (status = OK, description = "Scheduled post cancelled", body = ScheduledPost),
+        (status = NOT_FOUND, description = "Pending scheduled post not found", body = ErrorResponse),
+    )
+)]
+pub async fn cancel_scheduled_post_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(scheduled_post_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    let scheduled_post =
+        cancel_scheduled_post(&state.pool, session.0.id, scheduled_post_id).await?;
+    Ok((StatusCode::OK, Json(scheduled_post)).into_response())
+}
+
+// ====== Post Scheduler ======
+
+/// Publish a scheduled post whose time has come.
+/// A delegate's post is only published if they're still allowed to post for the user.
+/// Claiming the job, creating the post, linking the two and auditing happen in one transaction,
+/// so a restart halfway through leaves the job pending rather than losing the post.
+/// Returns `None` if the post was no longer pending, e.g. because it was cancelled in the meantime.
+async fn publish_scheduled_post(
+    state: &AppState,
+    scheduled_post: &ScheduledPost,
+) -> Result<Option<Post>> {
+    if scheduled_post.created_by != scheduled_post.user_id {
+        let delegation = check_delegation(
+            &state.pool,
+            scheduled_post.user_id,
+            scheduled_post.created_by,
+        )
+        .await?;
+        if !delegation.is_some_and(|delegation| delegation.can_post) {
+            return Err(AppError::AuthError(
+                "The delegation to post as this user was revoked or isn't in effect".into(),
+            ));
+        }
+    }
+
+    let mut tx = state.pool.begin().await?;
+    if !claim_scheduled_post(&mut *tx, scheduled_post.id).await? {
+        return Ok(None);
+    }
+    let post = create_post(
+        &mut *tx,
+        scheduled_post.user_id,
+        scheduled_post.created_by,
+        scheduled_post.content.0.clone(),
+        &scheduled_post.media,
+    )
+    .await?;
+    set_scheduled_post_published(&mut *tx, scheduled_post.id, post.id).await?;
+    if post.created_by != post.user_id {
+        record_audit_entry(
+            &mut tx,
+            NewAuditEntry::new(
+                post.created_by,
+                post.user_id,
+                AuditAction::DelegatedPostPublished,
+                AuditTarget::Post,
+                post.id,
+            )
+            .with_details(json!({ "scheduledPostId": scheduled_post.id })),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    // The post is out, failing to notify followers doesn't make publishing fail
+    if let Err(e) = broadcast_new_post(state, &post).await {
+        warn!("Failed to broadcast scheduled post {}: {e}", post.id);
+    }
+    Ok(Some(post))
+}
+
+/// How long to wait before trying to publish a scheduled post again after `attempts` failed,
+/// doubling with every failure.
+fn scheduled_post_retry_delay(attempts: i64) -> chrono::Duration {
+    let doublings = (attempts - 1).clamp(0, 16) as u32;
+    chrono::Duration::seconds(SCHEDULED_POST_RETRY_DELAY.as_secs() as i64 * 2_i64.pow(doublings))
+}
+
+/// Periodically publish scheduled posts that are due.
+/// The schedule lives in the database, so posts that came due while the server was down
+/// are published as soon as it starts again.
+pub async fn run_post_scheduler(state: AppState) {
+    let mut interval = tokio::time::interval(POST_SCHEDULER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let due = match get_due_scheduled_posts(&state.pool, POST_SCHEDULER_BATCH_SIZE).await {
+            Ok(due) => due,
+            Err(e) => {
+                warn!("Failed to load due scheduled posts: {e}");
+                continue;
+            }
+        };
+
+        for scheduled_post in due {
+            run_scheduled_post(&state, &scheduled_post).await;
+        }
+    }
+}
+
+/// Try to publish a due scheduled post, scheduling the next attempt if it fails.
+async fn run_scheduled_post(state: &AppState, scheduled_post: &ScheduledPost) {
+    let error = match publish_scheduled_post(state, scheduled_post).await {
+        Ok(Some(post)) => {
+            debug!(
+                "Published scheduled post {} as {}",
+                scheduled_post.id, post.id
+            );
+            return;
+        }
+        Ok(None) => return,
+        Err(e) => e,
+    };
+    warn!(
+        "Failed to publish scheduled post {}: {error}",
+        scheduled_post.id
+    );
+
+    // Give up once the last attempt failed
+    let attempts = scheduled_post.attempts + 1;
+    let retry_at = (attempts < SCHEDULED_POST_MAX_ATTEMPTS)
+        .then(|| Utc::now() + scheduled_post_retry_delay(attempts));
+    if let Err(e) = set_scheduled_post_attempt_failed(
+        &state.pool,
+        scheduled_post.id,
+        &error.to_string(),
+        retry_at,
+    )
+    .await
+    {
+        warn!("Failed to update scheduled post {}: {e}", scheduled_post.id);
+    }
+}
+
+// ====== Post Interaction Endpoints ======
+
+#[utoipa::path(
+    put,
+    path = "/api/posts/{id}/like",
+    params(
+        ("id" = Uuid, Path, description = "Post ID to like")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Post liked"),
+        (status = NOT_FOUND, description = "Post not found", body = ErrorResponse),
+    )
+)]
+pub async fn like_post_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(post_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    let user_id = session.0.id;
+    let post = get_post(&state.pool, post_id).await?;
+
+    // Liking twice is a no-op, so the owner only hears about the first like
+    if like_post(&state.pool, post.id, user_id).await? && post.user_id != user_id {
+        broadcast_event(
+            &state.clients,
+            &[post.user_id],
+            &SseEvent::PostLiked {
+                post_id: post.id,
+                user_id,
+            },
+        )
+        .await;
+    }
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/posts/{id}/like",
+    params(
+        ("id" = Uuid, Path, description = "Post ID to unlike")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Post unliked"),
+    )
+)]
+pub async fn unlike_post_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(post_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    unlike_post(&state.pool, post_id, session.0.id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/posts/{id}/comments",
+    description = "List the comments on a post, newest first. Replies point at their parent with `parentId`.",
+    params(
+        ("id" = Uuid, Path, description = "Post ID to get comments for"),
+        PageQuery
+    ),
+    responses(
+        (status = OK, description = "Comments retrieved successfully", body = Page<Comment>),
+        (status = NOT_FOUND, description = "Post not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_comments_handler(
+    State(state): State<AppState>,
+    Path(post_id): Path<Uuid>,
+    Query(page): Query<PageQuery>,
+) -> Result<Response> {
+    let post = get_post(&state.pool, post_id).await?;
+    let comments = get_post_comments(&state.pool, post.id, &page).await?;
+    Ok((StatusCode::OK, Json(comments)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/posts/{id}/comments",
+    params(
+        ("id" = Uuid, Path, description = "Post ID to comment on"),
+        ("act_as" = Option<Uuid>, Query, description = "Comment as this user (requires delegation)")
+    ),
+    request_body = CreateCommentRequest,
+    responses(
+        (status = CREATED, description = "Comment created successfully", body = Comment),
+        (status = BAD_REQUEST, description = "The comment being replied to isn't on this post", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Not authorized to comment as this user"),
+        (status = NOT_FOUND, description = "Post not found", body = ErrorResponse),
+    )
+)]
+pub async fn create_comment_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(post_id): Path<Uuid>,
+    Query(query): Query<ActAsQuery>,
+    Json(payload): Json<CreateCommentRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    let created_by = session.0.id;
+    let user_id = if let Some(act_as_id) = query.act_as {
+        match check_delegation(&state.pool, act_as_id, created_by).await? {
+            Some(delegation) if delegation.can_comment => act_as_id,
+            Some(_) => {
+                return Err(AppError::AuthError(
+                    "You don't have permission to comment as this user".into(),
+                ));
+            }
+            None => {
+                return Err(AppError::AuthError(
+                    "You don't have delegation from this user".into(),
+                ));
+            }
+        }
+    } else {
+        created_by
+    };
+    let post = get_post(&state.pool, post_id).await?;
+    let mut tx = state.pool.begin().await?;
+    let comment = create_comment(
+        &mut *tx,
+        post.id,
+        user_id,
+        payload.parent_id,
+        payload.content,
+    )
+    .await?;
+    if user_id != created_by {
+        record_audit_entry(
+            &mut tx,
+            NewAuditEntry::new(
+                created_by,
+                user_id,
+                AuditAction::DelegatedCommentCreated,
+                AuditTarget::Comment,
+                comment.id,
+            )
+            .with_details(json!({ "postId": post.id })),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    if post.user_id != user_id {
+        broadcast_event(
+            &state.clients,
+            &[post.user_id],
+            &SseEvent::NewComment(comment.clone()),
+        )
+        .await;
+    }
+    Ok((StatusCode::CREATED, Json(comment)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/posts/{id}/reshare",
+    description = "Share a post with the current user's followers. Resharing a reshare shares the original post.",
+    params(
+        ("id" = Uuid, Path, description = "Post ID to reshare")
+    ),
+    request_body = ReshareRequest,
+    responses(
+        (status = CREATED, description = "Post reshared", body = Post),
+        (status = NOT_FOUND, description = "Post not found", body = ErrorResponse),
+    )
+)]
+pub async fn reshare_post_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(post_id): Path<Uuid>,
+    Json(payload): Json<ReshareRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    let original = get_post(&state.pool, post_id).await?;
+    let content = payload
+        .content
+        .unwrap_or_else(|| OneOrMany::one(UserContent::text("")));
+    let post = create_reshare(&state.pool, session.0.id, &original, content).await?;
+    broadcast_new_post(&state, &post).await?;
+    Ok((StatusCode::CREATED, Json(post)).into_response())
+}
+
 // ====== Delegation Endpoints ======
 
 #[utoipa::path(
@@ -146,53 +778,122 @@ pub async fn delete_post_handler(
     request_body = CreateDelegationRequest,
     responses(
         (status = CREATED, description = "Delegation created successfully", body = Delegation),
+        (status = BAD_REQUEST, description = "Invalid expiry, conversations or active hours", body = ErrorResponse),
+        (status = CONFLICT, description = "The user is already a delegate, update the delegation instead", body = ErrorResponse),
     )
 )]
 pub async fn create_delegation_handler(
     State(state): State<AppState>,
     session: SessionAuth,
-    Json(payload): Json<CreateDelegationRequest>,
+    Json(mut payload): Json<CreateDelegationRequest>,
 ) -> Result<Response> {
-    let delegation = create_delegation(
-        &state.pool,
-        session.0.id,
-        payload.delegate_id,
-        payload.can_post,
-        payload.can_message,
-        payload.can_delete_posts,
+    session.require_scope(ApiScope::DelegationsManage)?;
+    let owner_id = session.0.id;
+    ensure_future_expiry(payload.settings.expires_at)?;
+    payload.settings.validate(&state.pool, owner_id).await?;
+    let mut tx = state.pool.begin().await?;
+    let delegation =
+        create_delegation(&mut *tx, owner_id, payload.delegate_id, &payload.settings).await?;
+    record_audit_entry(
+        &mut tx,
+        NewAuditEntry::new(
+            delegation.owner_id,
+            delegation.owner_id,
+            AuditAction::DelegationGranted,
+            AuditTarget::User,
+            delegation.delegate_id,
+        )
+        .with_details(json!(delegation)),
     )
     .await?;
+    tx.commit().await?;
 
     Ok((StatusCode::CREATED, Json(delegation)).into_response())
 }
 
+#[utoipa::path(
+    patch,
+    path = "/api/delegations/{delegate_id}",
+    description = "Change what a delegate may do for the current user, when and in which conversations",
+    params(
+        ("delegate_id" = Uuid, Path, description = "Delegate user ID to update")
+    ),
+    request_body = UpdateDelegationRequest,
+    responses(
+        (status = OK, description = "Delegation updated", body = Delegation),
+        (status = BAD_REQUEST, description = "Invalid expiry, conversations or active hours", body = ErrorResponse),
+        (status = NOT_FOUND, description = "Delegation not found", body = ErrorResponse),
+    )
+)]
+pub async fn update_delegation_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(delegate_id): Path<Uuid>,
+    Json(payload): Json<UpdateDelegationRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::DelegationsManage)?;
+    let owner_id = session.0.id;
+    // Only a new expiry has to be in the future, an expired delegation can still be changed
+    ensure_future_expiry(payload.expires_at.flatten())?;
+    let delegation = get_delegation(&state.pool, owner_id, delegate_id).await?;
+    let mut settings = payload.apply(delegation);
+    settings.validate(&state.pool, owner_id).await?;
+    let mut tx = state.pool.begin().await?;
+    let delegation = update_delegation(&mut *tx, owner_id, delegate_id, &settings).await?;
+    record_audit_entry(
+        &mut tx,
+        NewAuditEntry::new(
+            owner_id,
+            owner_id,
+            AuditAction::DelegationUpdated,
+            AuditTarget::User,
+            delegate_id,
+        )
+        .with_details(json!(delegation)),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, Json(delegation)).into_response())
+}
+
 #[utoipa::path(
     get,
     path = "/api/delegations",
+    params(
+        PageQuery
+    ),
     responses(
-        (status = OK, description = "Delegations retrieved", body = Vec<Delegation>),
+        (status = OK, description = "Delegations retrieved", body = Page<Delegation>),
     )
 )]
 pub async fn get_delegations_handler(
     State(state): State<AppState>,
     session: SessionAuth,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
-    let delegations = get_user_delegations(&state.pool, session.0.id).await?;
+    session.require_scope(ApiScope::DelegationsManage)?;
+    let delegations = get_user_delegations(&state.pool, session.0.id, &page).await?;
     Ok((StatusCode::OK, Json(delegations)).into_response())
 }
 
 #[utoipa::path(
     get,
     path = "/api/delegations/received",
+    params(
+        PageQuery
+    ),
     responses(
-        (status = OK, description = "Received delegations retrieved", body = Vec<Delegation>),
+        (status = OK, description = "Received delegations retrieved", body = Page<Delegation>),
     )
 )]
 pub async fn get_received_delegations_handler(
     State(state): State<AppState>,
     session: SessionAuth,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
-    let delegations = get_delegated_to_user(&state.pool, session.0.id).await?;
+    session.require_scope(ApiScope::DelegationsManage)?;
+    let delegations = get_delegated_to_user(&state.pool, session.0.id, &page).await?;
     Ok((StatusCode::OK, Json(delegations)).into_response())
 }
 
@@ -211,58 +912,154 @@ pub async fn revoke_delegation_handler(
     session: SessionAuth,
     Path(delegate_id): Path<Uuid>,
 ) -> Result<Response> {
-    delete_delegation(&state.pool, session.0.id, delegate_id).await?;
+    session.require_scope(ApiScope::DelegationsManage)?;
+    let owner_id = session.0.id;
+    let mut tx = state.pool.begin().await?;
+    if delete_delegation(&mut *tx, owner_id, delegate_id).await? {
+        record_audit_entry(
+            &mut tx,
+            NewAuditEntry::new(
+                owner_id,
+                owner_id,
+                AuditAction::DelegationRevoked,
+                AuditTarget::User,
+                delegate_id,
+            ),
+        )
+        .await?;
+    }
+    tx.commit().await?;
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
 // ====== Feed Endpoint ======
 
-#[derive(Serialize, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub struct FeedResponse {
-    pub posts: Vec<Post>,
-    pub from_users: Vec<Uuid>,
-}
-
 #[utoipa::path(
     get,
     path = "/api/feed",
+    description = "The current user's home feed: their own posts and the posts of everyone they follow, newest first",
+    params(PageQuery),
     responses(
-        (status = OK, description = "Feed retrieved successfully", body = FeedResponse),
+        (status = OK, description = "Feed retrieved successfully", body = Page<FeedPost>),
     )
 )]
 pub async fn get_feed_handler(
     State(state): State<AppState>,
     session: SessionAuth,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
-    let user_id = session.0.id;
+    session.require_scope(ApiScope::PostsRead)?;
+    let posts = get_home_feed(&state.pool, session.0.id, &page).await?;
+    Ok((StatusCode::OK, Json(posts)).into_response())
+}
 
-    // Get posts from:
-    // 1. The user themselves
-    let mut all_posts = get_user_posts(&state.pool, user_id).await?;
-    let mut from_users = vec![user_id];
-
-    // 2. Users who have delegated to them
-    let delegations = get_delegated_to_user(&state.pool, user_id).await?;
-    for delegation in delegations {
-        if delegation.can_post {
-            let posts = get_user_posts(&state.pool, delegation.owner_id).await?;
-            all_posts.extend(posts);
-            from_users.push(delegation.owner_id);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{ScheduledPostStatus, create_media, get_media},
+        llm::MockScript,
+        testing::{sign_up, test_state},
+    };
+
+    async fn schedule(state: &AppState, user_id: Uuid, created_by: Uuid, media: &[Uuid]) {
+        create_scheduled_post(
+            &state.pool,
+            user_id,
+            created_by,
+            OneOrMany::one(UserContent::text("Out now!")),
+            media,
+            Utc::now() + chrono::Duration::hours(1),
+        )
+        .await
+        .unwrap();
+        assert!(due(state).await.is_empty());
     }
 
-    // Sort by created_at descending
-    all_posts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    async fn due(state: &AppState) -> Vec<ScheduledPost> {
+        get_due_scheduled_posts(&state.pool, POST_SCHEDULER_BATCH_SIZE)
+            .await
+            .unwrap()
+    }
 
-    Ok((
-        StatusCode::OK,
-        Json(FeedResponse {
-            posts: all_posts,
-            from_users,
-        }),
-    )
-        .into_response())
-}
+    /// Move the time a post is scheduled or retried at into the past.
+    async fn make_due(state: &AppState) {
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_posts SET
+                scheduled_at = DATETIME('now', '-1 minute'),
+                retry_at = CASE WHEN retry_at IS NULL THEN NULL ELSE DATETIME('now', '-1 minute') END
+            "#
+        )
+        .execute(&state.pool)
+        .await
+        .unwrap();
+    }
+
+    async fn only_scheduled_post(state: &AppState, user_id: Uuid) -> ScheduledPost {
+        let mut scheduled_posts = get_user_scheduled_posts(&state.pool, user_id)
+            .await
+            .unwrap();
+        assert_eq!(scheduled_posts.len(), 1);
+        scheduled_posts.remove(0)
+    }
+
+    #[tokio::test]
+    async fn due_posts_are_published_with_their_media() {
+        let state = test_state(MockScript::default()).await;
+        let user_id = sign_up(&state.pool, "creator").await.0.id;
+        let media = create_media(&state.pool, user_id, "0123abcd", "image/png", 4)
+            .await
+            .unwrap();
+        schedule(&state, user_id, user_id, &[media.id]).await;
+
+        make_due(&state).await;
+        let due_posts = due(&state).await;
+        assert_eq!(due_posts.len(), 1);
+        run_scheduled_post(&state, &due_posts[0]).await;
+
+        let published = only_scheduled_post(&state, user_id).await;
+        assert_eq!(published.status, ScheduledPostStatus::Published);
+        let post_id = published.post_id.expect("the published post");
+        let media = get_media(&state.pool, media.id).await.unwrap().unwrap();
+        assert_eq!(media.post_id, Some(post_id));
+    }
 
-use serde::Serialize;
+    #[tokio::test]
+    async fn failed_posts_are_retried_before_giving_up() {
+        let state = test_state(MockScript::default()).await;
+        let owner_id = sign_up(&state.pool, "owner").await.0.id;
+        // Without a delegation every attempt fails
+        let delegate_id = sign_up(&state.pool, "delegate").await.0.id;
+        schedule(&state, owner_id, delegate_id, &[]).await;
+
+        for attempts in 1..=SCHEDULED_POST_MAX_ATTEMPTS {
+            make_due(&state).await;
+            let due_posts = due(&state).await;
+            assert_eq!(due_posts.len(), 1);
+            run_scheduled_post(&state, &due_posts[0]).await;
+
+            let scheduled_post = only_scheduled_post(&state, owner_id).await;
+            assert_eq!(scheduled_post.attempts, attempts);
+            assert!(scheduled_post.error.is_some());
+            assert_eq!(scheduled_post.post_id, None);
+            if attempts < SCHEDULED_POST_MAX_ATTEMPTS {
+                assert_eq!(scheduled_post.status, ScheduledPostStatus::Pending);
+                assert!(scheduled_post.retry_at.is_some_and(|at| at > Utc::now()));
+            } else {
+                assert_eq!(scheduled_post.status, ScheduledPostStatus::Failed);
+                assert_eq!(scheduled_post.retry_at, None);
+            }
+            // Nothing to do until the next attempt
+            assert!(due(&state).await.is_empty());
+        }
+    }
+
+    #[test]
+    fn retry_delay_doubles() {
+        let delays: Vec<_> = (1..=4)
+            .map(|attempts| scheduled_post_retry_delay(attempts).num_seconds())
+            .collect();
+        assert_eq!(delays, [60, 120, 240, 480]);
+    }
+}
@@ -0,0 +1,298 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use lazy_regex::{Lazy, lazy_regex};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    auth::SessionAuth,
+    entities::{
+        PromptTemplate, PromptTemplateName, create_prompt_template_version,
+        get_active_prompt_template, get_prompt_template_version, get_prompt_template_versions,
+        seed_prompt_template,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    state::AppState,
+};
+
+/// Matches `{{variable}}` placeholders, allowing spaces inside the braces.
+static VARIABLE_REGEX: Lazy<Regex> = lazy_regex!(r"\{\{\s*(\w+)\s*\}\}");
+
+const MAX_TEMPLATE_LEN: usize = 20_000;
+
+// ====== Templates ======
+
+impl PromptTemplateName {
+    pub const ALL: [PromptTemplateName; 6] = [
+        PromptTemplateName::Enhancer,
+        PromptTemplateName::Researcher,
+        PromptTemplateName::Categorizer,
+        PromptTemplateName::Summarizer,
+        PromptTemplateName::Responder,
+        PromptTemplateName::Captioner,
+    ];
+
+    /// The variables the template is rendered with. Every version has to use all of them.
+    pub fn variables(&self) -> &'static [&'static str] {
+        match self {
+            PromptTemplateName::Enhancer => &["text"],
+            PromptTemplateName::Researcher => &["query"],
+            PromptTemplateName::Categorizer => {
+                &["current_message", "message_history", "categories"]
+            }
+            PromptTemplateName::Summarizer => &["period", "activity"],
+            PromptTemplateName::Responder => &["instructions", "conversation"],
+            PromptTemplateName::Captioner => &["count", "description"],
+        }
+    }
+
+    /// The template shipped with the server, saved as version 1 on first start.
+    fn default_body(&self) -> &'static str {
+        match self {
+            PromptTemplateName::Enhancer => include_str!("../prompts/enhancer.md"),
+            PromptTemplateName::Researcher => include_str!("../prompts/researcher.md"),
+            PromptTemplateName::Categorizer => include_str!("../prompts/categorizer.md"),
+            PromptTemplateName::Summarizer => include_str!("../prompts/summarizer.md"),
+            PromptTemplateName::Responder => include_str!("../prompts/responder.md"),
+            PromptTemplateName::Captioner => include_str!("../prompts/captioner.md"),
+        }
+    }
+}
+
+/// Save the built-in templates that don't have a version in the database yet.
+/// Templates that were edited before are left alone, even if the built-in one changed since,
+/// unless they miss a variable the server added later. The built-in one replaces those as a new version.
+pub async fn seed_prompt_templates(pool: &SqlitePool) -> Result<()> {
+    for name in PromptTemplateName::ALL {
+        seed_prompt_template(pool, name, name.default_body()).await?;
+        let active = get_active_prompt_template(pool, name).await?;
+        if validate_template(name, &active.body).is_err() {
+            info!(
+                "The {name:?} prompt template is missing variables, saving the built-in one as version {}",
+                active.version + 1
+            );
+            create_prompt_template_version(pool, name, name.default_body(), None).await?;
+        }
+    }
+    Ok(())
+}
+
+/// A prompt rendered from the active version of a template.
+pub struct RenderedPrompt {
+    pub text: String,
+    /// The template version it was rendered from, recorded with whatever the agent produces
+    pub template_id: Uuid,
+}
+
+/// Fill in the active version of a template with `values`.
+/// Values are inserted as is, placeholders inside them are not expanded.
+pub async fn render_prompt(
+    pool: &SqlitePool,
+    name: PromptTemplateName,
+    values: &[(&str, &str)],
+) -> Result<RenderedPrompt> {
+    let template = get_active_prompt_template(pool, name).await?;
+    let text = VARIABLE_REGEX
+        .replace_all(&template.body, |captures: &Captures| {
+            values
+                .iter()
+                .find(|(variable, _)| *variable == &captures[1])
+                .map(|(_, value)| value.to_string())
+                .unwrap_or_else(|| captures[0].to_string())
+        })
+        .into_owned();
+    Ok(RenderedPrompt {
+        text,
+        template_id: template.id,
+    })
+}
+
+/// Check that a new version of a template uses exactly the variables it's rendered with.
+fn validate_template(name: PromptTemplateName, body: &str) -> Result<()> {
+    let bad_request =
+        |message: String| AppError::UserError((LossyError(StatusCode::BAD_REQUEST), message));
+    if body.trim().is_empty() {
+        return Err(bad_request("The template can't be empty".into()));
+    }
+    if body.len() > MAX_TEMPLATE_LEN {
+        return Err(bad_request(format!(
+            "The template can be at most {MAX_TEMPLATE_LEN} bytes"
+        )));
+    }
+
+    let variables = name.variables();
+    let used = VARIABLE_REGEX
+        .captures_iter(body)
+        .map(|captures| captures.get(1).map_or("", |variable| variable.as_str()))
+        .collect::<Vec<_>>();
+    if let Some(unknown) = used.iter().find(|variable| !variables.contains(variable)) {
+        return Err(bad_request(format!(
+            "Unknown variable `{{{{{unknown}}}}}`, this template can use {}",
+            format_variables(variables)
+        )));
+    }
+    if let Some(missing) = variables.iter().find(|variable| !used.contains(variable)) {
+        return Err(bad_request(format!(
+            "The template has to use `{{{{{missing}}}}}`, it can use {}",
+            format_variables(variables)
+        )));
+    }
+    Ok(())
+}
+
+fn format_variables(variables: &[&str]) -> String {
+    variables
+        .iter()
+        .map(|variable| format!("`{{{{{variable}}}}}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// ====== Request/Response Structs ======
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptTemplateInfo {
+    pub name: PromptTemplateName,
+    /// The `{{variable}}` placeholders every version has to use
+    pub variables: Vec<String>,
+    /// The version agents currently use
+    pub active: PromptTemplate,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatePromptTemplateRequest {
+    /// The new prompt, with `{{variable}}` placeholders
+    pub body: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackPromptTemplateRequest {
+    /// The earlier version to go back to
+    pub version: i64,
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/agents/prompts",
+    description = "List the agent prompt templates with the version each agent currently uses. Only available to admins.",
+    responses(
+        (status = OK, description = "Prompt templates", body = Vec<PromptTemplateInfo>),
+        (status = FORBIDDEN, description = "User is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn list_prompt_templates_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_admin()?;
+    let mut templates = Vec::new();
+    for name in PromptTemplateName::ALL {
+        templates.push(PromptTemplateInfo {
+            name,
+            variables: name.variables().iter().map(ToString::to_string).collect(),
+            active: get_active_prompt_template(&state.pool, name).await?,
+        });
+    }
+    Ok((StatusCode::OK, Json(templates)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/agents/prompts/{name}",
+    description = "List every version of a prompt template, newest first. Only available to admins.",
+    params(
+        ("name" = PromptTemplateName, Path, description = "The template to list the versions of")
+    ),
+    responses(
+        (status = OK, description = "Template versions", body = Vec<PromptTemplate>),
+        (status = FORBIDDEN, description = "User is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn get_prompt_template_versions_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(name): Path<PromptTemplateName>,
+) -> Result<Response> {
+    session.require_admin()?;
+    let versions = get_prompt_template_versions(&state.pool, name).await?;
+    Ok((StatusCode::OK, Json(versions)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/agents/prompts/{name}",
+    description = "Save a new version of a prompt template, agents use it from then on. Only available to admins.",
+    params(
+        ("name" = PromptTemplateName, Path, description = "The template to edit")
+    ),
+    request_body = UpdatePromptTemplateRequest,
+    responses(
+        (status = CREATED, description = "The new version", body = PromptTemplate),
+        (status = BAD_REQUEST, description = "The template is empty or uses the wrong variables", body = ErrorResponse),
+        (status = FORBIDDEN, description = "User is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn update_prompt_template_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(name): Path<PromptTemplateName>,
+    Json(payload): Json<UpdatePromptTemplateRequest>,
+) -> Result<Response> {
+    session.require_admin()?;
+    validate_template(name, &payload.body)?;
+    let template =
+        create_prompt_template_version(&state.pool, name, &payload.body, Some(session.0.id))
+            .await?;
+    Ok((StatusCode::CREATED, Json(template)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/agents/prompts/{name}/rollback",
+    description = "Go back to an earlier version of a prompt template. \
+        Its body is saved as a new version, so the history is never rewritten. Only available to admins.",
+    params(
+        ("name" = PromptTemplateName, Path, description = "The template to roll back")
+    ),
+    request_body = RollbackPromptTemplateRequest,
+    responses(
+        (status = CREATED, description = "The new version", body = PromptTemplate),
+        (status = BAD_REQUEST, description = "The version uses variables the template no longer has", body = ErrorResponse),
+        (status = FORBIDDEN, description = "User is not an admin", body = ErrorResponse),
+        (status = NOT_FOUND, description = "Version not found", body = ErrorResponse),
+    )
+)]
+pub async fn rollback_prompt_template_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(name): Path<PromptTemplateName>,
+    Json(payload): Json<RollbackPromptTemplateRequest>,
+) -> Result<Response> {
+    session.require_admin()?;
+    let Some(previous) = get_prompt_template_version(&state.pool, name, payload.version).await?
+    else {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::NOT_FOUND),
+            "Prompt template version not found!".into(),
+        )));
+    };
+    // Old versions can predate variables that were added since
+    validate_template(name, &previous.body)?;
+    let template =
+        create_prompt_template_version(&state.pool, name, &previous.body, Some(session.0.id))
+            .await?;
+    Ok((StatusCode::CREATED, Json(template)).into_response())
+}
@@ -0,0 +1,451 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use rig::{OneOrMany, message::UserContent};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::debug;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    agents::{self, MAX_REPLY_HISTORY},
+    audit::{NewAuditEntry, record_audit_entry},
+    auth::SessionAuth,
+    drafts::{NewDraft, submit_draft},
+    entities::{
+        ApiScope, AuditAction, AuditTarget, ChatMessage, DraftKind, DraftSource, MessageCategory,
+        ResponderAction, ResponderPolicy, create_chat_message, ensure_category_exists,
+        get_conversation_participants, get_latest_conversation_messages, get_responder_policies,
+        get_responder_policy, set_responder_policy,
+    },
+    error::{ErrorResponse, Result},
+    llm::AgentRole,
+    messaging::deliver_message,
+    state::AppState,
+};
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetResponderPolicyRequest {
+    pub action: ResponderAction,
+    /// Extra guidance for generated replies, e.g. tone or things to mention
+    pub instructions: Option<String>,
+}
+
+// ====== DM Responder ======
+
+/// Run `owner_id`'s DM responder on a message that was just categorized for them.
+/// Depending on their policy for the category this sends a reply as them,
+/// queues one as a draft for their approval or does nothing.
+pub async fn respond_to_message(
+    state: &AppState,
+    owner_id: Uuid,
+    message: &ChatMessage,
+    category: MessageCategory,
+) -> Result<()> {
+    // Never answer agents, two responders would otherwise talk to each other forever
+    if message.agent_authored || message.sender_id == owner_id {
+        return Ok(());
+    }
+
+    let Some(policy) = get_responder_policy(&state.pool, owner_id, category).await? else {
+        return Ok(());
+    };
+    if policy.action == ResponderAction::Ignore {
+        return Ok(());
+    }
+
+    let history =
+        get_latest_conversation_messages(&state.pool, message.conversation_id, MAX_REPLY_HISTORY)
+            .await?;
+    // Someone else may have replied while the message was being categorized
+    if history.last().is_some_and(|last| last.id != message.id) {
+        debug!(
+            "Not responding to {}, the conversation has moved on",
+            message.id
+        );
+        return Ok(());
+    }
+    let participants = get_conversation_participants(&state.pool, message.conversation_id).await?;
+
+    let (reply, prompt_template_id) = agents::generate_reply(
+        state,
+        owner_id,
+        &participants,
+        &history,
+        policy.instructions.as_deref(),
+    )
+    .await?;
+    if reply.is_empty() {
+        return Ok(());
+    }
+
+    match policy.action {
+        ResponderAction::AutoReply => {
+            let content = OneOrMany::one(UserContent::text(reply));
+            let mut tx = state.pool.begin().await?;
+            let reply = create_chat_message(
+                &mut *tx,
+                message.conversation_id,
+                owner_id,
+                content,
+                true,
+                &[],
+            )
+            .await?;
+            record_audit_entry(
+                &mut tx,
+                NewAuditEntry::agent(
+                    AgentRole::Responder,
+                    owner_id,
+                    AuditAction::AgentReplySent,
+                    AuditTarget::Message,
+                    reply.id,
+                )
+                .with_details(json!({
+                    "conversationId": message.conversation_id,
+                    "inReplyTo": message.id,
+                    "promptTemplateId": prompt_template_id,
+                })),
+            )
+            .await?;
+            tx.commit().await?;
+            deliver_reply(state, &reply).await?;
+        }
+        ResponderAction::Draft => {
+            submit_draft(
+                state,
+                NewDraft {
+                    owner_id,
+                    created_by: owner_id,
+                    kind: DraftKind::Message,
+                    source: DraftSource::DmResponder,
+                    conversation_id: Some(message.conversation_id),
+                    in_reply_to: Some(message.id),
+                    content: OneOrMany::one(UserContent::text(reply)),
+                    scheduled_at: None,
+                    prompt_template_id: Some(prompt_template_id),
+                },
+            )
+            .await?;
+        }
+        ResponderAction::Ignore => {}
+    }
+    Ok(())
+}
+
+/// Deliver a reply like any other message, so the other participants get it categorized and pinned.
+/// Their DM responders never answer it, but the future still has to be boxed
+/// since delivering a message is what runs the responder in the first place.
+fn deliver_reply<'a>(state: &'a AppState, reply: &'a ChatMessage) -> BoxFuture<'a, Result<()>> {
+    Box::pin(deliver_message(state, reply))
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/agents/responder/policies",
+    description = "List what the current user's DM responder does with each of their categories. \
+        Categories without a policy are ignored.",
+    responses(
+        (status = OK, description = "Responder policies", body = Vec<ResponderPolicy>),
+    )
+)]
+pub async fn list_responder_policies_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    let policies = get_responder_policies(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(policies)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/agents/responder/policies/{category}",
+    description = "Set what the current user's DM responder does with messages of a category",
+    params(
+        ("category" = MessageCategory, Path, description = "Key of the category the policy applies to")
+    ),
+    request_body = SetResponderPolicyRequest,
+    responses(
+        (status = OK, description = "Responder policy updated", body = ResponderPolicy),
+        (status = BAD_REQUEST, description = "Invalid category or action", body = ErrorResponse),
+    )
+)]
+pub async fn set_responder_policy_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(category): Path<MessageCategory>,
+    Json(payload): Json<SetResponderPolicyRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::AgentsRun)?;
+    ensure_category_exists(&state.pool, session.0.id, &category).await?;
+    let instructions = payload
+        .instructions
+        .as_deref()
+        .map(str::trim)
+        .filter(|instructions| !instructions.is_empty());
+    let policy = set_responder_policy(
+        &state.pool,
+        session.0.id,
+        category,
+        payload.action,
+        instructions,
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(policy)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::broadcast, time::timeout};
+
+    use super::*;
+    use crate::{
+        audit::AuditFilter,
+        entities::{
+            DraftStatus, PromptTemplateName, create_conversation, get_active_prompt_template,
+            get_audit_entries, get_user_drafts, set_kill_switch,
+        },
+        error::AppError,
+        events::SseEvent,
+        llm::MockScript,
+        pagination::PageQuery,
+        testing::{sign_up, subscribe, test_state},
+    };
+
+    /// How long to wait for the reply to be categorized for the other participant
+    const DELIVERED_TIMEOUT: Duration = Duration::from_secs(5);
+
+    const QUESTION: &str = "What camera do you use?";
+    const REPLY: &str = "Thanks for reaching out, I'll get back to you soon!";
+
+    struct Dm {
+        state: AppState,
+        owner_id: Uuid,
+        fan_id: Uuid,
+        conversation_id: Uuid,
+    }
+
+    /// A conversation between an owner whose responder does `action` with general inquiries and a fan.
+    async fn dm(action: Option<ResponderAction>) -> Dm {
+        let mut script = MockScript::default();
+        script
+            .responses
+            .insert("DM Responder Agent".into(), REPLY.into());
+        let state = test_state(script).await;
+        let owner_id = sign_up(&state.pool, "owner").await.0.id;
+        let fan_id = sign_up(&state.pool, "fan").await.0.id;
+        let conversation = create_conversation(&state.pool, &[owner_id, fan_id])
+            .await
+            .unwrap();
+        if let Some(action) = action {
+            set_responder_policy(&state.pool, owner_id, general_inquiry(), action, None)
+                .await
+                .unwrap();
+        }
+        Dm {
+            state,
+            owner_id,
+            fan_id,
+            conversation_id: conversation.id,
+        }
+    }
+
+    fn general_inquiry() -> MessageCategory {
+        MessageCategory::new("generalInquiry")
+    }
+
+    impl Dm {
+        async fn message(&self, sender_id: Uuid, text: &str, agent_authored: bool) -> ChatMessage {
+            create_chat_message(
+                &self.state.pool,
+                self.conversation_id,
+                sender_id,
+                OneOrMany::one(UserContent::text(text)),
+                agent_authored,
+                &[],
+            )
+            .await
+            .unwrap()
+        }
+
+        async fn respond(&self, message: &ChatMessage) -> Result<()> {
+            respond_to_message(&self.state, self.owner_id, message, general_inquiry()).await
+        }
+
+        async fn replies(&self) -> Vec<ChatMessage> {
+            get_latest_conversation_messages(&self.state.pool, self.conversation_id, 10)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|message| message.agent_authored)
+                .collect()
+        }
+
+        async fn drafts(&self) -> usize {
+            get_user_drafts(
+                &self.state.pool,
+                self.owner_id,
+                Some(DraftStatus::Pending),
+                &PageQuery::default(),
+            )
+            .await
+            .unwrap()
+            .items
+            .len()
+        }
+    }
+
+    /// Wait until `message_id` was both sent and categorized, in whichever order that happens.
+    async fn delivered(events: &mut broadcast::Receiver<SseEvent>, message_id: Uuid) -> bool {
+        timeout(DELIVERED_TIMEOUT, async {
+            let (mut sent, mut categorized) = (false, false);
+            while !(sent && categorized) {
+                match events.recv().await {
+                    Ok(SseEvent::NewMessage(message)) => sent |= message.id == message_id,
+                    Ok(SseEvent::MessageCategorized { message_id: id, .. }) => {
+                        categorized |= id == message_id
+                    }
+                    Ok(_) => {}
+                    Err(_) => return false,
+                }
+            }
+            true
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn auto_replies_are_delivered_like_any_message() {
+        let dm = dm(Some(ResponderAction::AutoReply)).await;
+        let mut fan_events = subscribe(&dm.state, dm.fan_id);
+        let message = dm.message(dm.fan_id, QUESTION, false).await;
+        dm.respond(&message).await.unwrap();
+
+        let replies = dm.replies().await;
+        assert_eq!(replies.len(), 1);
+        let reply = &replies[0];
+        assert_eq!(reply.sender_id, dm.owner_id);
+        assert_eq!(reply.text(), REPLY);
+        assert_eq!(dm.drafts().await, 0);
+
+        // The fan is sent the reply and gets it categorized, like a message the owner wrote
+        assert!(delivered(&mut fan_events, reply.id).await);
+
+        let audit = get_audit_entries(
+            &dm.state.pool,
+            dm.owner_id,
+            &AuditFilter {
+                action: Some(AuditAction::AgentReplySent),
+                ..Default::default()
+            },
+            &PageQuery::default(),
+        )
+        .await
+        .unwrap()
+        .items;
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].target_id, reply.id);
+        assert_eq!(audit[0].agent, Some(AgentRole::Responder));
+    }
+
+    #[tokio::test]
+    async fn drafts_wait_for_approval() {
+        let dm = dm(Some(ResponderAction::Draft)).await;
+        let message = dm.message(dm.fan_id, QUESTION, false).await;
+        dm.respond(&message).await.unwrap();
+
+        assert!(dm.replies().await.is_empty());
+        assert_eq!(dm.drafts().await, 1);
+    }
+
+    #[tokio::test]
+    async fn drafts_record_the_prompt_template() {
+        let dm = dm(Some(ResponderAction::Draft)).await;
+        let message = dm.message(dm.fan_id, QUESTION, false).await;
+        dm.respond(&message).await.unwrap();
+
+        let template = get_active_prompt_template(&dm.state.pool, PromptTemplateName::Responder)
+            .await
+            .unwrap();
+        let drafts = get_user_drafts(
+            &dm.state.pool,
+            dm.owner_id,
+            Some(DraftStatus::Pending),
+            &PageQuery::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(drafts.items[0].prompt_template_id, Some(template.id));
+    }
+
+    #[tokio::test]
+    async fn does_nothing_without_a_policy() {
+        for action in [None, Some(ResponderAction::Ignore)] {
+            let dm = dm(action).await;
+            let message = dm.message(dm.fan_id, QUESTION, false).await;
+            dm.respond(&message).await.unwrap();
+
+            assert!(dm.replies().await.is_empty());
+            assert_eq!(dm.drafts().await, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn never_answers_agents_or_the_owner() {
+        let dm = dm(Some(ResponderAction::AutoReply)).await;
+        let from_an_agent = dm.message(dm.fan_id, "Hi from my assistant", true).await;
+        dm.respond(&from_an_agent).await.unwrap();
+        let from_the_owner = dm.message(dm.owner_id, "Hi!", false).await;
+        dm.respond(&from_the_owner).await.unwrap();
+
+        // Only the fan's agent wrote anything
+        assert_eq!(dm.replies().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_answers_the_latest_message() {
+        let dm = dm(Some(ResponderAction::AutoReply)).await;
+        let earlier = dm.message(dm.fan_id, "Hello?", false).await;
+        // Messages sent within the same second would otherwise be ordered by their IDs
+        sqlx::query!(
+            "UPDATE messages SET created_at = DATETIME('now', '-1 minute') WHERE id = ?",
+            earlier.id
+        )
+        .execute(&dm.state.pool)
+        .await
+        .unwrap();
+        dm.message(dm.fan_id, "Never mind, found it", false).await;
+
+        dm.respond(&earlier).await.unwrap();
+        assert!(dm.replies().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stops_when_agents_are_disabled() {
+        let dm = dm(Some(ResponderAction::AutoReply)).await;
+        set_kill_switch(&dm.state.pool, dm.owner_id, true, dm.owner_id)
+            .await
+            .unwrap();
+        let message = dm.message(dm.fan_id, QUESTION, false).await;
+
+        assert!(matches!(
+            dm.respond(&message).await,
+            Err(AppError::KillSwitchEngaged(_))
+        ));
+        assert!(dm.replies().await.is_empty());
+    }
+}
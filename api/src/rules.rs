@@ -0,0 +1,400 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use regex::RegexBuilder;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    agents::MessageCategorization,
+    auth::SessionAuth,
+    entities::{
+        ApiScope, CategorizationRule, ChatMessage, MessageCategory, RuleCondition,
+        create_categorization_rule, delete_categorization_rule, ensure_category_exists,
+        get_user_categorization_rules, has_corresponded_with_sender, update_categorization_rule,
+    },
+    error::{AppError, ErrorResponse, LossyError, Result},
+    state::AppState,
+};
+
+/// Keeps user supplied patterns from compiling into huge automatons.
+const REGEX_SIZE_LIMIT: usize = 1 << 16;
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategorizationRuleRequest {
+    pub name: String,
+    pub condition: RuleCondition,
+    /// The category given to messages the rule matches
+    pub category: MessageCategory,
+    /// Why the message got its category, shown like the LLM's reasoning
+    pub reasoning: String,
+    /// Rules are evaluated from the lowest to the highest priority
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl CategorizationRuleRequest {
+    fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| {
+            Err(AppError::UserError((
+                LossyError(StatusCode::BAD_REQUEST),
+                message.into(),
+            )))
+        };
+
+        if self.name.trim().is_empty() {
+            return invalid("Rule name can't be empty");
+        }
+        match &self.condition {
+            RuleCondition::Sender { user_ids } if user_ids.is_empty() => {
+                invalid("A sender rule needs at least one user")
+            }
+            RuleCondition::Keywords { keywords }
+                if keywords.iter().all(|keyword| keyword.trim().is_empty()) =>
+            {
+                invalid("A keyword rule needs at least one keyword")
+            }
+            RuleCondition::Regex { pattern } => match compile(pattern) {
+                Ok(_) => Ok(()),
+                Err(e) => invalid(&format!("Invalid regular expression: {e}")),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+fn compile(pattern: &str) -> std::result::Result<regex::Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+// ====== Rule Evaluation ======
+
+impl RuleCondition {
+    /// Whether a received message satisfies the condition.
+    /// `known_sender` is whether the recipient and the sender have corresponded before.
+    pub fn matches(&self, message: &ChatMessage, text: &str, known_sender: bool) -> bool {
+        match self {
+            RuleCondition::Sender { user_ids } => user_ids.contains(&message.sender_id),
+            RuleCondition::Keywords { keywords } => {
+                let text = text.to_lowercase();
+                keywords
+                    .iter()
+                    .map(|keyword| keyword.trim().to_lowercase())
+                    .any(|keyword| !keyword.is_empty() && text.contains(&keyword))
+            }
+            // Patterns are validated when the rule is saved, so this only fails on old rules
+            RuleCondition::Regex { pattern } => {
+                compile(pattern).is_ok_and(|regex| regex.is_match(text))
+            }
+            RuleCondition::FirstMessageFromUnknownSender => !known_sender,
+        }
+    }
+
+    /// Whether matching needs to know if the sender is known.
+    fn depends_on_history(&self) -> bool {
+        matches!(self, RuleCondition::FirstMessageFromUnknownSender)
+    }
+}
+
+/// Categorize a message with the first of `user_id`'s enabled rules that matches it.
+/// Returns `None` when no rule matches and the LLM should decide instead.
+pub async fn apply_categorization_rules(
+    pool: &SqlitePool,
+    user_id: Uuid,
+    message: &ChatMessage,
+) -> Result<Option<MessageCategorization>> {
+    let rules: Vec<_> = get_user_categorization_rules(pool, user_id)
+        .await?
+        .into_iter()
+        .filter(|rule| rule.enabled)
+        .collect();
+    // Looking through every conversation is only worth it when a rule asks
+    let checks_sender = rules.iter().any(|rule| rule.condition.depends_on_history());
+    let known_sender =
+        !checks_sender || has_corresponded_with_sender(pool, user_id, message).await?;
+
+    let text = message.text();
+    Ok(rules
+        .into_iter()
+        .find(|rule| rule.condition.matches(message, &text, known_sender))
+        .map(|rule| MessageCategorization {
+            reasoning: rule.reasoning,
+            category: rule.category,
+        }))
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    get,
+    path = "/api/categorization_rules",
+    description = "List the current user's message categorization rules in evaluation order",
+    responses(
+        (status = OK, description = "Categorization rules", body = Vec<CategorizationRule>),
+    )
+)]
+pub async fn list_rules_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesRead)?;
+    let rules = get_user_categorization_rules(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(rules)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/categorization_rules",
+    description = "Create a rule that categorizes matching incoming messages without asking the LLM",
+    request_body = CategorizationRuleRequest,
+    responses(
+        (status = CREATED, description = "Categorization rule created", body = CategorizationRule),
+        (status = BAD_REQUEST, description = "Invalid rule or unknown category", body = ErrorResponse),
+    )
+)]
+pub async fn create_rule_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<CategorizationRuleRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    payload.validate()?;
+    ensure_category_exists(&state.pool, session.0.id, &payload.category).await?;
+    let rule = create_categorization_rule(&state.pool, session.0.id, &payload).await?;
+    Ok((StatusCode::CREATED, Json(rule)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/categorization_rules/{id}",
+    description = "Replace one of the current user's categorization rules",
+    params(
+        ("id" = Uuid, Path, description = "ID of the rule to update")
+    ),
+    request_body = CategorizationRuleRequest,
+    responses(
+        (status = OK, description = "Categorization rule updated", body = CategorizationRule),
+        (status = BAD_REQUEST, description = "Invalid rule or unknown category", body = ErrorResponse),
+        (status = NOT_FOUND, description = "Categorization rule not found", body = ErrorResponse),
+    )
+)]
+pub async fn update_rule_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(rule_id): Path<Uuid>,
+    Json(payload): Json<CategorizationRuleRequest>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    payload.validate()?;
+    ensure_category_exists(&state.pool, session.0.id, &payload.category).await?;
+    let rule = update_categorization_rule(&state.pool, session.0.id, rule_id, &payload).await?;
+    Ok((StatusCode::OK, Json(rule)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/categorization_rules/{id}",
+    description = "Delete one of the current user's categorization rules",
+    params(
+        ("id" = Uuid, Path, description = "ID of the rule to delete")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Categorization rule deleted"),
+        (status = NOT_FOUND, description = "Categorization rule not found", body = ErrorResponse),
+    )
+)]
+pub async fn delete_rule_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(rule_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::MessagesWrite)?;
+    delete_categorization_rule(&state.pool, session.0.id, rule_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use rig::{OneOrMany, message::UserContent};
+
+    use super::*;
+    use crate::{
+        entities::{create_chat_message, create_conversation},
+        testing::{sign_up, test_pool},
+    };
+
+    async fn send(pool: &SqlitePool, sender: Uuid, recipient: Uuid, text: &str) -> ChatMessage {
+        let conversation = create_conversation(pool, &[sender, recipient])
+            .await
+            .unwrap();
+        create_chat_message(
+            pool,
+            conversation.id,
+            sender,
+            OneOrMany::one(UserContent::text(text)),
+            false,
+            &[],
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn add_rule(pool: &SqlitePool, user_id: Uuid, category: &str, condition: RuleCondition) {
+        let rule = CategorizationRuleRequest {
+            name: category.into(),
+            condition,
+            category: MessageCategory::new(category),
+            reasoning: format!("Matched the {category} rule"),
+            priority: 0,
+            enabled: true,
+        };
+        create_categorization_rule(pool, user_id, &rule)
+            .await
+            .unwrap();
+    }
+
+    async fn category(pool: &SqlitePool, user_id: Uuid, message: &ChatMessage) -> Option<String> {
+        apply_categorization_rules(pool, user_id, message)
+            .await
+            .unwrap()
+            .map(|categorization| categorization.category.0)
+    }
+
+    #[tokio::test]
+    async fn conditions_match_the_message() {
+        let pool = test_pool().await;
+        let owner = sign_up(&pool, "owner").await.0.id;
+        let brand = sign_up(&pool, "brand").await.0.id;
+        let friend = sign_up(&pool, "friend").await.0.id;
+        add_rule(
+            &pool,
+            owner,
+            "spam",
+            RuleCondition::Regex {
+                pattern: r"(?i)free \w+ giveaway".into(),
+            },
+        )
+        .await;
+        add_rule(
+            &pool,
+            owner,
+            "sponsorship",
+            RuleCondition::Keywords {
+                keywords: vec!["  ".into(), "PARTNERSHIP".into()],
+            },
+        )
+        .await;
+        add_rule(
+            &pool,
+            owner,
+            "important",
+            RuleCondition::Sender {
+                user_ids: vec![friend],
+            },
+        )
+        .await;
+
+        let message = send(&pool, brand, owner, "A paid partnership?").await;
+        assert_eq!(
+            category(&pool, owner, &message).await.as_deref(),
+            Some("sponsorship")
+        );
+        let message = send(&pool, brand, owner, "Join our FREE iPhone giveaway").await;
+        assert_eq!(
+            category(&pool, owner, &message).await.as_deref(),
+            Some("spam")
+        );
+        let message = send(&pool, friend, owner, "Lunch tomorrow?").await;
+        assert_eq!(
+            category(&pool, owner, &message).await.as_deref(),
+            Some("important")
+        );
+        // Blank keywords don't match everything
+        let message = send(&pool, brand, owner, "Hello there").await;
+        assert_eq!(category(&pool, owner, &message).await, None);
+    }
+
+    #[tokio::test]
+    async fn disabled_rules_and_priorities() {
+        let pool = test_pool().await;
+        let owner = sign_up(&pool, "owner").await.0.id;
+        let sender = sign_up(&pool, "sender").await.0.id;
+        for (category, priority, enabled) in [
+            ("spam", 0, false),
+            ("networking", 2, true),
+            ("important", 1, true),
+        ] {
+            let rule = CategorizationRuleRequest {
+                name: category.into(),
+                condition: RuleCondition::Sender {
+                    user_ids: vec![sender],
+                },
+                category: MessageCategory::new(category),
+                reasoning: "Known sender".into(),
+                priority,
+                enabled,
+            };
+            create_categorization_rule(&pool, owner, &rule)
+                .await
+                .unwrap();
+        }
+
+        let message = send(&pool, sender, owner, "Hi").await;
+        let categorization = apply_categorization_rules(&pool, owner, &message)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(categorization.category.as_str(), "important");
+        assert_eq!(categorization.reasoning, "Known sender");
+    }
+
+    #[tokio::test]
+    async fn unknown_senders_are_known_from_any_conversation() {
+        let pool = test_pool().await;
+        let owner = sign_up(&pool, "owner").await.0.id;
+        let fan = sign_up(&pool, "fan").await.0.id;
+        let contact = sign_up(&pool, "contact").await.0.id;
+        add_rule(
+            &pool,
+            owner,
+            "networking",
+            RuleCondition::FirstMessageFromUnknownSender,
+        )
+        .await;
+
+        let first = send(&pool, fan, owner, "Big fan!").await;
+        assert_eq!(
+            category(&pool, owner, &first).await.as_deref(),
+            Some("networking")
+        );
+        // Re-evaluating the first message doesn't count the message itself
+        assert_eq!(
+            category(&pool, owner, &first).await.as_deref(),
+            Some("networking")
+        );
+        // Starting another conversation doesn't make them a stranger again
+        let second = send(&pool, fan, owner, "Me again").await;
+        assert_eq!(category(&pool, owner, &second).await, None);
+
+        // Neither does writing back first, in a different conversation
+        send(&pool, owner, contact, "Are you free next week?").await;
+        let reply = send(&pool, contact, owner, "Sure").await;
+        assert_eq!(category(&pool, owner, &reply).await, None);
+    }
+}
@@ -1,11 +1,13 @@
-use std::sync::Arc;
+use std::{future::Future, sync::Arc};
 
 use papaya::HashMap;
 use sqlx::SqlitePool;
 use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
 use uuid::Uuid;
 
-use crate::events::SseEvent;
+use crate::{events::SseEvent, llm::LlmRegistry};
 
 // A sender for a client's broadcast channel.
 // The string is a JSON-encoded event.
@@ -18,13 +20,78 @@ pub type ClientMap = HashMap<Uuid, ClientTx>;
 pub struct AppState {
     pub pool: SqlitePool,
     pub clients: Arc<ClientMap>,
+    pub agent_tasks: Arc<AgentTasks>,
+    /// The model backends agents send their prompts to
+    pub llm: Arc<LlmRegistry>,
 }
 
 impl AppState {
-    pub fn new(pool: SqlitePool) -> Self {
+    pub fn new(pool: SqlitePool, llm: LlmRegistry) -> Self {
         Self {
             pool,
             clients: Default::default(),
+            agent_tasks: Default::default(),
+            llm: Arc::new(llm),
         }
     }
 }
+
+/// Keeps track of the cancellation tokens for background agent work
+/// so that engaging a kill switch stops tasks that are already running.
+#[derive(Debug, Default)]
+pub struct AgentTasks {
+    global: std::sync::Mutex<CancellationToken>,
+    users: HashMap<Uuid, CancellationToken>,
+}
+
+impl AgentTasks {
+    /// Spawn agent work on behalf of `user_id`.
+    /// The task is dropped as soon as either the user's or the global kill switch is engaged.
+    pub fn spawn<F>(&self, user_id: Uuid, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn_or_cancel(user_id, task, async {});
+    }
+
+    /// Like [`AgentTasks::spawn`], but runs `on_cancel` once a kill switch stopped the task,
+    /// e.g. to tell a client that is waiting for its output.
+    pub fn spawn_or_cancel<F, C>(&self, user_id: Uuid, task: F, on_cancel: C)
+    where
+        F: Future<Output = ()> + Send + 'static,
+        C: Future<Output = ()> + Send + 'static,
+    {
+        let global = self.global.lock().unwrap().clone();
+        let user = self
+            .users
+            .pin()
+            .get_or_insert_with(user_id, CancellationToken::new)
+            .clone();
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = global.cancelled() => {
+                    debug!("Agent task for {user_id} cancelled by the global kill switch")
+                }
+                _ = user.cancelled() => {
+                    debug!("Agent task for {user_id} cancelled by their kill switch")
+                }
+                _ = task => return,
+            }
+            on_cancel.await;
+        });
+    }
+
+    /// Cancel every running agent task for a user.
+    pub fn cancel_user(&self, user_id: Uuid) {
+        if let Some(token) = self.users.pin().remove(&user_id) {
+            token.cancel();
+        }
+    }
+
+    /// Cancel every running agent task for every user.
+    pub fn cancel_all(&self) {
+        let old = std::mem::take(&mut *self.global.lock().unwrap());
+        old.cancel();
+    }
+}
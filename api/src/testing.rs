@@ -0,0 +1,101 @@
+//! Shared setup for tests: an in-memory database with every migration applied,
+//! app state with mock agents and signed in users.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{Request, header::AUTHORIZATION},
+};
+use sqlx::{
+    SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Credential, SessionAuth, generate_api_token},
+    entities::{ApiScope, create_api_token, create_session, create_user},
+    error::Result,
+    events::SseEvent,
+    llm::{LlmRegistry, MockScript},
+    prompts::seed_prompt_templates,
+    state::AppState,
+    users::CreateUser,
+};
+
+/// A fresh database, set up like `init_db` does.
+pub async fn test_pool() -> SqlitePool {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:")
+        .expect("valid database URL")
+        .foreign_keys(true);
+    // Every connection to `:memory:` opens its own database, so there must only ever be one
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .expect("in-memory database");
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .expect("migrations");
+    seed_prompt_templates(&pool)
+        .await
+        .expect("built-in prompt templates");
+    pool
+}
+
+pub async fn test_state(script: MockScript) -> AppState {
+    AppState::new(test_pool().await, LlmRegistry::mock(script))
+}
+
+/// Register `username` and sign them in with a session cookie.
+pub async fn sign_up(pool: &SqlitePool, username: &str) -> SessionAuth {
+    let user = create_user(
+        pool,
+        &CreateUser {
+            username: username.into(),
+            password: "not a real hash".into(),
+        },
+    )
+    .await
+    .expect("new user");
+    let session = create_session(pool, user.id, None, None)
+        .await
+        .expect("new session");
+    SessionAuth(user, Credential::Session(session))
+}
+
+/// Authenticate a request carrying `Authorization: Bearer <token>`, as the API would.
+pub async fn authenticate_token(state: &AppState, token: &str) -> Result<SessionAuth> {
+    let (mut parts, ()) = Request::builder()
+        .header(AUTHORIZATION, format!("Bearer {token}"))
+        .body(())
+        .expect("valid request")
+        .into_parts();
+    SessionAuth::from_request_parts(&mut parts, state).await
+}
+
+/// Give `user_id` a new API token limited to `scopes` and authenticate with it.
+pub async fn sign_in_with_token(
+    state: &AppState,
+    user_id: Uuid,
+    scopes: &[ApiScope],
+) -> SessionAuth {
+    let (token, hash) = generate_api_token();
+    create_api_token(&state.pool, user_id, "test", &hash, scopes, None)
+        .await
+        .expect("new token");
+    authenticate_token(state, &token)
+        .await
+        .expect("valid token")
+}
+
+/// Receive the events sent to `user_id`, as the `/api/events` stream would.
+pub fn subscribe(state: &AppState, user_id: Uuid) -> broadcast::Receiver<SseEvent> {
+    let (tx, _) = broadcast::channel(64);
+    state.clients.pin().get_or_insert(user_id, tx).subscribe()
+}
@@ -0,0 +1,150 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    auth::{SessionAuth, generate_api_token},
+    entities::{ApiScope, ApiToken, create_api_token, delete_api_token, get_user_api_tokens},
+    error::{AppError, ErrorResponse, LossyError, Result},
+    state::AppState,
+};
+
+// ====== Request/Response Structs ======
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiTokenRequest {
+    /// A label to recognize the token by, e.g. the script or agent using it
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// When the token stops working. The token never expires if omitted.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiTokenResponse {
+    #[serde(flatten)]
+    pub token: ApiToken,
+    /// The token to send as `Authorization: Bearer <token>`.
+    /// This is only ever shown once.
+    pub secret: String,
+}
+
+// ====== Endpoint Handlers ======
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    description = "Create a personal API token. Can only be done from a browser session.",
+    request_body = CreateApiTokenRequest,
+    responses(
+        (status = CREATED, description = "API token created", body = CreateApiTokenResponse),
+        (status = BAD_REQUEST, description = "Invalid token name, scopes or expiry", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Tokens can't create other tokens", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn create_api_token_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<CreateApiTokenRequest>,
+) -> Result<Response> {
+    session.require_session()?;
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Token name can't be empty".into(),
+        )));
+    }
+    if payload.scopes.is_empty() {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "A token needs at least one scope".into(),
+        )));
+    }
+    if payload
+        .expires_at
+        .is_some_and(|expires| expires <= Utc::now())
+    {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::BAD_REQUEST),
+            "Token expiry must be in the future".into(),
+        )));
+    }
+
+    let (secret, hash) = generate_api_token();
+    let token = create_api_token(
+        &state.pool,
+        session.0.id,
+        name,
+        &hash,
+        &payload.scopes,
+        payload.expires_at,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiTokenResponse { token, secret }),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    description = "List the current user's API tokens",
+    responses(
+        (status = OK, description = "API tokens", body = Vec<ApiToken>),
+        (status = FORBIDDEN, description = "Tokens can't list other tokens", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn list_api_tokens_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+) -> Result<Response> {
+    session.require_session()?;
+    let tokens = get_user_api_tokens(&state.pool, session.0.id).await?;
+    Ok((StatusCode::OK, Json(tokens)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}",
+    description = "Revoke one of the current user's API tokens",
+    params(
+        ("id" = Uuid, Path, description = "ID of the token to revoke")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "API token revoked"),
+        (status = NOT_FOUND, description = "API token not found", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Tokens can't revoke other tokens", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn revoke_api_token_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(token_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_session()?;
+    delete_api_token(&state.pool, session.0.id, token_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
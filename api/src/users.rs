@@ -1,21 +1,31 @@
+use std::net::SocketAddr;
+
 use axum::{
     Json,
-    extract::{Path, Query, State},
-    http::{StatusCode, header::SET_COOKIE},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{SET_COOKIE, USER_AGENT},
+    },
     response::{AppendHeaders, IntoResponse, Response},
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use url::Url;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    auth::SessionAuth,
+    ADMIN_USERNAMES,
+    auth::{PasswordCheck, SessionAuth, hash_password, session_cookies, verify_password},
     entities::{
-        Conversation, User, create_session, create_user, get_all_users, get_user, get_user_by_id,
-        get_user_conversations, search_users,
+        ApiScope, Conversation, FollowedUser, ProfileLink, Session, User, create_session,
+        create_user, delete_session, delete_user_session, follow_user, get_all_users,
+        get_followers, get_following, get_user, get_user_by_id, get_user_conversations,
+        get_user_sessions, search_users, unfollow_user, update_user_password, update_user_profile,
     },
     error::{AppError, ErrorResponse, LossyError, Result},
+    pagination::{Page, PageQuery},
     state::AppState,
 };
 
@@ -39,8 +49,9 @@ pub struct CreateUser {
 )]
 pub async fn register(
     State(state): State<AppState>,
-    Json(user): Json<CreateUser>,
+    Json(mut user): Json<CreateUser>,
 ) -> Result<Response> {
+    user.password = hash_password(user.password).await?;
     create_user(&state.pool, &user).await?;
     Ok((StatusCode::CREATED).into_response())
 }
@@ -64,6 +75,8 @@ pub struct LoginUser {
 )]
 pub async fn login(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(login_user): Json<LoginUser>,
 ) -> Result<Response> {
     let Some(user) = get_user(&state.pool, login_user.username).await? else {
@@ -74,49 +87,270 @@ pub async fn login(
     };
 
     // Verify the provided password against the hashed password
-    if login_user.password != user.password {
-        return Err(AppError::UserError((
-            LossyError(StatusCode::UNAUTHORIZED),
-            "Invalid username or password".into(),
-        )));
+    match verify_password(login_user.password.clone(), user.password).await? {
+        PasswordCheck::Invalid => {
+            return Err(AppError::UserError((
+                LossyError(StatusCode::UNAUTHORIZED),
+                "Invalid username or password".into(),
+            )));
+        }
+        PasswordCheck::ValidNeedsRehash => {
+            // Legacy plaintext row, upgrade it now that we know the password
+            let hash = hash_password(login_user.password).await?;
+            update_user_password(&state.pool, user.id, &hash).await?;
+        }
+        PasswordCheck::Valid => {}
     }
 
-    let session = create_session(&state.pool, user.id).await?;
+    let user_agent = headers.get(USER_AGENT).and_then(|h| h.to_str().ok());
+    let session = create_session(
+        &state.pool,
+        user.id,
+        user_agent,
+        Some(addr.ip().to_string()),
+    )
+    .await?;
 
-    // For development with cross-origin requests (localhost:3000 -> localhost:6969)
-    // In production, you'd want Secure=true and proper domain settings
+    let [session_cookie, authenticated_cookie] = session_cookies(&session);
     Ok((
         StatusCode::OK,
         AppendHeaders([
-            (
-                SET_COOKIE,
-                format!(
-                    "session={}; HttpOnly; Max-Age=34560000; Path=/; SameSite=Lax",
-                    session.id
-                ),
-            ),
-            (
-                SET_COOKIE,
-                "authenticated=true; Max-Age=34560000; Path=/; SameSite=Lax".to_string(),
-            ),
+            (SET_COOKIE, session_cookie),
+            (SET_COOKIE, authenticated_cookie),
+        ]),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    description = "Log out of the current session",
+    responses(
+        (status = NO_CONTENT, description = "User successfully logged out"),
+        (status = UNAUTHORIZED, description = "User is not authenticated", body = ErrorResponse)
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn logout(State(state): State<AppState>, auth: SessionAuth) -> Result<Response> {
+    let session = auth.require_session()?;
+    delete_session(&state.pool, session.id).await?;
+    Ok((
+        StatusCode::NO_CONTENT,
+        AppendHeaders([
+            (SET_COOKIE, "session=; HttpOnly; Max-Age=0; Path=/"),
+            (SET_COOKIE, "authenticated=; Max-Age=0; Path=/"),
         ]),
     )
         .into_response())
 }
 
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub session: Session,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sessions",
+    description = "List the active sessions of the current user",
+    responses(
+        (status = OK, description = "Active sessions", body = Vec<SessionInfo>),
+        (status = UNAUTHORIZED, description = "User is not authenticated", body = ErrorResponse)
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn list_sessions_handler(
+    State(state): State<AppState>,
+    auth: SessionAuth,
+) -> Result<Response> {
+    let current = auth.require_session()?;
+    let sessions: Vec<SessionInfo> = get_user_sessions(&state.pool, auth.0.id)
+        .await?
+        .into_iter()
+        .map(|session| SessionInfo {
+            current: session.id == current.id,
+            session,
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(sessions)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/sessions/{id}",
+    description = "Revoke one of the current user's sessions",
+    params(
+        ("id" = Uuid, Path, description = "Public ID of the session to revoke")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Session revoked"),
+        (status = NOT_FOUND, description = "Session not found", body = ErrorResponse),
+        (status = UNAUTHORIZED, description = "User is not authenticated", body = ErrorResponse)
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn revoke_session_handler(
+    State(state): State<AppState>,
+    auth: SessionAuth,
+    Path(public_id): Path<Uuid>,
+) -> Result<Response> {
+    auth.require_session()?;
+    delete_user_session(&state.pool, auth.0.id, public_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
 #[utoipa::path(
     get,
     path = "/api/profile",
     responses(
         (status = OK, description = "Current user profile", body = User),
-        (status = FORBIDDEN, description = "User is not authenticated"),
+        (status = UNAUTHORIZED, description = "User is not authenticated", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Requested with an API token", body = ErrorResponse),
     ),
     security(
         ("lokr_session_cookie" = [])
     )
 )]
-pub async fn get_profile(SessionAuth(user): SessionAuth) -> Result<Json<User>> {
-    Ok(Json(user))
+pub async fn get_profile(session: SessionAuth) -> Result<Json<User>> {
+    session.require_session()?;
+    Ok(Json(session.0))
+}
+
+const MAX_USERNAME_LEN: usize = 32;
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+const MAX_BIO_LEN: usize = 500;
+const MAX_PROFILE_LINKS: usize = 5;
+
+/// Fields left out are kept as they are. An empty display name or bio removes it.
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfileRequest {
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    /// Replaces all of the user's links
+    pub links: Option<Vec<ProfileLink>>,
+}
+
+impl UpdateProfileRequest {
+    fn validate(&self) -> Result<()> {
+        let invalid = |message: String| {
+            Err(AppError::UserError((
+                LossyError(StatusCode::BAD_REQUEST),
+                message,
+            )))
+        };
+
+        if let Some(username) = &self.username {
+            if username.is_empty() || username.chars().count() > MAX_USERNAME_LEN {
+                return invalid(format!(
+                    "Usernames have to be between 1 and {MAX_USERNAME_LEN} characters"
+                ));
+            }
+            if username.chars().any(char::is_whitespace) {
+                return invalid("Usernames can't contain whitespace".into());
+            }
+        }
+        if self
+            .display_name
+            .as_ref()
+            .is_some_and(|name| name.trim().chars().count() > MAX_DISPLAY_NAME_LEN)
+        {
+            return invalid(format!(
+                "Display names can be at most {MAX_DISPLAY_NAME_LEN} characters"
+            ));
+        }
+        if self
+            .bio
+            .as_ref()
+            .is_some_and(|bio| bio.trim().chars().count() > MAX_BIO_LEN)
+        {
+            return invalid(format!("Bios can be at most {MAX_BIO_LEN} characters"));
+        }
+        if let Some(links) = &self.links {
+            if links.len() > MAX_PROFILE_LINKS {
+                return invalid(format!("At most {MAX_PROFILE_LINKS} links are allowed"));
+            }
+            for link in links {
+                if link.label.trim().is_empty() {
+                    return invalid("Links need a label".into());
+                }
+                let is_web_url =
+                    Url::parse(&link.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+                if !is_web_url {
+                    return invalid(format!("`{}` isn't an http(s) URL", link.url));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Trim an optional text field, treating an empty value as removing it.
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|value| !value.is_empty())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/profile",
+    description = "Change the current user's username, display name, bio or links",
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = OK, description = "Updated profile", body = User),
+        (status = BAD_REQUEST, description = "Invalid profile field", body = ErrorResponse),
+        (status = FORBIDDEN, description = "Renaming to or from an admin username", body = ErrorResponse),
+        (status = CONFLICT, description = "Username already in use", body = ErrorResponse),
+    ),
+    security(
+        ("lokr_session_cookie" = [])
+    )
+)]
+pub async fn update_profile_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Json(payload): Json<UpdateProfileRequest>,
+) -> Result<Response> {
+    session.require_session()?;
+    payload.validate()?;
+    let user = &session.0;
+
+    let username = payload.username.as_deref().unwrap_or(&user.username);
+    // Admins are picked by username, so renaming must neither take an admin's name
+    // (even one nobody registered yet) nor move an admin away from theirs
+    let is_admin_name = |name: &str| ADMIN_USERNAMES.contains(&name.to_lowercase());
+    if username.to_lowercase() != user.username.to_lowercase()
+        && (is_admin_name(username) || is_admin_name(&user.username))
+    {
+        return Err(AppError::UserError((
+            LossyError(StatusCode::FORBIDDEN),
+            "Admin usernames can only be changed through CLONEOPS_ADMINS".into(),
+        )));
+    }
+    let display_name = match &payload.display_name {
+        Some(display_name) => non_empty(display_name),
+        None => user.display_name.as_deref(),
+    };
+    let bio = match &payload.bio {
+        Some(bio) => non_empty(bio),
+        None => user.bio.as_deref(),
+    };
+    let links = payload.links.as_deref().unwrap_or(&user.links.0);
+
+    let user =
+        update_user_profile(&state.pool, user.id, username, display_name, bio, links).await?;
+    Ok((StatusCode::OK, Json(user)).into_response())
 }
 
 // ====== User Search Endpoints ======
@@ -143,14 +377,42 @@ fn default_limit() -> i64 {
 pub struct PublicUser {
     pub id: Uuid,
     pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub links: Vec<ProfileLink>,
+    /// Where to get the user's avatar, unset if they have none.
+    /// Changes whenever the avatar does, so it can be cached.
+    pub avatar_url: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
+fn avatar_url(user_id: Uuid, avatar_updated_at: Option<DateTime<Utc>>) -> Option<String> {
+    avatar_updated_at.map(|at| format!("/api/users/{user_id}/avatar?v={}", at.timestamp()))
+}
+
 impl From<User> for PublicUser {
     fn from(user: User) -> Self {
         PublicUser {
+            avatar_url: avatar_url(user.id, user.avatar_updated_at),
             id: user.id,
             username: user.username,
+            display_name: user.display_name,
+            bio: user.bio,
+            links: user.links.0,
+            created_at: user.created_at,
+        }
+    }
+}
+
+impl From<FollowedUser> for PublicUser {
+    fn from(user: FollowedUser) -> Self {
+        PublicUser {
+            avatar_url: avatar_url(user.id, user.avatar_updated_at),
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            bio: user.bio,
+            links: user.links.0,
             created_at: user.created_at,
         }
     }
@@ -218,16 +480,19 @@ pub async fn get_user_handler(
 #[utoipa::path(
     get,
     path = "/api/users/me/conversations",
+    params(PageQuery),
     responses(
-        (status = OK, description = "User conversations", body = Vec<Conversation>),
-        (status = FORBIDDEN, description = "Not authenticated"),
+        (status = OK, description = "User conversations, most recently active first", body = Page<Conversation>),
+        (status = FORBIDDEN, description = "Not authenticated, or an API token without `messages:read`"),
     )
 )]
 pub async fn get_my_conversations_handler(
     State(state): State<AppState>,
     session: SessionAuth,
+    Query(page): Query<PageQuery>,
 ) -> Result<Response> {
-    let conversations = get_user_conversations(&state.pool, session.0.id).await?;
+    session.require_scope(ApiScope::MessagesRead)?;
+    let conversations = get_user_conversations(&state.pool, session.0.id, None, &page).await?;
     Ok((StatusCode::OK, Json(conversations)).into_response())
 }
 
@@ -246,7 +511,174 @@ pub async fn delete_user_handler(
     State(state): State<AppState>,
     session: SessionAuth,
 ) -> Result<Response> {
+    session.require_session()?;
     // Delete user and associated data
     crate::entities::delete_user(&state.pool, session.0.id).await?;
     Ok(StatusCode::NO_CONTENT.into_response())
 }
+
+// ====== Follow Endpoints ======
+
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}/follow",
+    description = "Follow a user so their posts show up in the home feed",
+    params(
+        ("id" = Uuid, Path, description = "User ID to follow")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "User followed"),
+        (status = BAD_REQUEST, description = "Users can't follow themselves", body = ErrorResponse),
+        (status = NOT_FOUND, description = "User not found", body = ErrorResponse),
+    )
+)]
+pub async fn follow_user_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(user_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    follow_user(&state.pool, session.0.id, user_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}/follow",
+    params(
+        ("id" = Uuid, Path, description = "User ID to unfollow")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "User unfollowed"),
+    )
+)]
+pub async fn unfollow_user_handler(
+    State(state): State<AppState>,
+    session: SessionAuth,
+    Path(user_id): Path<Uuid>,
+) -> Result<Response> {
+    session.require_scope(ApiScope::PostsWrite)?;
+    unfollow_user(&state.pool, session.0.id, user_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/followers",
+    params(
+        ("id" = Uuid, Path, description = "User ID to get followers for"),
+        PageQuery
+    ),
+    responses(
+        (status = OK, description = "Users following the user", body = Page<PublicUser>),
+    )
+)]
+pub async fn get_followers_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    Query(page): Query<PageQuery>,
+) -> Result<Response> {
+    let users = get_followers(&state.pool, user_id, &page)
+        .await?
+        .map(PublicUser::from);
+    Ok((StatusCode::OK, Json(users)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/following",
+    params(
+        ("id" = Uuid, Path, description = "User ID to get followed users for"),
+        PageQuery
+    ),
+    responses(
+        (status = OK, description = "Users the user follows", body = Page<PublicUser>),
+    )
+)]
+pub async fn get_following_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    Query(page): Query<PageQuery>,
+) -> Result<Response> {
+    let users = get_following(&state.pool, user_id, &page)
+        .await?
+        .map(PublicUser::from);
+    Ok((StatusCode::OK, Json(users)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::*;
+    use crate::{
+        llm::MockScript,
+        testing::{sign_in_with_token, sign_up, test_state},
+    };
+
+    async fn log_in(state: &AppState, username: &str, password: &str) -> Result<Response> {
+        login(
+            State(state.clone()),
+            ConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))),
+            HeaderMap::new(),
+            Json(LoginUser {
+                username: username.into(),
+                password: password.into(),
+            }),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn plaintext_passwords_are_rehashed_on_login() {
+        let state = test_state(MockScript::default()).await;
+        // `sign_up` stores its password as is, like rows from before passwords were hashed
+        let user = sign_up(&state.pool, "legacy").await;
+        assert_eq!(user.0.password, "not a real hash");
+
+        assert!(matches!(
+            log_in(&state, "legacy", "wrong").await,
+            Err(AppError::UserError(_))
+        ));
+        let stored = get_user_by_id(&state.pool, user.0.id).await.unwrap();
+        assert_eq!(stored.password, "not a real hash");
+
+        let response = log_in(&state, "legacy", "not a real hash").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stored = get_user_by_id(&state.pool, user.0.id).await.unwrap();
+        assert!(stored.password.starts_with("$argon2id$"));
+        assert_eq!(
+            verify_password("not a real hash".into(), stored.password.clone())
+                .await
+                .unwrap(),
+            PasswordCheck::Valid
+        );
+
+        // And the hash keeps working from then on
+        let response = log_in(&state, "legacy", "not a real hash").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let rehashed = get_user_by_id(&state.pool, user.0.id).await.unwrap();
+        assert_eq!(rehashed.password, stored.password);
+    }
+
+    /// The status the API would respond with.
+    fn status(result: Result<Response>) -> StatusCode {
+        result.unwrap_or_else(IntoResponse::into_response).status()
+    }
+
+    #[tokio::test]
+    async fn listing_conversations_needs_messages_read() {
+        let state = test_state(MockScript::default()).await;
+        let session = sign_up(&state.pool, "owner").await;
+        let user_id = session.0.id;
+        let list = |auth| {
+            get_my_conversations_handler(State(state.clone()), auth, Query(PageQuery::default()))
+        };
+
+        assert_eq!(status(list(session).await), StatusCode::OK);
+        let posts_only = sign_in_with_token(&state, user_id, &[ApiScope::PostsRead]).await;
+        assert_eq!(status(list(posts_only).await), StatusCode::FORBIDDEN);
+        let messages = sign_in_with_token(&state, user_id, &[ApiScope::MessagesRead]).await;
+        assert_eq!(status(list(messages).await), StatusCode::OK);
+    }
+}
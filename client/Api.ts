@@ -78,6 +78,8 @@ export type Audio = {
 };
 
 export interface ChatMessage {
+  /** Written by the sender's DM responder agent rather than the sender themselves */
+  agentAuthored: boolean;
   content: string;
   /** @format uuid */
   conversationId: string;
@@ -85,6 +87,8 @@ export interface ChatMessage {
   createdAt: string;
   /** @format uuid */
   id: string;
+  /** IDs of the attached media, served from `/api/media/{id}` */
+  media: string[];
   /** @format uuid */
   senderId: string;
   /** @format date-time */
@@ -146,15 +150,27 @@ export interface CreateUser {
 }
 
 export interface Delegation {
+  canComment: boolean;
   canDeletePosts: boolean;
   canMessage: boolean;
   canPost: boolean;
+  /** Read the owner's conversations, implied by `canMessage` */
+  canReadMessages: boolean;
+  /** The conversations reading and messaging are limited to, every conversation if unset */
+  conversationIds?: string[] | null;
   /** @format date-time */
   createdAt: string;
   /** @format uuid */
   delegateId: string;
+  /**
+   * The delegation stops working at this time, it never does if unset
+   * @format date-time
+   */
+  expiresAt?: string | null;
   /** @format uuid */
   ownerId: string;
+  /** @format date-time */
+  updatedAt?: string | null;
 }
 
 /** Document content containing document data and metadata about it. */
@@ -195,9 +211,30 @@ export interface ErrorResponse {
   type: string;
 }
 
-export interface FeedResponse {
-  fromUsers: string[];
-  posts: Post[];
+/** A post along with how people interacted with it, as seen by the viewer. */
+export interface FeedPost {
+  /** @format int64 */
+  commentCount: number;
+  content: UserContent[];
+  /** @format date-time */
+  createdAt: string;
+  /** @format uuid */
+  createdBy: string;
+  /** @format uuid */
+  id: string;
+  /** @format int64 */
+  likeCount: number;
+  /** Whether the viewer liked the post */
+  liked: boolean;
+  media: string[];
+  /** @format uuid */
+  reshareOf?: string | null;
+  /** @format int64 */
+  reshareCount: number;
+  /** @format date-time */
+  updatedAt: string;
+  /** @format uuid */
+  userId: string;
 }
 
 /** Image content containing image data and metadata about it. */
@@ -217,6 +254,66 @@ export interface LoginUser {
   username: string;
 }
 
+/** One page of a list, newest items first. */
+export interface PageChatMessage {
+  items: ChatMessage[];
+  /**
+   * Pass this as `before` to get the next page, or as `after` when paging with `after`.
+   * Unset on the last page.
+   */
+  nextCursor?: string | null;
+}
+
+/** One page of a list, newest items first. */
+export interface PageChatMessageWithMetadata {
+  items: ChatMessageWithMetadata[];
+  /**
+   * Pass this as `before` to get the next page, or as `after` when paging with `after`.
+   * Unset on the last page.
+   */
+  nextCursor?: string | null;
+}
+
+/** One page of a list, newest items first. */
+export interface PageConversation {
+  items: Conversation[];
+  /**
+   * Pass this as `before` to get the next page, or as `after` when paging with `after`.
+   * Unset on the last page.
+   */
+  nextCursor?: string | null;
+}
+
+/** One page of a list, newest items first. */
+export interface PageDelegation {
+  items: Delegation[];
+  /**
+   * Pass this as `before` to get the next page, or as `after` when paging with `after`.
+   * Unset on the last page.
+   */
+  nextCursor?: string | null;
+}
+
+/** One page of a list, newest items first. */
+export interface PageFeedPost {
+  items: FeedPost[];
+  /**
+   * Pass this as `before` to get the next page, or as `after` when paging with `after`.
+   * Unset on the last page.
+   */
+  nextCursor?: string | null;
+}
+
+/** One page of a list, newest items first. */
+export interface PagePost {
+  items: Post[];
+  /**
+   * Pass this as `before` to get the next page, or as `after` when paging with `after`.
+   * Unset on the last page.
+   */
+  nextCursor?: string | null;
+}
+
 export interface Post {
   content: UserContent[];
   /** @format date-time */
@@ -225,6 +322,13 @@ export interface Post {
   createdBy: string;
   /** @format uuid */
   id: string;
+  /** IDs of the attached media, served from `/api/media/{id}` */
+  media: string[];
+  /**
+   * The post this one reshares
+   * @format uuid
+   */
+  reshareOf?: string | null;
   /** @format date-time */
   updatedAt: string;
   /** @format uuid */
@@ -668,10 +772,19 @@ export class Api<
          * @format uuid
          */
         user_id?: string;
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
       },
       params: RequestParams = {},
     ) =>
-      this.request<Conversation[], void>({
+      this.request<PageConversation, void>({
         path: `/api/conversations`,
         method: "GET",
         query: query,
@@ -742,10 +855,25 @@ export class Api<
      * @name GetMessagesHandler
      * @request GET:/api/conversations/{id}/messages
      */
-    getMessagesHandler: (id: string, params: RequestParams = {}) =>
-      this.request<ChatMessage[], void>({
+    getMessagesHandler: (
+      id: string,
+      query?: {
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
+      },
+      params: RequestParams = {},
+    ) =>
+      this.request<PageChatMessage, void>({
         path: `/api/conversations/${id}/messages`,
         method: "GET",
+        query: query,
         format: "json",
         ...params,
       }),
@@ -778,10 +906,25 @@ export class Api<
      * @name GetCategorizedMessagesHandler
      * @request GET:/api/conversations/{id}/messages/categorized
      */
-    getCategorizedMessagesHandler: (id: string, params: RequestParams = {}) =>
-      this.request<ChatMessageWithMetadata[], void>({
+    getCategorizedMessagesHandler: (
+      id: string,
+      query?: {
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
+      },
+      params: RequestParams = {},
+    ) =>
+      this.request<PageChatMessageWithMetadata, void>({
         path: `/api/conversations/${id}/messages/categorized`,
         method: "GET",
+        query: query,
         format: "json",
         ...params,
       }),
@@ -813,10 +956,24 @@ export class Api<
      * @name GetDelegationsHandler
      * @request GET:/api/delegations
      */
-    getDelegationsHandler: (params: RequestParams = {}) =>
-      this.request<Delegation[], any>({
+    getDelegationsHandler: (
+      query?: {
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
+      },
+      params: RequestParams = {},
+    ) =>
+      this.request<PageDelegation, any>({
         path: `/api/delegations`,
         method: "GET",
+        query: query,
         format: "json",
         ...params,
       }),
@@ -848,10 +1005,24 @@ export class Api<
      * @name GetReceivedDelegationsHandler
      * @request GET:/api/delegations/received
      */
-    getReceivedDelegationsHandler: (params: RequestParams = {}) =>
-      this.request<Delegation[], any>({
+    getReceivedDelegationsHandler: (
+      query?: {
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
+      },
+      params: RequestParams = {},
+    ) =>
+      this.request<PageDelegation, any>({
         path: `/api/delegations/received`,
         method: "GET",
+        query: query,
         format: "json",
         ...params,
       }),
@@ -892,10 +1063,24 @@ export class Api<
      * @name GetFeedHandler
      * @request GET:/api/feed
      */
-    getFeedHandler: (params: RequestParams = {}) =>
-      this.request<FeedResponse, any>({
+    getFeedHandler: (
+      query?: {
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
+      },
+      params: RequestParams = {},
+    ) =>
+      this.request<PageFeedPost, any>({
         path: `/api/feed`,
         method: "GET",
+        query: query,
         format: "json",
         ...params,
       }),
@@ -1040,10 +1225,25 @@ export class Api<
      * @name GetPostsHandler
      * @request GET:/api/users/{id}/posts
      */
-    getPostsHandler: (id: string, params: RequestParams = {}) =>
-      this.request<Post[], any>({
+    getPostsHandler: (
+      id: string,
+      query?: {
+        /**
+         * Number of items to return, at most 100 (default: 50)
+         * @format int64
+         */
+        limit?: number | null;
+        /** Only return items older than this cursor */
+        before?: string | null;
+        /** Only return items newer than this cursor */
+        after?: string | null;
+      },
+      params: RequestParams = {},
+    ) =>
+      this.request<PagePost, any>({
         path: `/api/users/${id}/posts`,
         method: "GET",
+        query: query,
         format: "json",
         ...params,
       }),
@@ -2,7 +2,7 @@
 
 import { useState, useEffect } from "react";
 import { useSession } from "@/components/session-provider";
-import { API } from "@/lib/api";
+import { API, fetchAllPages } from "@/lib/api";
 import { Post as ApiPost, UserContent, Image, Document, Audio, Video } from "@/Api";
 import { 
   Card, 
@@ -61,8 +61,9 @@ export default function ProfilePage() {
     if (!user) return;
     
     try {
-      const response = await API.api.getPostsHandler(user.id);
-      const rawPosts: ApiPost[] = response.data;
+      const rawPosts: ApiPost[] = await fetchAllPages((before) =>
+        API.api.getPostsHandler(user.id, { before }),
+      );
 
       // Process posts to extract captions and media from content
       const processedPosts = rawPosts.map(post => {
@@ -8,7 +8,7 @@ import { Label } from "@/components/ui/label"
 import { Avatar, AvatarFallback } from "@/components/ui/avatar"
 import { Input } from "@/components/ui/input"
 import { Users, UserCheck, UserX, Settings, Crown, UserPlus, Search, Trash2, ShieldAlert, LogIn } from "lucide-react"
-import { API } from "@/lib/api"
+import { API, fetchAllPages } from "@/lib/api"
 import { useSession } from "@/components/session-provider"
 import { useRouter } from "next/navigation"
 
@@ -130,8 +130,9 @@ export function AccessControls() {
   const loadDelegations = async () => {
     try {
       setIsLoadingDelegations(true);
-      const response = await API.api.getDelegationsHandler();
-      setDelegations(response.data);
+      setDelegations(
+        await fetchAllPages((before) => API.api.getDelegationsHandler({ before })),
+      );
     } catch (error) {
       console.error("Error loading delegations:", error);
     } finally {
@@ -142,8 +143,11 @@ export function AccessControls() {
   const loadReceivedDelegations = async () => {
     try {
       setIsLoadingReceivedDelegations(true);
-      const response = await API.api.getReceivedDelegationsHandler();
-      setReceivedDelegations(response.data);
+      setReceivedDelegations(
+        await fetchAllPages((before) =>
+          API.api.getReceivedDelegationsHandler({ before }),
+        ),
+      );
     } catch (error) {
       console.error("Error loading received delegations:", error);
     } finally {
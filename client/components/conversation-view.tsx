@@ -9,7 +9,7 @@ import { Avatar, AvatarFallback, AvatarImage } from "@/components/ui/avatar";
 import { MessageSquare, Send, Bot, Users } from "lucide-react";
 import { Dialog, DialogContent, DialogHeader, DialogTitle, DialogTrigger } from "@/components/ui/dialog";
 import { Input } from "@/components/ui/input";
-import { API } from "@/lib/api";
+import { API, fetchAllPages } from "@/lib/api";
 import { useRealtimeEvents } from "@/components/realtime-event-handler";
 import { useSession } from "@/components/session-provider";
 
@@ -84,6 +84,8 @@ export function ConversationView() {
   const [conversations, setConversations] = useState<Conversation[]>([]);
   const [selectedConversation, setSelectedConversation] = useState<Conversation | null>(null);
   const [messages, setMessages] = useState<Message[]>([]);
+  const [olderMessagesCursor, setOlderMessagesCursor] = useState<string | null>(null);
+  const [loadingOlderMessages, setLoadingOlderMessages] = useState(false);
   const [newMessage, setNewMessage] = useState("");
   const [loading, setLoading] = useState(true);
   const [isGenerating, setIsGenerating] = useState(false);
@@ -100,8 +102,9 @@ export function ConversationView() {
     const fetchData = async () => {
       try {
         // Fetch from the API - use the endpoint that returns conversation with participants
-        const response = await API.api.listConversationsHandler();
-        const fetchedConversations = response.data;
+        const fetchedConversations = await fetchAllPages(before =>
+          API.api.listConversationsHandler({ before })
+        );
         
         setConversations(fetchedConversations);
         setLoading(false);
@@ -133,10 +136,11 @@ export function ConversationView() {
       const fetchMessages = async () => {
         try {
           // Fetch from the API
+          // Only the latest page, older messages are loaded on demand
           const response = await API.api.getMessagesHandler(selectedConversation.id);
-          const fetchedMessages = response.data;
           
-          setMessages(fetchedMessages);
+          setMessages(response.data.items);
+          setOlderMessagesCursor(response.data.nextCursor ?? null);
         } catch (error) {
           console.error("Error fetching messages:", error);
           
@@ -160,7 +164,7 @@ export function ConversationView() {
         // For now, we'll use a mock implementation for invitations
         // The generated API doesn't seem to have invitation-specific endpoints
         // But let's try to get conversations that the user is a part of
-        const response = await API.api.listConversationsHandler();
+        await API.api.listConversationsHandler();
         // We'll need to implement invitation logic manually if needed
         setInvitations([]);
       } catch (error) {
@@ -196,8 +200,9 @@ export function ConversationView() {
         else {
           const fetchData = async () => {
             try {
-              const response = await API.api.listConversationsHandler();
-              const fetchedConversations = response.data;
+              const fetchedConversations = await fetchAllPages(before =>
+                API.api.listConversationsHandler({ before })
+              );
               setConversations(fetchedConversations);
             } catch (error) {
               console.error("Error fetching conversations:", error);
@@ -229,6 +234,24 @@ export function ConversationView() {
     }
   }, [messages]);
 
+  const loadOlderMessages = async () => {
+    if (!selectedConversation || !olderMessagesCursor) return;
+
+    setLoadingOlderMessages(true);
+    try {
+      const response = await API.api.getMessagesHandler(selectedConversation.id, {
+        before: olderMessagesCursor,
+      });
+      // Messages are kept newest first, so older ones go at the end
+      setMessages(prev => [...prev, ...response.data.items]);
+      setOlderMessagesCursor(response.data.nextCursor ?? null);
+    } catch (error) {
+      console.error("Error fetching older messages:", error);
+    } finally {
+      setLoadingOlderMessages(false);
+    }
+  };
+
   const handleSendMessage = async () => {
     if (!newMessage.trim() || !selectedConversation) return;
 
@@ -532,6 +555,18 @@ export function ConversationView() {
               <CardContent className="flex-grow flex flex-col">
                 <ScrollArea className="flex-grow mb-4" ref={scrollAreaRef}>
                   <div className="space-y-4">
+                    {olderMessagesCursor && (
+                      <div className="flex justify-center">
+                        <Button
+                          variant="ghost"
+                          size="sm"
+                          onClick={loadOlderMessages}
+                          disabled={loadingOlderMessages}
+                        >
+                          {loadingOlderMessages ? "Loading..." : "Load older messages"}
+                        </Button>
+                      </div>
+                    )}
                     {messages.slice().reverse().map(message => (
                       <div
                         key={message.id}
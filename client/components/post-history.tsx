@@ -5,7 +5,7 @@ import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/com
 import { Badge } from "@/components/ui/badge";
 import { Tabs, TabsContent, TabsList, TabsTrigger } from "@/components/ui/tabs";
 import { useRealtimeEvents } from "@/components/realtime-event-handler";
-import { API } from "@/lib/api";
+import { API, fetchAllPages } from "@/lib/api";
 import { Post as ApiPost, UserContent } from "@/Api";
 import { useSession } from "@/components/session-provider";
 import { Image as ImageIcon, FileText, Video as VideoIcon, AudioWaveform } from "lucide-react";
@@ -119,8 +119,9 @@ export function PostHistory() {
     if (!user) return;
     
     try {
-      const response = await API.api.getPostsHandler(user.id);
-      const rawPosts: ApiPost[] = response.data;
+      const rawPosts: ApiPost[] = await fetchAllPages((before) =>
+        API.api.getPostsHandler(user.id, { before }),
+      );
 
       // Process posts to extract captions and media from content
       const processedPosts = rawPosts.map(post => {
@@ -10,3 +10,19 @@ export const API = new Api({
       ? { credentials: "include" }
       : {},
 });
+
+/** Fetch every page of a cursor-paginated list, newest items first. */
+export async function fetchAllPages<T>(
+  fetchPage: (
+    before?: string,
+  ) => Promise<{ data: { items: T[]; nextCursor?: string | null } }>,
+): Promise<T[]> {
+  const items: T[] = [];
+  let before: string | undefined;
+  do {
+    const { data } = await fetchPage(before);
+    items.push(...data.items);
+    before = data.nextCursor ?? undefined;
+  } while (before);
+  return items;
+}